@@ -0,0 +1,90 @@
+use anyhow::Result;
+use eki_client::decode::{self, AlignedData};
+use eki_client::eki::{Bookkeeping, TimeMode, DUST_PRECISION};
+use eki_client::MarketKeys;
+
+use crate::fetch_market;
+use crate::rpc::Rpc;
+
+pub fn print(rpc: &Rpc, keys: &MarketKeys, window: u64) -> Result<()> {
+    let market = fetch_market(rpc)?;
+    let bookkeeping: Bookkeeping = decode::account(&rpc.data(&keys.bookkeeping())?)?;
+
+    let time_mode = match market.time_mode {
+        TimeMode::Slot => "slot",
+        TimeMode::Timestamp => "timestamp",
+    };
+
+    println!("Market {}", keys.market());
+    println!("  authority          {}", market.authority);
+    println!("  token mint a       {}", market.token_mint_a);
+    println!("  token mint b       {}", market.token_mint_b);
+    println!("  token program a    {}", market.token_program_a);
+    println!("  token program b    {}", market.token_program_b);
+    println!("  treasury a         {}", market.treasury_a);
+    println!("  treasury b         {}", market.treasury_b);
+    println!("  time mode          {time_mode}");
+    println!("  start slot         {}", market.start_slot);
+    println!("  end slot interval  {}", market.end_slot_interval);
+    if let Some(end_slot) = market.end_slot {
+        println!("  closed at slot     {end_slot}");
+    }
+    println!("  token a volume     {}", market.token_a_volume);
+    println!("  token b volume     {}", market.token_b_volume);
+    println!(
+        "  current slot       {}",
+        rpc.current_slot(market.time_mode)?
+    );
+    println!();
+
+    println!("Bookkeeping {}", keys.bookkeeping());
+    println!("  a per b            {}", bookkeeping.a_per_b);
+    println!("  b per a            {}", bookkeeping.b_per_a);
+    println!("  unfilled a         {}", bookkeeping.unfilled_a);
+    println!("  unfilled b         {}", bookkeeping.unfilled_b);
+    println!("  last slot          {}", bookkeeping.last_slot);
+    println!("  pool reserve a     {}", bookkeeping.pool.reserve_a);
+    println!("  pool reserve b     {}", bookkeeping.pool.reserve_b);
+    println!("  pool shares        {}", bookkeeping.pool.shares);
+    println!(
+        "  dust a             {}",
+        bookkeeping.dust_a / DUST_PRECISION
+    );
+    println!(
+        "  dust b             {}",
+        bookkeeping.dust_b / DUST_PRECISION
+    );
+    println!();
+
+    let mut prices_data = AlignedData::new(&rpc.data(&keys.prices)?);
+    let prices = decode::prices(prices_data.as_bytes_mut())?;
+    let used = prices.checkpoints.iter().filter(|c| c.bucket != 0).count();
+    println!("Prices {}", keys.prices);
+    println!("  checkpoints        {used} of {}", prices.length);
+    println!();
+
+    let mut exits_data = AlignedData::new(&rpc.data(&keys.exits)?);
+    let exits = decode::exits(exits_data.as_bytes_mut())?;
+    println!("Exits {}", keys.exits);
+    println!("  length             {}", exits.length);
+    println!("  last bucket        {}", exits.pointer);
+    println!(
+        "  {:>10} {:>14} {:>20} {:>20}",
+        "bucket", "slot", "token a", "token b"
+    );
+
+    // buckets are at most one round of the ring buffer ahead of the last processed one
+    let last = exits.pointer + window.min(exits.length - 1);
+    for bucket in exits.pointer + 1..=last {
+        let index = (bucket % exits.length) as usize;
+        println!(
+            "  {:>10} {:>14} {:>20} {:>20}",
+            bucket,
+            exits.start_slot + bucket * market.end_slot_interval,
+            exits.token_a[index],
+            exits.token_b[index]
+        );
+    }
+
+    Ok(())
+}
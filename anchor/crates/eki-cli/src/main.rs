@@ -0,0 +1,401 @@
+// Command-line tool for setting up and operating the eki market, e.g. against a local
+// test validator. Positions are named after the token they sell, so `--side a` is the
+// position streaming token A into token B.
+
+mod inspect;
+mod rpc;
+
+use std::path::PathBuf;
+
+use anyhow::{anyhow, Result};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use eki_client::eki::{Exits, Market, Prices, TimeMode};
+use eki_client::{decode, instructions, MarketKeys};
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::{read_keypair_file, Keypair, Signer};
+
+use rpc::Rpc;
+
+#[derive(Parser)]
+#[command(name = "eki", about = "Operate the eki market")]
+struct Cli {
+    /// RPC endpoint of the cluster
+    #[arg(
+        long,
+        short = 'u',
+        global = true,
+        default_value = "http://127.0.0.1:8899"
+    )]
+    url: String,
+
+    /// Keypair paying for and signing transactions [default: ~/.config/solana/id.json]
+    #[arg(long, short = 'k', global = true)]
+    keypair: Option<PathBuf>,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Create the exits and prices accounts and write their discriminators
+    InitExits {
+        /// Number of buckets in the exits ring buffer
+        #[arg(long)]
+        exits_length: u64,
+
+        /// Number of price checkpoints the prices table can hold
+        #[arg(long)]
+        prices_length: u64,
+    },
+
+    /// Create the market over two mints, using initialized exits and prices accounts
+    CreateMarket {
+        #[arg(long)]
+        mint_a: Pubkey,
+
+        #[arg(long)]
+        mint_b: Pubkey,
+
+        #[arg(long)]
+        exits: Pubkey,
+
+        #[arg(long)]
+        prices: Pubkey,
+
+        /// First slot of trading, moved up to the current one if it already passed
+        #[arg(long, default_value_t = 0)]
+        start_slot: u64,
+
+        /// Distance between position end slots
+        #[arg(long)]
+        end_slot_interval: u64,
+
+        #[arg(long, value_enum, default_value_t = TimeModeArg::Slot)]
+        time_mode: TimeModeArg,
+    },
+
+    /// Open a position streaming amount of one token until end slot
+    Deposit {
+        #[arg(long, value_enum)]
+        side: Side,
+
+        /// Deposit in base units of the token
+        #[arg(long)]
+        amount: u64,
+
+        #[arg(long)]
+        end_slot: u64,
+
+        #[command(flatten)]
+        buffers: Buffers,
+    },
+
+    /// Withdraw what a position has received so far
+    Withdraw {
+        #[arg(long, value_enum)]
+        side: Side,
+
+        /// Unwrap received native SOL
+        #[arg(long)]
+        unwrap: bool,
+
+        /// Archive page holding the checkpoint of the position's end bucket
+        #[arg(long)]
+        price_archive: Option<Pubkey>,
+
+        #[command(flatten)]
+        buffers: Buffers,
+    },
+
+    /// Close a position, paying out received tokens and the unstreamed deposit
+    Close {
+        #[arg(long, value_enum)]
+        side: Side,
+
+        /// Unwrap received native SOL
+        #[arg(long)]
+        unwrap: bool,
+
+        /// Archive page holding the checkpoint of the position's end bucket
+        #[arg(long)]
+        price_archive: Option<Pubkey>,
+
+        #[command(flatten)]
+        buffers: Buffers,
+    },
+
+    /// Take part of the unstreamed deposit out of a position, which keeps streaming the
+    /// rest until its end slot
+    Reduce {
+        #[arg(long, value_enum)]
+        side: Side,
+
+        /// Deposit to take out in base units of the token
+        #[arg(long)]
+        amount: u64,
+
+        /// Unwrap received native SOL
+        #[arg(long)]
+        unwrap: bool,
+
+        #[command(flatten)]
+        buffers: Buffers,
+    },
+
+    /// Process passed exits buckets and bring bookkeeping up to date
+    Crank {
+        #[command(flatten)]
+        buffers: Buffers,
+    },
+
+    /// Stop new positions from being opened, as the market authority
+    CloseMarket,
+
+    /// Take the rounding dust out of the treasuries of a closed market, as its authority
+    SweepDust,
+
+    /// Print the market, its bookkeeping and the upcoming exits buckets
+    Inspect {
+        /// Number of exits buckets to show after the last processed one
+        #[arg(long, default_value_t = 10)]
+        window: u64,
+
+        #[command(flatten)]
+        buffers: Buffers,
+    },
+}
+
+// The market doesn't store its exits and prices accounts, so they are looked up among
+// the program accounts unless given
+#[derive(Args)]
+struct Buffers {
+    #[arg(long)]
+    exits: Option<Pubkey>,
+
+    #[arg(long)]
+    prices: Option<Pubkey>,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Side {
+    A,
+    B,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum TimeModeArg {
+    Slot,
+    Timestamp,
+}
+
+impl From<TimeModeArg> for TimeMode {
+    fn from(mode: TimeModeArg) -> Self {
+        match mode {
+            TimeModeArg::Slot => TimeMode::Slot,
+            TimeModeArg::Timestamp => TimeMode::Timestamp,
+        }
+    }
+}
+
+fn main() -> Result<()> {
+    let cli = Cli::parse();
+
+    let keypair_path = match cli.keypair {
+        Some(path) => path,
+        None => PathBuf::from(std::env::var("HOME")?).join(".config/solana/id.json"),
+    };
+    let payer = read_keypair_file(&keypair_path)
+        .map_err(|e| anyhow!("reading keypair {}: {e}", keypair_path.display()))?;
+    let rpc = Rpc::new(cli.url, payer);
+
+    match cli.command {
+        Command::InitExits {
+            exits_length,
+            prices_length,
+        } => init_exits(&rpc, exits_length, prices_length),
+        Command::CreateMarket {
+            mint_a,
+            mint_b,
+            exits,
+            prices,
+            start_slot,
+            end_slot_interval,
+            time_mode,
+        } => {
+            let keys = MarketKeys {
+                token_mint_a: mint_a,
+                token_mint_b: mint_b,
+                token_program_a: rpc.owner(&mint_a)?,
+                token_program_b: rpc.owner(&mint_b)?,
+                exits,
+                prices,
+            };
+            let (exits_length, prices_length) = rpc.buffer_lengths(&exits, &prices)?;
+
+            rpc.send(
+                &[instructions::initialize_market(
+                    &keys,
+                    &rpc.payer(),
+                    start_slot,
+                    end_slot_interval,
+                    time_mode.into(),
+                    exits_length,
+                    prices_length,
+                )],
+                &[],
+            )?;
+            println!("Market: {}", keys.market());
+            Ok(())
+        }
+        Command::Deposit {
+            side,
+            amount,
+            end_slot,
+            buffers,
+        } => {
+            let keys = rpc.market_keys(&buffers)?;
+            let owner = rpc.payer();
+
+            let instruction = match side {
+                Side::A => instructions::deposit_token_a(
+                    &keys,
+                    &owner,
+                    deposit_account(&keys.token_mint_a, keys.token_account_a(&owner)),
+                    amount,
+                    end_slot,
+                ),
+                Side::B => instructions::deposit_token_b(
+                    &keys,
+                    &owner,
+                    deposit_account(&keys.token_mint_b, keys.token_account_b(&owner)),
+                    amount,
+                    end_slot,
+                ),
+            };
+            rpc.send(&[instruction], &[])
+        }
+        Command::Withdraw {
+            side,
+            unwrap,
+            price_archive,
+            buffers,
+        } => {
+            let keys = rpc.market_keys(&buffers)?;
+
+            let instruction = match side {
+                Side::A => instructions::withdraw_swapped_token_b(
+                    &keys,
+                    &rpc.payer(),
+                    price_archive,
+                    unwrap,
+                ),
+                Side::B => instructions::withdraw_swapped_token_a(
+                    &keys,
+                    &rpc.payer(),
+                    price_archive,
+                    unwrap,
+                ),
+            };
+            rpc.send(&[instruction], &[])
+        }
+        Command::Close {
+            side,
+            unwrap,
+            price_archive,
+            buffers,
+        } => {
+            let keys = rpc.market_keys(&buffers)?;
+
+            let instruction = match side {
+                Side::A => {
+                    instructions::close_position_a(&keys, &rpc.payer(), price_archive, unwrap)
+                }
+                Side::B => {
+                    instructions::close_position_b(&keys, &rpc.payer(), price_archive, unwrap)
+                }
+            };
+            rpc.send(&[instruction], &[])
+        }
+        Command::Reduce {
+            side,
+            amount,
+            unwrap,
+            buffers,
+        } => {
+            let keys = rpc.market_keys(&buffers)?;
+
+            let instruction = match side {
+                Side::A => instructions::reduce_position_a(&keys, &rpc.payer(), amount, unwrap),
+                Side::B => instructions::reduce_position_b(&keys, &rpc.payer(), amount, unwrap),
+            };
+            rpc.send(&[instruction], &[])
+        }
+        Command::Crank { buffers } => {
+            let keys = rpc.market_keys(&buffers)?;
+
+            rpc.send(
+                &[instructions::update_bookkeeping(&keys, &rpc.payer())],
+                &[],
+            )
+        }
+        Command::CloseMarket => {
+            let keys = mint_keys(&rpc)?;
+
+            rpc.send(&[instructions::close_market(&keys, &rpc.payer())], &[])
+        }
+        Command::SweepDust => {
+            let keys = mint_keys(&rpc)?;
+
+            rpc.send(&[instructions::sweep_dust(&keys, &rpc.payer())], &[])
+        }
+        Command::Inspect { window, buffers } => {
+            let keys = rpc.market_keys(&buffers)?;
+
+            inspect::print(&rpc, &keys, window)
+        }
+    }
+}
+
+// Keys of the market for instructions that don't touch the exits and prices accounts
+fn mint_keys(rpc: &Rpc) -> Result<MarketKeys> {
+    let market = fetch_market(rpc)?;
+
+    Ok(MarketKeys {
+        token_mint_a: market.token_mint_a,
+        token_mint_b: market.token_mint_b,
+        token_program_a: market.token_program_a,
+        token_program_b: market.token_program_b,
+        exits: Pubkey::default(),
+        prices: Pubkey::default(),
+    })
+}
+
+fn init_exits(rpc: &Rpc, exits_length: u64, prices_length: u64) -> Result<()> {
+    let exits = Keypair::new();
+    let prices = Keypair::new();
+
+    rpc.send(
+        &[
+            rpc.create_program_account(&exits.pubkey(), Exits::space(exits_length as usize))?,
+            rpc.create_program_account(&prices.pubkey(), Prices::space(prices_length as usize))?,
+            instructions::initialize_exits(&rpc.payer(), &exits.pubkey(), &prices.pubkey()),
+        ],
+        &[&exits, &prices],
+    )?;
+
+    println!("Exits: {}", exits.pubkey());
+    println!("Prices: {}", prices.pubkey());
+    Ok(())
+}
+
+// Native SOL is deposited straight from lamports, everything else from the owner's ATA
+fn deposit_account(mint: &Pubkey, token_account: Pubkey) -> Option<Pubkey> {
+    (!eki_client::eki::is_native_mint(mint)).then_some(token_account)
+}
+
+fn fetch_market(rpc: &Rpc) -> Result<Market> {
+    let data = rpc.data(&eki_client::pda::market().0)?;
+
+    Ok(decode::account(&data)?)
+}
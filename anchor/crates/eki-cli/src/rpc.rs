@@ -0,0 +1,166 @@
+use anchor_lang::Discriminator;
+use anyhow::{bail, Context, Result};
+use eki_client::decode::{self, AlignedData};
+use eki_client::eki::{Exits, Prices, TimeMode};
+use eki_client::{MarketKeys, PROGRAM_ID};
+use solana_account_decoder::UiAccountEncoding;
+use solana_client::rpc_client::RpcClient;
+use solana_client::rpc_config::{RpcAccountInfoConfig, RpcProgramAccountsConfig};
+use solana_client::rpc_filter::{Memcmp, RpcFilterType};
+use solana_sdk::commitment_config::CommitmentConfig;
+use solana_sdk::instruction::Instruction;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::{Keypair, Signer};
+use solana_sdk::system_instruction;
+use solana_sdk::transaction::Transaction;
+
+use crate::{fetch_market, Buffers};
+
+pub struct Rpc {
+    client: RpcClient,
+    payer: Keypair,
+}
+
+impl Rpc {
+    pub fn new(url: String, payer: Keypair) -> Self {
+        Self {
+            client: RpcClient::new_with_commitment(url, CommitmentConfig::confirmed()),
+            payer,
+        }
+    }
+
+    pub fn payer(&self) -> Pubkey {
+        self.payer.pubkey()
+    }
+
+    pub fn send(&self, instructions: &[Instruction], signers: &[&Keypair]) -> Result<()> {
+        let mut all_signers = vec![&self.payer];
+        all_signers.extend_from_slice(signers);
+
+        let transaction = Transaction::new_signed_with_payer(
+            instructions,
+            Some(&self.payer.pubkey()),
+            &all_signers,
+            self.client.get_latest_blockhash()?,
+        );
+        let signature = self.client.send_and_confirm_transaction(&transaction)?;
+
+        println!("Signature: {signature}");
+        Ok(())
+    }
+
+    pub fn data(&self, address: &Pubkey) -> Result<Vec<u8>> {
+        let account = self
+            .client
+            .get_account(address)
+            .with_context(|| format!("fetching account {address}"))?;
+
+        Ok(account.data)
+    }
+
+    pub fn owner(&self, address: &Pubkey) -> Result<Pubkey> {
+        Ok(self.client.get_account(address)?.owner)
+    }
+
+    // Current value of the clock the market runs on
+    pub fn current_slot(&self, time_mode: TimeMode) -> Result<u64> {
+        let slot = self.client.get_slot()?;
+
+        Ok(match time_mode {
+            TimeMode::Slot => slot,
+            TimeMode::Timestamp => self.client.get_block_time(slot)? as u64,
+        })
+    }
+
+    // Rent exempt account of the given size owned by the program
+    pub fn create_program_account(&self, address: &Pubkey, space: usize) -> Result<Instruction> {
+        let lamports = self.client.get_minimum_balance_for_rent_exemption(space)?;
+
+        Ok(system_instruction::create_account(
+            &self.payer.pubkey(),
+            address,
+            lamports,
+            space as u64,
+            &PROGRAM_ID,
+        ))
+    }
+
+    // Largest lengths the exits and prices accounts have room for
+    pub fn buffer_lengths(&self, exits: &Pubkey, prices: &Pubkey) -> Result<(u64, u64)> {
+        let exits_size = self.data(exits)?.len();
+        let prices_size = self.data(prices)?.len();
+
+        let mut exits_length = exits_size / 16;
+        while exits_length > 0 && Exits::space(exits_length) > exits_size {
+            exits_length -= 1;
+        }
+
+        let mut prices_length = prices_size / 64;
+        while prices_length > 0 && Prices::space(prices_length) > prices_size {
+            prices_length -= 1;
+        }
+
+        Ok((exits_length as u64, prices_length as u64))
+    }
+
+    pub fn market_keys(&self, buffers: &Buffers) -> Result<MarketKeys> {
+        let market = fetch_market(self)?;
+
+        let exits = match buffers.exits {
+            Some(exits) => exits,
+            None => self.find_buffer("exits", Exits::DISCRIMINATOR, |data| {
+                decode::exits(data).is_ok_and(|exits| exits.length > 0)
+            })?,
+        };
+        let prices = match buffers.prices {
+            Some(prices) => prices,
+            None => self.find_buffer("prices", Prices::DISCRIMINATOR, |data| {
+                decode::prices(data).is_ok_and(|prices| prices.length > 0)
+            })?,
+        };
+
+        Ok(MarketKeys {
+            token_mint_a: market.token_mint_a,
+            token_mint_b: market.token_mint_b,
+            token_program_a: market.token_program_a,
+            token_program_b: market.token_program_b,
+            exits,
+            prices,
+        })
+    }
+
+    // The program account with the given discriminator a market has been created with.
+    // Accounts set up by init-exits but never used for a market still have a zero length.
+    fn find_buffer(
+        &self,
+        name: &str,
+        discriminator: [u8; 8],
+        initialized: impl Fn(&mut [u8]) -> bool,
+    ) -> Result<Pubkey> {
+        let config = RpcProgramAccountsConfig {
+            filters: Some(vec![RpcFilterType::Memcmp(Memcmp::new_base58_encoded(
+                0,
+                &discriminator,
+            ))]),
+            account_config: RpcAccountInfoConfig {
+                encoding: Some(UiAccountEncoding::Base64),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        let accounts: Vec<Pubkey> = self
+            .client
+            .get_program_accounts_with_config(&PROGRAM_ID, config)?
+            .into_iter()
+            .filter(|(_, account)| initialized(AlignedData::new(&account.data).as_bytes_mut()))
+            .map(|(address, _)| address)
+            .collect();
+
+        match accounts[..] {
+            [address] => Ok(address),
+            [] => bail!("no initialized {name} account found, pass --{name}"),
+            _ => bail!("several {name} accounts found, pass --{name}"),
+        }
+    }
+}
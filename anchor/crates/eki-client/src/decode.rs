@@ -0,0 +1,86 @@
+use anchor_lang::error::ErrorCode;
+use anchor_lang::{AccountDeserialize, Discriminator, Result, Space};
+use eki::{
+    Exits, ExitsBuffer, PriceArchive, PriceCheckpoint, Prices, PricesBuffer, ANCHOR_DISCRIMINATOR,
+};
+
+// Account data copied into 8 byte aligned memory, which the zero-copy views require.
+// Buffers handed out by RPC clients make no alignment promises.
+pub struct AlignedData {
+    words: Vec<u64>,
+    len: usize,
+}
+
+impl AlignedData {
+    pub fn new(data: &[u8]) -> Self {
+        let mut words = vec![0u64; data.len().div_ceil(8)];
+        bytemuck::cast_slice_mut::<u64, u8>(&mut words)[..data.len()].copy_from_slice(data);
+
+        Self {
+            words,
+            len: data.len(),
+        }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &bytemuck::cast_slice(&self.words)[..self.len]
+    }
+
+    pub fn as_bytes_mut(&mut self) -> &mut [u8] {
+        &mut bytemuck::cast_slice_mut(&mut self.words)[..self.len]
+    }
+}
+
+fn check_layout<T: Discriminator>(data: &[u8], header_size: usize) -> Result<()> {
+    if data.len() < ANCHOR_DISCRIMINATOR + header_size {
+        return Err(ErrorCode::AccountDidNotDeserialize.into());
+    }
+    if data[..ANCHOR_DISCRIMINATOR] != T::DISCRIMINATOR {
+        return Err(ErrorCode::AccountDiscriminatorMismatch.into());
+    }
+    if data.as_ptr().align_offset(8) != 0 {
+        return Err(ErrorCode::AccountDidNotDeserialize.into());
+    }
+
+    Ok(())
+}
+
+// View over the exit ring buffers of an exits account
+pub fn exits(data: &mut [u8]) -> Result<ExitsBuffer<'_>> {
+    check_layout::<Exits>(data, Exits::INIT_SPACE)?;
+
+    ExitsBuffer::load(data)
+}
+
+// View over the checkpoint table of a prices account
+pub fn prices(data: &mut [u8]) -> Result<PricesBuffer<'_>> {
+    check_layout::<Prices>(data, Prices::INIT_SPACE)?;
+
+    PricesBuffer::load(data)
+}
+
+pub fn price_archive(data: &[u8]) -> Result<&PriceArchive> {
+    check_layout::<PriceArchive>(data, PriceArchive::INIT_SPACE)?;
+
+    Ok(bytemuck::from_bytes(
+        &data[ANCHOR_DISCRIMINATOR..ANCHOR_DISCRIMINATOR + PriceArchive::INIT_SPACE],
+    ))
+}
+
+// Checkpoint kept for an absolute exits bucket, if it is still in the table
+pub fn checkpoint(prices: &PricesBuffer, bucket: u64) -> Option<PriceCheckpoint> {
+    if bucket == 0 {
+        return None;
+    }
+
+    prices
+        .checkpoints
+        .iter()
+        .find(|checkpoint| checkpoint.bucket == bucket)
+        .copied()
+}
+
+// Any of the borsh accounts, e.g. Market, Bookkeeping or a position
+pub fn account<T: AccountDeserialize>(mut data: &[u8]) -> Result<T> {
+    T::try_deserialize(&mut data)
+}
@@ -0,0 +1,574 @@
+use anchor_lang::prelude::Pubkey;
+use anchor_lang::solana_program::instruction::Instruction;
+use anchor_lang::solana_program::system_program;
+use anchor_lang::{InstructionData, ToAccountMetas};
+use anchor_spl::{associated_token, token};
+use eki::{accounts, instruction, TimeMode};
+
+use crate::pda;
+
+// Accounts that identify the market and never change after its creation. The exits and
+// prices accounts are created by the caller, so they can't be derived.
+#[derive(Clone, Copy, Debug)]
+pub struct MarketKeys {
+    pub token_mint_a: Pubkey,
+    pub token_mint_b: Pubkey,
+    pub token_program_a: Pubkey,
+    pub token_program_b: Pubkey,
+    pub exits: Pubkey,
+    pub prices: Pubkey,
+}
+
+impl MarketKeys {
+    pub fn market(&self) -> Pubkey {
+        pda::market().0
+    }
+
+    pub fn bookkeeping(&self) -> Pubkey {
+        pda::bookkeeping(&self.market()).0
+    }
+
+    pub fn treasury_a(&self) -> Pubkey {
+        pda::treasury_a(&self.market()).0
+    }
+
+    pub fn treasury_b(&self) -> Pubkey {
+        pda::treasury_b(&self.market()).0
+    }
+
+    pub fn lp_mint(&self) -> Pubkey {
+        pda::lp_mint(&self.market()).0
+    }
+
+    pub fn token_account_a(&self, owner: &Pubkey) -> Pubkey {
+        pda::associated_token_account(owner, &self.token_mint_a, &self.token_program_a)
+    }
+
+    pub fn token_account_b(&self, owner: &Pubkey) -> Pubkey {
+        pda::associated_token_account(owner, &self.token_mint_b, &self.token_program_b)
+    }
+
+    // The LP mint always belongs to the SPL token program, whatever the market tokens use
+    pub fn lp_token_account(&self, owner: &Pubkey) -> Pubkey {
+        pda::associated_token_account(owner, &self.lp_mint(), &token::ID)
+    }
+}
+
+fn build(accounts: impl ToAccountMetas, data: impl InstructionData) -> Instruction {
+    Instruction {
+        program_id: eki::ID,
+        accounts: accounts.to_account_metas(None),
+        data: data.data(),
+    }
+}
+
+// Both accounts need to be allocated and assigned to the program beforehand, sized with
+// Exits::space and Prices::space.
+pub fn initialize_exits(signer: &Pubkey, exits: &Pubkey, prices: &Pubkey) -> Instruction {
+    build(
+        accounts::InitializeExits {
+            signer: *signer,
+            exits: *exits,
+            prices: *prices,
+            system_program: system_program::ID,
+        },
+        instruction::InitializeExits {},
+    )
+}
+
+pub fn initialize_market(
+    keys: &MarketKeys,
+    signer: &Pubkey,
+    start_slot: u64,
+    end_slot_interval: u64,
+    time_mode: TimeMode,
+    exits_length: u64,
+    prices_length: u64,
+) -> Instruction {
+    build(
+        accounts::InitializeMarket {
+            signer: *signer,
+            token_mint_a: keys.token_mint_a,
+            token_mint_b: keys.token_mint_b,
+            market: keys.market(),
+            treasury_a: keys.treasury_a(),
+            treasury_b: keys.treasury_b(),
+            bookkeeping: keys.bookkeeping(),
+            prices: keys.prices,
+            exits: keys.exits,
+            token_program_a: keys.token_program_a,
+            token_program_b: keys.token_program_b,
+            system_program: system_program::ID,
+        },
+        instruction::InitializeMarket {
+            start_slot,
+            end_slot_interval,
+            time_mode,
+            exits_length,
+            prices_length,
+        },
+    )
+}
+
+// Without a token account a native SOL deposit is wrapped straight into the treasury.
+// Transfer hook accounts, if the mint has any, go after the returned accounts.
+pub fn deposit_token_a(
+    keys: &MarketKeys,
+    depositor: &Pubkey,
+    depositor_token_account: Option<Pubkey>,
+    amount: u64,
+    end_slot: u64,
+) -> Instruction {
+    let market = keys.market();
+
+    build(
+        accounts::DepositTokenA {
+            depositor: *depositor,
+            depositor_token_account,
+            token_mint_a: keys.token_mint_a,
+            market,
+            position_a: pda::position_a(&market, depositor).0,
+            treasury_a: keys.treasury_a(),
+            bookkeeping: keys.bookkeeping(),
+            exits: keys.exits,
+            prices: keys.prices,
+            token_program_a: keys.token_program_a,
+            associated_token_program: associated_token::ID,
+            system_program: system_program::ID,
+        },
+        instruction::DepositTokenA { amount, end_slot },
+    )
+}
+
+pub fn deposit_token_b(
+    keys: &MarketKeys,
+    depositor: &Pubkey,
+    depositor_token_account: Option<Pubkey>,
+    amount: u64,
+    end_slot: u64,
+) -> Instruction {
+    let market = keys.market();
+
+    build(
+        accounts::DepositTokenB {
+            depositor: *depositor,
+            depositor_token_account,
+            token_mint_b: keys.token_mint_b,
+            market,
+            position_b: pda::position_b(&market, depositor).0,
+            treasury_b: keys.treasury_b(),
+            bookkeeping: keys.bookkeeping(),
+            exits: keys.exits,
+            prices: keys.prices,
+            token_program_b: keys.token_program_b,
+            associated_token_program: associated_token::ID,
+            system_program: system_program::ID,
+        },
+        instruction::DepositTokenB { amount, end_slot },
+    )
+}
+
+// Withdraws the token A a token B seller has received so far. The price archive is only
+// needed once the position has ended and the checkpoint of its end bucket has been moved
+// out of the prices table.
+pub fn withdraw_swapped_token_a(
+    keys: &MarketKeys,
+    withdrawer: &Pubkey,
+    price_archive: Option<Pubkey>,
+    unwrap: bool,
+) -> Instruction {
+    let market = keys.market();
+
+    build(
+        accounts::WithdrawSwappedTokensA {
+            withdrawer: *withdrawer,
+            withdrawer_token_account: keys.token_account_a(withdrawer),
+            token_mint_a: keys.token_mint_a,
+            market,
+            position_b: pda::position_b(&market, withdrawer).0,
+            treasury_a: keys.treasury_a(),
+            bookkeeping: keys.bookkeeping(),
+            exits: keys.exits,
+            prices: keys.prices,
+            price_archive,
+            unwrap_account: pda::unwrap_account(&market, withdrawer).0,
+            token_program_a: keys.token_program_a,
+            associated_token_program: associated_token::ID,
+            system_program: system_program::ID,
+        },
+        instruction::WithdrawSwappedTokenA { unwrap },
+    )
+}
+
+// Withdraws the token B a token A seller has received so far, see withdraw_swapped_token_a
+pub fn withdraw_swapped_token_b(
+    keys: &MarketKeys,
+    withdrawer: &Pubkey,
+    price_archive: Option<Pubkey>,
+    unwrap: bool,
+) -> Instruction {
+    let market = keys.market();
+
+    build(
+        accounts::WithdrawSwappedTokensB {
+            withdrawer: *withdrawer,
+            withdrawer_token_account: keys.token_account_b(withdrawer),
+            token_mint_b: keys.token_mint_b,
+            market,
+            position_a: pda::position_a(&market, withdrawer).0,
+            treasury_b: keys.treasury_b(),
+            bookkeeping: keys.bookkeeping(),
+            exits: keys.exits,
+            prices: keys.prices,
+            price_archive,
+            unwrap_account: pda::unwrap_account(&market, withdrawer).0,
+            token_program_b: keys.token_program_b,
+            associated_token_program: associated_token::ID,
+            system_program: system_program::ID,
+        },
+        instruction::WithdrawSwappedTokenB { unwrap },
+    )
+}
+
+// The price archive is only needed once the checkpoint of the position's end bucket has
+// been moved out of the prices table.
+pub fn close_position_a(
+    keys: &MarketKeys,
+    signer: &Pubkey,
+    price_archive: Option<Pubkey>,
+    unwrap: bool,
+) -> Instruction {
+    let market = keys.market();
+
+    build(
+        accounts::ClosePositionA {
+            signer: *signer,
+            signer_token_account_a: keys.token_account_a(signer),
+            signer_token_account_b: keys.token_account_b(signer),
+            token_mint_a: keys.token_mint_a,
+            token_mint_b: keys.token_mint_b,
+            market,
+            position_a: pda::position_a(&market, signer).0,
+            treasury_a: keys.treasury_a(),
+            treasury_b: keys.treasury_b(),
+            bookkeeping: keys.bookkeeping(),
+            exits: keys.exits,
+            prices: keys.prices,
+            price_archive,
+            unwrap_account: pda::unwrap_account(&market, signer).0,
+            token_program_a: keys.token_program_a,
+            token_program_b: keys.token_program_b,
+            associated_token_program: associated_token::ID,
+            system_program: system_program::ID,
+        },
+        instruction::ClosePositionA { unwrap },
+    )
+}
+
+pub fn close_position_b(
+    keys: &MarketKeys,
+    signer: &Pubkey,
+    price_archive: Option<Pubkey>,
+    unwrap: bool,
+) -> Instruction {
+    let market = keys.market();
+
+    build(
+        accounts::ClosePositionB {
+            signer: *signer,
+            signer_token_account_a: keys.token_account_a(signer),
+            signer_token_account_b: keys.token_account_b(signer),
+            token_mint_a: keys.token_mint_a,
+            token_mint_b: keys.token_mint_b,
+            market,
+            position_b: pda::position_b(&market, signer).0,
+            treasury_a: keys.treasury_a(),
+            treasury_b: keys.treasury_b(),
+            bookkeeping: keys.bookkeeping(),
+            exits: keys.exits,
+            prices: keys.prices,
+            price_archive,
+            unwrap_account: pda::unwrap_account(&market, signer).0,
+            token_program_a: keys.token_program_a,
+            token_program_b: keys.token_program_b,
+            associated_token_program: associated_token::ID,
+            system_program: system_program::ID,
+        },
+        instruction::ClosePositionB { unwrap },
+    )
+}
+
+// Pays out what the position is owed so far along with amount of its unstreamed deposit,
+// leaving the rest streaming until the end slot.
+pub fn reduce_position_a(
+    keys: &MarketKeys,
+    signer: &Pubkey,
+    amount: u64,
+    unwrap: bool,
+) -> Instruction {
+    let market = keys.market();
+
+    build(
+        accounts::ReducePositionA {
+            signer: *signer,
+            signer_token_account_a: keys.token_account_a(signer),
+            signer_token_account_b: keys.token_account_b(signer),
+            token_mint_a: keys.token_mint_a,
+            token_mint_b: keys.token_mint_b,
+            market,
+            position_a: pda::position_a(&market, signer).0,
+            treasury_a: keys.treasury_a(),
+            treasury_b: keys.treasury_b(),
+            bookkeeping: keys.bookkeeping(),
+            exits: keys.exits,
+            prices: keys.prices,
+            unwrap_account: pda::unwrap_account(&market, signer).0,
+            token_program_a: keys.token_program_a,
+            token_program_b: keys.token_program_b,
+            associated_token_program: associated_token::ID,
+            system_program: system_program::ID,
+        },
+        instruction::ReducePositionA { amount, unwrap },
+    )
+}
+
+pub fn reduce_position_b(
+    keys: &MarketKeys,
+    signer: &Pubkey,
+    amount: u64,
+    unwrap: bool,
+) -> Instruction {
+    let market = keys.market();
+
+    build(
+        accounts::ReducePositionB {
+            signer: *signer,
+            signer_token_account_a: keys.token_account_a(signer),
+            signer_token_account_b: keys.token_account_b(signer),
+            token_mint_a: keys.token_mint_a,
+            token_mint_b: keys.token_mint_b,
+            market,
+            position_b: pda::position_b(&market, signer).0,
+            treasury_a: keys.treasury_a(),
+            treasury_b: keys.treasury_b(),
+            bookkeeping: keys.bookkeeping(),
+            exits: keys.exits,
+            prices: keys.prices,
+            unwrap_account: pda::unwrap_account(&market, signer).0,
+            token_program_a: keys.token_program_a,
+            token_program_b: keys.token_program_b,
+            associated_token_program: associated_token::ID,
+            system_program: system_program::ID,
+        },
+        instruction::ReducePositionB { amount, unwrap },
+    )
+}
+
+// Cranks the exits and bookkeeping up to the current slot
+pub fn update_bookkeeping(keys: &MarketKeys, signer: &Pubkey) -> Instruction {
+    build(
+        accounts::UpdateBookkeeping {
+            signer: *signer,
+            market: keys.market(),
+            bookkeeping: keys.bookkeeping(),
+            exits: keys.exits,
+            prices: keys.prices,
+            system_program: system_program::ID,
+        },
+        instruction::UpdateBookkeeping {},
+    )
+}
+
+// Returns the value through the transaction return data, so it's meant to be simulated
+pub fn max_end_slot(keys: &MarketKeys) -> Instruction {
+    build(
+        accounts::MaxEndSlot {
+            market: keys.market(),
+            exits: keys.exits,
+        },
+        instruction::MaxEndSlot {},
+    )
+}
+
+pub fn archive_prices(keys: &MarketKeys, payer: &Pubkey, epoch: u64, page: u64) -> Instruction {
+    let market = keys.market();
+
+    build(
+        accounts::ArchivePrices {
+            payer: *payer,
+            market,
+            exits: keys.exits,
+            prices: keys.prices,
+            price_archive: pda::price_archive(&market, epoch, page).0,
+            system_program: system_program::ID,
+        },
+        instruction::ArchivePrices { epoch, page },
+    )
+}
+
+pub fn initialize_pool(keys: &MarketKeys, signer: &Pubkey) -> Instruction {
+    build(
+        accounts::InitializePool {
+            signer: *signer,
+            market: keys.market(),
+            lp_mint: keys.lp_mint(),
+            lp_token_program: token::ID,
+            system_program: system_program::ID,
+        },
+        instruction::InitializePool {},
+    )
+}
+
+pub fn add_liquidity(
+    keys: &MarketKeys,
+    provider: &Pubkey,
+    max_amount_a: u64,
+    max_amount_b: u64,
+    min_shares: u64,
+) -> Instruction {
+    build(
+        accounts::AddLiquidity {
+            provider: *provider,
+            provider_token_account_a: keys.token_account_a(provider),
+            provider_token_account_b: keys.token_account_b(provider),
+            provider_lp_token_account: keys.lp_token_account(provider),
+            token_mint_a: keys.token_mint_a,
+            token_mint_b: keys.token_mint_b,
+            lp_mint: keys.lp_mint(),
+            market: keys.market(),
+            treasury_a: keys.treasury_a(),
+            treasury_b: keys.treasury_b(),
+            bookkeeping: keys.bookkeeping(),
+            exits: keys.exits,
+            prices: keys.prices,
+            token_program_a: keys.token_program_a,
+            token_program_b: keys.token_program_b,
+            lp_token_program: token::ID,
+            associated_token_program: associated_token::ID,
+            system_program: system_program::ID,
+        },
+        instruction::AddLiquidity {
+            max_amount_a,
+            max_amount_b,
+            min_shares,
+        },
+    )
+}
+
+pub fn remove_liquidity(
+    keys: &MarketKeys,
+    provider: &Pubkey,
+    shares: u64,
+    min_amount_a: u64,
+    min_amount_b: u64,
+) -> Instruction {
+    build(
+        accounts::RemoveLiquidity {
+            provider: *provider,
+            provider_token_account_a: keys.token_account_a(provider),
+            provider_token_account_b: keys.token_account_b(provider),
+            provider_lp_token_account: keys.lp_token_account(provider),
+            token_mint_a: keys.token_mint_a,
+            token_mint_b: keys.token_mint_b,
+            lp_mint: keys.lp_mint(),
+            market: keys.market(),
+            treasury_a: keys.treasury_a(),
+            treasury_b: keys.treasury_b(),
+            bookkeeping: keys.bookkeeping(),
+            exits: keys.exits,
+            prices: keys.prices,
+            token_program_a: keys.token_program_a,
+            token_program_b: keys.token_program_b,
+            lp_token_program: token::ID,
+            associated_token_program: associated_token::ID,
+            system_program: system_program::ID,
+        },
+        instruction::RemoveLiquidity {
+            shares,
+            min_amount_a,
+            min_amount_b,
+        },
+    )
+}
+
+pub fn swap_exact_in(
+    keys: &MarketKeys,
+    trader: &Pubkey,
+    amount_in: u64,
+    min_amount_out: u64,
+    a_to_b: bool,
+) -> Instruction {
+    build(
+        accounts::SwapExactIn {
+            trader: *trader,
+            trader_token_account_a: keys.token_account_a(trader),
+            trader_token_account_b: keys.token_account_b(trader),
+            token_mint_a: keys.token_mint_a,
+            token_mint_b: keys.token_mint_b,
+            market: keys.market(),
+            treasury_a: keys.treasury_a(),
+            treasury_b: keys.treasury_b(),
+            bookkeeping: keys.bookkeeping(),
+            exits: keys.exits,
+            prices: keys.prices,
+            token_program_a: keys.token_program_a,
+            token_program_b: keys.token_program_b,
+            associated_token_program: associated_token::ID,
+            system_program: system_program::ID,
+        },
+        instruction::SwapExactIn {
+            amount_in,
+            min_amount_out,
+            a_to_b,
+        },
+    )
+}
+
+// Fails when the market is inconsistent and returns an InvariantReport through the
+// transaction return data, so it's meant to be simulated
+pub fn verify_invariants(keys: &MarketKeys, signer: &Pubkey) -> Instruction {
+    build(
+        accounts::VerifyInvariants {
+            signer: *signer,
+            market: keys.market(),
+            bookkeeping: keys.bookkeeping(),
+            treasury_a: keys.treasury_a(),
+            treasury_b: keys.treasury_b(),
+            exits: keys.exits,
+            prices: keys.prices,
+        },
+        instruction::VerifyInvariants {},
+    )
+}
+
+pub fn close_market(keys: &MarketKeys, authority: &Pubkey) -> Instruction {
+    build(
+        accounts::CloseMarket {
+            authority: *authority,
+            market: keys.market(),
+        },
+        instruction::CloseMarket {},
+    )
+}
+
+// Pays the whole tokens of rounding dust into the authority's token accounts
+pub fn sweep_dust(keys: &MarketKeys, authority: &Pubkey) -> Instruction {
+    build(
+        accounts::SweepDust {
+            authority: *authority,
+            authority_token_account_a: keys.token_account_a(authority),
+            authority_token_account_b: keys.token_account_b(authority),
+            token_mint_a: keys.token_mint_a,
+            token_mint_b: keys.token_mint_b,
+            market: keys.market(),
+            treasury_a: keys.treasury_a(),
+            treasury_b: keys.treasury_b(),
+            bookkeeping: keys.bookkeeping(),
+            token_program_a: keys.token_program_a,
+            token_program_b: keys.token_program_b,
+            associated_token_program: associated_token::ID,
+            system_program: system_program::ID,
+        },
+        instruction::SweepDust {},
+    )
+}
@@ -0,0 +1,12 @@
+// Off-chain client for the eki program: account addresses, instruction builders and
+// decoders for the zero-copy exits and prices accounts. Layouts and seeds come straight
+// from the program crate, so they can't drift from what runs on chain.
+
+pub mod decode;
+pub mod instructions;
+pub mod pda;
+
+pub use eki;
+pub use eki::ID as PROGRAM_ID;
+
+pub use instructions::MarketKeys;
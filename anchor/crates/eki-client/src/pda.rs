@@ -0,0 +1,79 @@
+use anchor_lang::prelude::Pubkey;
+use anchor_spl::associated_token::get_associated_token_address_with_program_id;
+use eki::{
+    Bookkeeping, Market, PositionA, PositionB, PriceArchive, LP_MINT_SEED, TREASURY_A_SEED,
+    TREASURY_B_SEED, UNWRAP_SEED,
+};
+
+// There is a single market per program deployment
+pub fn market() -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[Market::SEED_PREFIX.as_bytes()], &eki::ID)
+}
+
+pub fn bookkeeping(market: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[Bookkeeping::SEED_PREFIX.as_bytes(), market.as_ref()],
+        &eki::ID,
+    )
+}
+
+pub fn treasury_a(market: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[TREASURY_A_SEED.as_bytes(), market.as_ref()], &eki::ID)
+}
+
+pub fn treasury_b(market: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[TREASURY_B_SEED.as_bytes(), market.as_ref()], &eki::ID)
+}
+
+// Position of owner selling token A
+pub fn position_a(market: &Pubkey, owner: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[
+            PositionA::SEED_PREFIX.as_bytes(),
+            market.as_ref(),
+            owner.as_ref(),
+        ],
+        &eki::ID,
+    )
+}
+
+// Position of owner selling token B
+pub fn position_b(market: &Pubkey, owner: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[
+            PositionB::SEED_PREFIX.as_bytes(),
+            market.as_ref(),
+            owner.as_ref(),
+        ],
+        &eki::ID,
+    )
+}
+
+pub fn lp_mint(market: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[LP_MINT_SEED.as_bytes(), market.as_ref()], &eki::ID)
+}
+
+// Temporary wrapped SOL account owner's native payouts are unwrapped through
+pub fn unwrap_account(market: &Pubkey, owner: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[UNWRAP_SEED.as_bytes(), market.as_ref(), owner.as_ref()],
+        &eki::ID,
+    )
+}
+
+pub fn price_archive(market: &Pubkey, epoch: u64, page: u64) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[
+            PriceArchive::SEED_PREFIX.as_bytes(),
+            market.as_ref(),
+            &epoch.to_le_bytes(),
+            &page.to_le_bytes(),
+        ],
+        &eki::ID,
+    )
+}
+
+// Token account the program expects for owner, created on demand by withdrawals
+pub fn associated_token_account(owner: &Pubkey, mint: &Pubkey, token_program: &Pubkey) -> Pubkey {
+    get_associated_token_address_with_program_id(owner, mint, token_program)
+}
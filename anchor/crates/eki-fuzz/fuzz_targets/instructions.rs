@@ -0,0 +1,310 @@
+// Random sequences of deposits, withdrawals, reductions, closes, cranks and dust sweeps
+// with clock warps, run against the program in an in-process bank and replayed on the
+// reference simulator. The program must not panic, has to succeed exactly when the
+// simulator does, and its treasuries, volumes and bookkeeping have to match the simulator
+// after every step.
+//
+// Every run boots a fresh bank, and banks are never entirely freed, so longer campaigns
+// should run in fork mode, which restarts the fuzzing process between jobs:
+//
+//     cargo +nightly fuzz run --fuzz-dir crates/eki-fuzz instructions -- -fork=1
+
+#![no_main]
+
+use std::collections::HashSet;
+
+use arbitrary::Arbitrary;
+use eki_client::eki::{TimeMode, MINIMUM_EXITS_HORIZON_SECONDS};
+use eki_client::instructions;
+use eki_sim::{MarketConfig, Simulator};
+use eki_tests::{TestMarket, DECIMALS};
+use libfuzzer_sys::fuzz_target;
+use solana_sdk::signature::Signer;
+
+const START_SLOT: u64 = 100;
+const END_SLOT_INTERVAL: u64 = 10;
+const PRICES_LENGTH: u64 = 32;
+const USERS: u8 = 4;
+// Enough of both tokens for any sequence of deposits without overflowing the supply
+const USER_BALANCE: u64 = 1_000_000_000_000_000_000;
+
+#[derive(Arbitrary, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+enum Action {
+    DepositTokenA {
+        user: u8,
+        amount: u32,
+        duration: u16,
+    },
+    DepositTokenB {
+        user: u8,
+        amount: u32,
+        duration: u16,
+    },
+    WithdrawSwappedTokenA {
+        user: u8,
+    },
+    WithdrawSwappedTokenB {
+        user: u8,
+    },
+    ReducePositionA {
+        user: u8,
+        amount: u32,
+    },
+    ReducePositionB {
+        user: u8,
+        amount: u32,
+    },
+    ClosePositionA {
+        user: u8,
+    },
+    ClosePositionB {
+        user: u8,
+    },
+    UpdateBookkeeping,
+    CloseMarket,
+    SweepDust,
+    Warp {
+        slots: u16,
+    },
+}
+
+impl Action {
+    // Same action with the user folded into the ones that exist
+    fn normalized(self) -> Self {
+        match self {
+            Action::DepositTokenA {
+                user,
+                amount,
+                duration,
+            } => Action::DepositTokenA {
+                user: user % USERS,
+                amount,
+                duration,
+            },
+            Action::DepositTokenB {
+                user,
+                amount,
+                duration,
+            } => Action::DepositTokenB {
+                user: user % USERS,
+                amount,
+                duration,
+            },
+            Action::WithdrawSwappedTokenA { user } => {
+                Action::WithdrawSwappedTokenA { user: user % USERS }
+            }
+            Action::WithdrawSwappedTokenB { user } => {
+                Action::WithdrawSwappedTokenB { user: user % USERS }
+            }
+            Action::ReducePositionA { user, amount } => Action::ReducePositionA {
+                user: user % USERS,
+                amount,
+            },
+            Action::ReducePositionB { user, amount } => Action::ReducePositionB {
+                user: user % USERS,
+                amount,
+            },
+            Action::ClosePositionA { user } => Action::ClosePositionA { user: user % USERS },
+            Action::ClosePositionB { user } => Action::ClosePositionB { user: user % USERS },
+            action => action,
+        }
+    }
+}
+
+// Every run gets its own runtime, which drops the background tasks of its bank with it
+fuzz_target!(|actions: Vec<Action>| {
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap()
+        .block_on(run(actions));
+});
+
+async fn run(actions: Vec<Action>) {
+    let horizon = TimeMode::Slot.duration_from_seconds(MINIMUM_EXITS_HORIZON_SECONDS);
+    let exits_length = horizon / END_SLOT_INTERVAL;
+
+    let mut market =
+        TestMarket::new(START_SLOT, END_SLOT_INTERVAL, exits_length, PRICES_LENGTH).await;
+    let mut sim = Simulator::new(MarketConfig {
+        start_slot: START_SLOT,
+        end_slot_interval: END_SLOT_INTERVAL,
+        time_mode: TimeMode::Slot,
+        exits_length,
+        prices_length: PRICES_LENGTH,
+        decimals_a: DECIMALS,
+        decimals_b: DECIMALS,
+    })
+    .unwrap();
+
+    let mut users = Vec::new();
+    for _ in 0..USERS {
+        users.push(market.user(USER_BALANCE, USER_BALANCE).await);
+    }
+
+    let keys = market.keys;
+    let payer = market.payer();
+    let mut slot = market.slot().await;
+    let mut sent = HashSet::new();
+
+    for action in actions {
+        let action = action.normalized();
+
+        if let Action::Warp { slots } = action {
+            slot += 1 + u64::from(slots) % horizon;
+            market.warp_to_slot(slot).await;
+            continue;
+        }
+
+        // an identical transaction in the same slot would be rejected as already processed
+        if !sent.insert((slot, action)) {
+            continue;
+        }
+
+        let (instruction, user, simulated) = match action {
+            Action::DepositTokenA {
+                user,
+                amount,
+                duration,
+            } => {
+                let user = &users[user as usize];
+                let owner = user.pubkey();
+                let amount = u64::from(amount) * 1000;
+                let end_slot = slot + u64::from(duration) % (2 * horizon);
+
+                let instruction = instructions::deposit_token_a(
+                    &keys,
+                    &owner,
+                    Some(keys.token_account_a(&owner)),
+                    amount,
+                    end_slot,
+                );
+                let simulated = sim.deposit_token_a(owner, amount, end_slot, slot);
+                (instruction, Some(user), simulated)
+            }
+            Action::DepositTokenB {
+                user,
+                amount,
+                duration,
+            } => {
+                let user = &users[user as usize];
+                let owner = user.pubkey();
+                let amount = u64::from(amount) * 1000;
+                let end_slot = slot + u64::from(duration) % (2 * horizon);
+
+                let instruction = instructions::deposit_token_b(
+                    &keys,
+                    &owner,
+                    Some(keys.token_account_b(&owner)),
+                    amount,
+                    end_slot,
+                );
+                let simulated = sim.deposit_token_b(owner, amount, end_slot, slot);
+                (instruction, Some(user), simulated)
+            }
+            Action::WithdrawSwappedTokenA { user } => {
+                let user = &users[user as usize];
+                let instruction =
+                    instructions::withdraw_swapped_token_a(&keys, &user.pubkey(), None, false);
+                let simulated = sim.withdraw_swapped_token_a(user.pubkey(), slot).map(drop);
+                (instruction, Some(user), simulated)
+            }
+            Action::WithdrawSwappedTokenB { user } => {
+                let user = &users[user as usize];
+                let instruction =
+                    instructions::withdraw_swapped_token_b(&keys, &user.pubkey(), None, false);
+                let simulated = sim.withdraw_swapped_token_b(user.pubkey(), slot).map(drop);
+                (instruction, Some(user), simulated)
+            }
+            Action::ReducePositionA { user, amount } => {
+                let user = &users[user as usize];
+                let amount = u64::from(amount);
+                let instruction =
+                    instructions::reduce_position_a(&keys, &user.pubkey(), amount, false);
+                let simulated = sim.reduce_position_a(user.pubkey(), amount, slot).map(drop);
+                (instruction, Some(user), simulated)
+            }
+            Action::ReducePositionB { user, amount } => {
+                let user = &users[user as usize];
+                let amount = u64::from(amount);
+                let instruction =
+                    instructions::reduce_position_b(&keys, &user.pubkey(), amount, false);
+                let simulated = sim.reduce_position_b(user.pubkey(), amount, slot).map(drop);
+                (instruction, Some(user), simulated)
+            }
+            Action::ClosePositionA { user } => {
+                let user = &users[user as usize];
+                let instruction =
+                    instructions::close_position_a(&keys, &user.pubkey(), None, false);
+                let simulated = sim.close_position_a(user.pubkey(), slot).map(drop);
+                (instruction, Some(user), simulated)
+            }
+            Action::ClosePositionB { user } => {
+                let user = &users[user as usize];
+                let instruction =
+                    instructions::close_position_b(&keys, &user.pubkey(), None, false);
+                let simulated = sim.close_position_b(user.pubkey(), slot).map(drop);
+                (instruction, Some(user), simulated)
+            }
+            Action::UpdateBookkeeping => (
+                instructions::update_bookkeeping(&keys, &payer),
+                None,
+                sim.crank(slot),
+            ),
+            Action::CloseMarket => (
+                instructions::close_market(&keys, &payer),
+                None,
+                sim.close_market(slot),
+            ),
+            Action::SweepDust => (
+                instructions::sweep_dust(&keys, &payer),
+                None,
+                sim.sweep_dust().map(drop),
+            ),
+            Action::Warp { .. } => unreachable!(),
+        };
+
+        let signers: Vec<_> = user.into_iter().collect();
+        let executed = market.process(&[instruction], &signers).await;
+        assert_eq!(
+            executed.is_ok(),
+            simulated.is_ok(),
+            "{action:?} at slot {slot}: program {executed:?}, simulator {simulated:?}"
+        );
+
+        check_balances(&mut market, &mut sim).await;
+    }
+}
+
+// The program's treasuries, volumes and bookkeeping match the simulator, whose invariants
+// tie them to the open positions
+async fn check_balances(market: &mut TestMarket, sim: &mut Simulator) {
+    let treasury_a = market.balance(market.keys.treasury_a()).await;
+    let treasury_b = market.balance(market.keys.treasury_b()).await;
+    assert_eq!(treasury_a, sim.treasury_a, "treasury a");
+    assert_eq!(treasury_b, sim.treasury_b, "treasury b");
+
+    let state = market.market().await;
+    assert_eq!(
+        state.token_a_volume, sim.market.token_a_volume,
+        "token a volume"
+    );
+    assert_eq!(
+        state.token_b_volume, sim.market.token_b_volume,
+        "token b volume"
+    );
+
+    let bookkeeping = market.bookkeeping().await;
+    assert_eq!(bookkeeping.a_per_b, sim.bookkeeping.a_per_b, "a per b");
+    assert_eq!(bookkeeping.b_per_a, sim.bookkeeping.b_per_a, "b per a");
+    assert_eq!(
+        bookkeeping.last_slot, sim.bookkeeping.last_slot,
+        "last slot"
+    );
+    assert_eq!(bookkeeping.dust_a, sim.bookkeeping.dust_a, "dust a");
+    assert_eq!(bookkeeping.dust_b, sim.bookkeeping.dust_b, "dust b");
+
+    if let Err(violation) = sim.check_invariants() {
+        panic!("{violation}");
+    }
+}
@@ -0,0 +1,90 @@
+use std::collections::BTreeMap;
+
+use crate::Simulator;
+
+impl Simulator {
+    // Checks that the aggregate state agrees with the open positions:
+    // - market volumes are the sum of streams whose exit bucket hasn't been processed
+    // - each exits bucket holds the volume of exactly those streams ending in it
+    // - each price checkpoint is owed to exactly the closed-over streams ending there
+    // - the treasuries cover the pool reserves
+    // Returns a description of the first violation.
+    pub fn check_invariants(&mut self) -> Result<(), String> {
+        let interval = self.market.end_slot_interval;
+        let exits = *self.exits();
+        let (pointer, length) = (exits.pointer, exits.length);
+
+        let mut pending = vec![(0u64, 0u64); length as usize];
+        let mut passed = BTreeMap::<u64, (u64, u64)>::new();
+        for position in self.positions_a.values() {
+            let bucket = exits.bucket_id(position.end_slot, interval);
+            if bucket > pointer {
+                pending[(bucket % length) as usize].0 += position.get_volume();
+            } else {
+                passed.entry(bucket).or_default().0 += position.get_volume();
+            }
+        }
+        for position in self.positions_b.values() {
+            let bucket = exits.bucket_id(position.end_slot, interval);
+            if bucket > pointer {
+                pending[(bucket % length) as usize].1 += position.get_volume();
+            } else {
+                passed.entry(bucket).or_default().1 += position.get_volume();
+            }
+        }
+
+        let volume_a: u64 = pending.iter().map(|(a, _)| a).sum();
+        let volume_b: u64 = pending.iter().map(|(_, b)| b).sum();
+        if (volume_a, volume_b) != (self.market.token_a_volume, self.market.token_b_volume) {
+            return Err(format!(
+                "market volumes ({}, {}) don't match open streams ({volume_a}, {volume_b})",
+                self.market.token_a_volume, self.market.token_b_volume
+            ));
+        }
+
+        {
+            let exits = self.exits();
+            for (bucket, (a, b)) in pending.iter().enumerate() {
+                if (exits.token_a[bucket], exits.token_b[bucket]) != (*a, *b) {
+                    return Err(format!(
+                        "exits bucket {bucket} holds ({}, {}) instead of ({a}, {b})",
+                        exits.token_a[bucket], exits.token_b[bucket]
+                    ));
+                }
+            }
+        }
+
+        {
+            let prices = self.prices();
+            for checkpoint in prices.checkpoints.iter().filter(|c| c.bucket != 0) {
+                let expected = passed.remove(&checkpoint.bucket).unwrap_or_default();
+                if (checkpoint.outstanding_a, checkpoint.outstanding_b) != expected {
+                    return Err(format!(
+                        "checkpoint of bucket {} is owed ({}, {}) instead of ({}, {})",
+                        checkpoint.bucket,
+                        checkpoint.outstanding_a,
+                        checkpoint.outstanding_b,
+                        expected.0,
+                        expected.1
+                    ));
+                }
+            }
+        }
+
+        if let Some(bucket) = passed.keys().next() {
+            return Err(format!(
+                "positions ending in bucket {bucket} have no checkpoint"
+            ));
+        }
+
+        let pool = self.bookkeeping.pool;
+        if self.treasury_a < pool.reserve_a || self.treasury_b < pool.reserve_b {
+            return Err(format!(
+                "treasuries ({}, {}) don't cover the pool reserves ({}, {})",
+                self.treasury_a, self.treasury_b, pool.reserve_a, pool.reserve_b
+            ));
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,512 @@
+// Host-only model of an eki market. It keeps the same Market, Bookkeeping, Exits, Prices
+// and position state as the program and drives it through the program's own position
+// flows, state methods and crank, so deposits, withdrawals and closes can be replayed
+// over a slot timeline without a validator. Token transfers are reduced to treasury
+// balances and returned payouts. Price archives, native SOL and transfer hooks are not
+// modelled.
+
+mod invariants;
+
+use std::collections::BTreeMap;
+
+use anchor_lang::error::ErrorCode;
+use anchor_lang::prelude::*;
+use eki::error::CustomErrorCode;
+use eki::{
+    apply_close_a, apply_close_b, apply_deposit_a, apply_deposit_b, apply_reduce_a, apply_reduce_b,
+    apply_withdraw_a, apply_withdraw_b, crank_exits, new_position_a, new_position_b, trade_up_to,
+    Bookkeeping, Exits, ExitsBuffer, Market, PositionA, PositionB, Prices, PricesBuffer, TimeMode,
+    MINIMUM_EXITS_HORIZON_SECONDS, MINIMUM_EXITS_LENGTH, MINIMUM_PRICES_LENGTH,
+};
+
+#[derive(Clone, Copy)]
+pub struct MarketConfig {
+    pub start_slot: u64,
+    pub end_slot_interval: u64,
+    pub time_mode: TimeMode,
+    pub exits_length: u64,
+    pub prices_length: u64,
+    pub decimals_a: u8,
+    pub decimals_b: u8,
+}
+
+// Tokens paid out by closing a position
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Payout {
+    pub amount_a: u64,
+    pub amount_b: u64,
+}
+
+#[derive(Clone)]
+pub struct Simulator {
+    pub market: Market,
+    pub bookkeeping: Bookkeeping,
+    pub positions_a: BTreeMap<Pubkey, PositionA>,
+    pub positions_b: BTreeMap<Pubkey, PositionB>,
+    pub treasury_a: u64,
+    pub treasury_b: u64,
+    decimals_a: u8,
+    decimals_b: u8,
+    // account data of the zero-copy accounts, kept in words for alignment
+    exits: Vec<u64>,
+    prices: Vec<u64>,
+}
+
+impl Simulator {
+    // Same checks as initialize_market, except that start_slot isn't moved to the clock
+    pub fn new(config: MarketConfig) -> Result<Self> {
+        let MarketConfig {
+            start_slot,
+            end_slot_interval,
+            time_mode,
+            exits_length,
+            prices_length,
+            ..
+        } = config;
+
+        if end_slot_interval == 0 {
+            return Err(CustomErrorCode::InvalidSlotInterval.into());
+        }
+
+        if exits_length < MINIMUM_EXITS_LENGTH || prices_length < MINIMUM_PRICES_LENGTH {
+            return Err(CustomErrorCode::InvalidBufferLength.into());
+        }
+
+        let horizon = end_slot_interval.saturating_mul(exits_length);
+        if horizon < time_mode.duration_from_seconds(MINIMUM_EXITS_HORIZON_SECONDS) {
+            return Err(CustomErrorCode::ExitsHorizonTooShort.into());
+        }
+
+        let mut simulator = Self {
+            market: Market::new(
+                Pubkey::default(),
+                Pubkey::default(),
+                Pubkey::default(),
+                Pubkey::default(),
+                Pubkey::default(),
+                Pubkey::default(),
+                Pubkey::default(),
+                start_slot,
+                end_slot_interval,
+                time_mode,
+                0,
+            ),
+            bookkeeping: Bookkeeping::new(start_slot, 0),
+            positions_a: BTreeMap::new(),
+            positions_b: BTreeMap::new(),
+            treasury_a: 0,
+            treasury_b: 0,
+            decimals_a: config.decimals_a,
+            decimals_b: config.decimals_b,
+            exits: vec![0; Exits::space(exits_length as usize) / 8],
+            prices: vec![0; Prices::space(prices_length as usize) / 8],
+        };
+
+        let exits_start_slot = start_slot / end_slot_interval * end_slot_interval;
+        ExitsBuffer::init(
+            bytemuck::cast_slice_mut(&mut simulator.exits),
+            exits_length as usize,
+            exits_start_slot,
+        )?;
+        PricesBuffer::init(
+            bytemuck::cast_slice_mut(&mut simulator.prices),
+            prices_length as usize,
+        )?;
+
+        Ok(simulator)
+    }
+
+    pub fn exits(&mut self) -> ExitsBuffer<'_> {
+        ExitsBuffer::load(bytemuck::cast_slice_mut(&mut self.exits)).unwrap()
+    }
+
+    pub fn prices(&mut self) -> PricesBuffer<'_> {
+        PricesBuffer::load(bytemuck::cast_slice_mut(&mut self.prices)).unwrap()
+    }
+
+    pub fn deposit_token_a(
+        &mut self,
+        owner: Pubkey,
+        amount: u64,
+        end_slot: u64,
+        slot: u64,
+    ) -> Result<()> {
+        self.transact(|sim| {
+            if sim.positions_a.contains_key(&owner) {
+                return Err(ProgramError::AccountAlreadyInitialized.into());
+            }
+
+            let exits_length = sim.exits().length;
+            let mut position = new_position_a(
+                &sim.market,
+                exits_length,
+                sim.decimals_a,
+                amount,
+                end_slot,
+                slot,
+                0,
+            )?;
+            sim.treasury_a += amount;
+
+            let (market, bookkeeping, mut exits, mut prices) = sim.state();
+            apply_deposit_a(
+                market,
+                bookkeeping,
+                &mut exits,
+                &mut prices,
+                &mut position,
+                slot,
+            )?;
+
+            sim.positions_a.insert(owner, position);
+            Ok(())
+        })
+    }
+
+    pub fn deposit_token_b(
+        &mut self,
+        owner: Pubkey,
+        amount: u64,
+        end_slot: u64,
+        slot: u64,
+    ) -> Result<()> {
+        self.transact(|sim| {
+            if sim.positions_b.contains_key(&owner) {
+                return Err(ProgramError::AccountAlreadyInitialized.into());
+            }
+
+            let exits_length = sim.exits().length;
+            let mut position = new_position_b(
+                &sim.market,
+                exits_length,
+                sim.decimals_b,
+                amount,
+                end_slot,
+                slot,
+                0,
+            )?;
+            sim.treasury_b += amount;
+
+            let (market, bookkeeping, mut exits, mut prices) = sim.state();
+            apply_deposit_b(
+                market,
+                bookkeeping,
+                &mut exits,
+                &mut prices,
+                &mut position,
+                slot,
+            )?;
+
+            sim.positions_b.insert(owner, position);
+            Ok(())
+        })
+    }
+
+    // Token A swapped so far for the owner's token B position, like withdraw_swapped_token_a
+    pub fn withdraw_swapped_token_a(&mut self, owner: Pubkey, slot: u64) -> Result<u64> {
+        self.transact(|sim| {
+            let mut position = sim
+                .positions_b
+                .get(&owner)
+                .cloned()
+                .ok_or(ErrorCode::AccountNotInitialized)?;
+
+            let (market, bookkeeping, mut exits, mut prices) = sim.state();
+            let amount_a = apply_withdraw_a(
+                market,
+                bookkeeping,
+                &mut exits,
+                &mut prices,
+                None,
+                &mut position,
+                slot,
+            )?;
+
+            sim.treasury_a = pay(sim.treasury_a, amount_a)?;
+            sim.positions_b.insert(owner, position);
+            Ok(amount_a)
+        })
+    }
+
+    // Token B swapped so far for the owner's token A position, like withdraw_swapped_token_b
+    pub fn withdraw_swapped_token_b(&mut self, owner: Pubkey, slot: u64) -> Result<u64> {
+        self.transact(|sim| {
+            let mut position = sim
+                .positions_a
+                .get(&owner)
+                .cloned()
+                .ok_or(ErrorCode::AccountNotInitialized)?;
+
+            let (market, bookkeeping, mut exits, mut prices) = sim.state();
+            let amount_b = apply_withdraw_b(
+                market,
+                bookkeeping,
+                &mut exits,
+                &mut prices,
+                None,
+                &mut position,
+                slot,
+            )?;
+
+            sim.treasury_b = pay(sim.treasury_b, amount_b)?;
+            sim.positions_a.insert(owner, position);
+            Ok(amount_b)
+        })
+    }
+
+    pub fn close_position_a(&mut self, owner: Pubkey, slot: u64) -> Result<Payout> {
+        self.transact(|sim| {
+            let mut position = sim
+                .positions_a
+                .remove(&owner)
+                .ok_or(ErrorCode::AccountNotInitialized)?;
+
+            let (market, bookkeeping, mut exits, mut prices) = sim.state();
+            let (amount_a, amount_b) = apply_close_a(
+                market,
+                bookkeeping,
+                &mut exits,
+                &mut prices,
+                None,
+                &mut position,
+                slot,
+            )?;
+
+            sim.treasury_a = pay(sim.treasury_a, amount_a)?;
+            sim.treasury_b = pay(sim.treasury_b, amount_b)?;
+            Ok(Payout { amount_a, amount_b })
+        })
+    }
+
+    pub fn close_position_b(&mut self, owner: Pubkey, slot: u64) -> Result<Payout> {
+        self.transact(|sim| {
+            let mut position = sim
+                .positions_b
+                .remove(&owner)
+                .ok_or(ErrorCode::AccountNotInitialized)?;
+
+            let (market, bookkeeping, mut exits, mut prices) = sim.state();
+            let (amount_a, amount_b) = apply_close_b(
+                market,
+                bookkeeping,
+                &mut exits,
+                &mut prices,
+                None,
+                &mut position,
+                slot,
+            )?;
+
+            sim.treasury_a = pay(sim.treasury_a, amount_a)?;
+            sim.treasury_b = pay(sim.treasury_b, amount_b)?;
+            Ok(Payout { amount_a, amount_b })
+        })
+    }
+
+    // Like reduce_position_a, returns what the position was paid along with amount
+    pub fn reduce_position_a(&mut self, owner: Pubkey, amount: u64, slot: u64) -> Result<Payout> {
+        self.transact(|sim| {
+            let mut position = sim
+                .positions_a
+                .get(&owner)
+                .cloned()
+                .ok_or(ErrorCode::AccountNotInitialized)?;
+
+            let (market, bookkeeping, mut exits, mut prices) = sim.state();
+            let (amount_a, amount_b) = apply_reduce_a(
+                market,
+                bookkeeping,
+                &mut exits,
+                &mut prices,
+                &mut position,
+                amount,
+                slot,
+            )?;
+
+            sim.treasury_a = pay(sim.treasury_a, amount_a)?;
+            sim.treasury_b = pay(sim.treasury_b, amount_b)?;
+            sim.positions_a.insert(owner, position);
+            Ok(Payout { amount_a, amount_b })
+        })
+    }
+
+    // Like reduce_position_b, returns what the position was paid along with amount
+    pub fn reduce_position_b(&mut self, owner: Pubkey, amount: u64, slot: u64) -> Result<Payout> {
+        self.transact(|sim| {
+            let mut position = sim
+                .positions_b
+                .get(&owner)
+                .cloned()
+                .ok_or(ErrorCode::AccountNotInitialized)?;
+
+            let (market, bookkeeping, mut exits, mut prices) = sim.state();
+            let (amount_a, amount_b) = apply_reduce_b(
+                market,
+                bookkeeping,
+                &mut exits,
+                &mut prices,
+                &mut position,
+                amount,
+                slot,
+            )?;
+
+            sim.treasury_a = pay(sim.treasury_a, amount_a)?;
+            sim.treasury_b = pay(sim.treasury_b, amount_b)?;
+            sim.positions_b.insert(owner, position);
+            Ok(Payout { amount_a, amount_b })
+        })
+    }
+
+    pub fn close_market(&mut self, slot: u64) -> Result<()> {
+        self.transact(|sim| {
+            if sim.market.end_slot.is_some() {
+                return Err(CustomErrorCode::MarketClosed.into());
+            }
+
+            sim.market.end_slot = Some(slot.max(sim.market.start_slot));
+            Ok(())
+        })
+    }
+
+    // Whole tokens of rounding dust taken by the authority, like sweep_dust
+    pub fn sweep_dust(&mut self) -> Result<Payout> {
+        self.transact(|sim| {
+            if sim.market.end_slot.is_none() {
+                return Err(CustomErrorCode::MarketNotClosed.into());
+            }
+
+            let (amount_a, amount_b) = sim.bookkeeping.sweep_dust();
+            sim.treasury_a = pay(sim.treasury_a, amount_a)?;
+            sim.treasury_b = pay(sim.treasury_b, amount_b)?;
+            Ok(Payout { amount_a, amount_b })
+        })
+    }
+
+    // Like update_bookkeeping, processes passed exits buckets for as long as their
+    // checkpoints fit into the prices table
+    pub fn crank(&mut self, slot: u64) -> Result<()> {
+        if slot <= self.market.start_slot {
+            return Ok(());
+        }
+
+        let mut exits = ExitsBuffer::load(bytemuck::cast_slice_mut(&mut self.exits))?;
+        let mut prices = PricesBuffer::load(bytemuck::cast_slice_mut(&mut self.prices))?;
+        crank_exits(
+            &mut self.market,
+            &mut self.bookkeeping,
+            &mut exits,
+            &mut prices,
+            slot,
+        );
+
+        Ok(())
+    }
+
+    pub fn add_liquidity(
+        &mut self,
+        max_amount_a: u64,
+        max_amount_b: u64,
+        min_shares: u64,
+        slot: u64,
+    ) -> Result<u64> {
+        self.transact(|sim| {
+            sim.trade_up_to(slot)?;
+
+            let (shares, amount_a, amount_b) =
+                sim.bookkeeping.pool.deposit(max_amount_a, max_amount_b)?;
+            if shares < min_shares {
+                return Err(CustomErrorCode::SlippageExceeded.into());
+            }
+
+            sim.treasury_a += amount_a;
+            sim.treasury_b += amount_b;
+            Ok(shares)
+        })
+    }
+
+    pub fn remove_liquidity(
+        &mut self,
+        shares: u64,
+        min_amount_a: u64,
+        min_amount_b: u64,
+        slot: u64,
+    ) -> Result<Payout> {
+        self.transact(|sim| {
+            sim.trade_up_to(slot)?;
+
+            let (amount_a, amount_b) = sim.bookkeeping.pool.withdraw(shares)?;
+            if amount_a < min_amount_a || amount_b < min_amount_b {
+                return Err(CustomErrorCode::SlippageExceeded.into());
+            }
+
+            sim.treasury_a = pay(sim.treasury_a, amount_a)?;
+            sim.treasury_b = pay(sim.treasury_b, amount_b)?;
+            Ok(Payout { amount_a, amount_b })
+        })
+    }
+
+    pub fn swap_exact_in(
+        &mut self,
+        amount_in: u64,
+        min_amount_out: u64,
+        a_to_b: bool,
+        slot: u64,
+    ) -> Result<u64> {
+        self.transact(|sim| {
+            sim.trade_up_to(slot)?;
+
+            let amount_out = sim.bookkeeping.pool.swap(amount_in, a_to_b)?;
+            if amount_out == 0 || amount_out < min_amount_out {
+                return Err(CustomErrorCode::SlippageExceeded.into());
+            }
+
+            if a_to_b {
+                sim.treasury_a += amount_in;
+                sim.treasury_b = pay(sim.treasury_b, amount_out)?;
+            } else {
+                sim.treasury_b += amount_in;
+                sim.treasury_a = pay(sim.treasury_a, amount_out)?;
+            }
+            Ok(amount_out)
+        })
+    }
+
+    // A failed instruction leaves no trace, so state is rolled back on errors
+    fn transact<T>(&mut self, f: impl FnOnce(&mut Self) -> Result<T>) -> Result<T> {
+        let snapshot = self.clone();
+        let result = f(self);
+        if result.is_err() {
+            *self = snapshot;
+        }
+        result
+    }
+
+    // The state the program's flows work on, borrowed apart from the rest of the simulator
+    fn state(
+        &mut self,
+    ) -> (
+        &mut Market,
+        &mut Bookkeeping,
+        ExitsBuffer<'_>,
+        PricesBuffer<'_>,
+    ) {
+        (
+            &mut self.market,
+            &mut self.bookkeeping,
+            ExitsBuffer::load(bytemuck::cast_slice_mut(&mut self.exits)).unwrap(),
+            PricesBuffer::load(bytemuck::cast_slice_mut(&mut self.prices)).unwrap(),
+        )
+    }
+
+    // Streams trade against the reserves up to slot before liquidity or swaps move them
+    fn trade_up_to(&mut self, slot: u64) -> Result<()> {
+        let (market, bookkeeping, mut exits, mut prices) = self.state();
+        trade_up_to(market, bookkeeping, &mut exits, &mut prices, slot)
+    }
+}
+
+// Transfer out of a treasury, which fails if it doesn't hold enough
+fn pay(treasury: u64, amount: u64) -> Result<u64> {
+    treasury
+        .checked_sub(amount)
+        .ok_or_else(|| ProgramError::InsufficientFunds.into())
+}
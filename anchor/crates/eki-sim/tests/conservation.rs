@@ -0,0 +1,341 @@
+// Random streams on both sides, each withdrawing a few times, maybe reducing its deposit
+// and closing once, replayed in slot order. Every payout has to come out of the
+// treasuries, the invariants have to hold after every step, each position has to receive
+// about what an exact continuous model of the market pays it, and the rounding dust has
+// to account for whatever is left in the end.
+
+use anchor_lang::prelude::Pubkey;
+use eki::{TimeMode, DUST_PRECISION, VOLUME_PRECISION};
+use eki_sim::{MarketConfig, Simulator};
+use proptest::prelude::*;
+
+const START_SLOT: u64 = 100;
+const DECIMALS: u8 = 6;
+
+fn config() -> MarketConfig {
+    MarketConfig {
+        start_slot: START_SLOT,
+        end_slot_interval: 100,
+        time_mode: TimeMode::Slot,
+        exits_length: 100,
+        prices_length: 64,
+        decimals_a: DECIMALS,
+        decimals_b: DECIMALS,
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Stream {
+    sells_a: bool,
+    amount: u64,
+    deposit_slot: u64,
+    end_slot: u64,
+    // fractions of the streaming time at which the position withdraws
+    withdrawals: Vec<f64>,
+    // fraction of the streaming time at which the position gives up a share of what it
+    // hasn't streamed yet
+    reduction: Option<(f64, f64)>,
+    close_slot: u64,
+}
+
+// Durations of at least 1000 slots and deposits of up to 1000 tokens keep the summed
+// volumes small enough for the unchecked bookkeeping math
+fn stream() -> impl Strategy<Value = Stream> {
+    (
+        any::<bool>(),
+        1_000_000..=1_000_000_000u64,
+        0..5_000u64,
+        1_000..=9_000u64,
+        prop::collection::vec(0.0..1.0f64, 0..3),
+        prop::option::of((0.0..1.0f64, 0.01..0.9f64)),
+        1..11_000u64,
+    )
+        .prop_map(
+            |(sells_a, amount, deposit_offset, duration, withdrawals, reduction, close_offset)| {
+                let deposit_slot = START_SLOT + deposit_offset;
+                Stream {
+                    sells_a,
+                    amount,
+                    deposit_slot,
+                    end_slot: deposit_slot + duration,
+                    withdrawals,
+                    reduction,
+                    close_slot: deposit_slot + close_offset,
+                }
+            },
+        )
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+enum Action {
+    Deposit,
+    Withdraw,
+    Reduce,
+    Close,
+}
+
+// What a position is paid in the token it buys and gets back of the token it sells
+#[derive(Clone, Copy, Default, Debug)]
+struct Paid<T> {
+    bought: T,
+    refunded: T,
+}
+
+// Position window and volume as stored after rounding, and the slot it stopped streaming at.
+// A reduced position has a window for each volume it streamed.
+struct Window {
+    sells_a: bool,
+    rate: f64,
+    start_slot: u64,
+    end_slot: u64,
+    stop_slot: u64,
+}
+
+// Exact continuous payouts. Over every stretch where both sides stream, each position
+// buys at the ratio of the total volumes, otherwise that stretch is refunded, as is
+// anything left after an early close.
+fn ideal_payouts(windows: &[Window]) -> Vec<Paid<f64>> {
+    let mut slots: Vec<u64> = windows
+        .iter()
+        .flat_map(|w| [w.start_slot, w.stop_slot])
+        .collect();
+    slots.sort_unstable();
+    slots.dedup();
+
+    let mut paid = vec![Paid::default(); windows.len()];
+    for stretch in slots.windows(2) {
+        let (from, to) = (stretch[0], stretch[1]);
+        let active = |w: &Window| w.start_slot <= from && to <= w.stop_slot;
+
+        let volume = |sells_a: bool| -> f64 {
+            windows
+                .iter()
+                .filter(|w| w.sells_a == sells_a && active(w))
+                .map(|w| w.rate)
+                .sum()
+        };
+        let (volume_a, volume_b) = (volume(true), volume(false));
+
+        for (w, paid) in windows.iter().zip(paid.iter_mut()) {
+            if !active(w) {
+                continue;
+            }
+            let streamed = w.rate * (to - from) as f64;
+            match (volume_a > 0.0 && volume_b > 0.0, w.sells_a) {
+                (true, true) => paid.bought += streamed * volume_b / volume_a,
+                (true, false) => paid.bought += streamed * volume_a / volume_b,
+                (false, _) => paid.refunded += streamed,
+            }
+        }
+    }
+
+    for (w, paid) in windows.iter().zip(paid.iter_mut()) {
+        paid.refunded += w.rate * (w.end_slot - w.stop_slot) as f64;
+    }
+    paid
+}
+
+// Rounding allowed on what a position was paid over several payouts. Every payout rounds
+// down to a whole base unit, so a position paid once stays within one base unit of the
+// exact amount. Truncated prices cost less than 1e-9 of a base unit per bookkeeping update
+// at these volumes, which together with the float error of the model is far below the
+// allowance on top.
+fn rounding(payouts: usize) -> f64 {
+    payouts as f64 + 1e-3
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(256))]
+
+    #[test]
+    fn payouts_are_solvent_and_pro_rata(streams in prop::collection::vec(stream(), 1..=8)) {
+        let mut sim = Simulator::new(config()).unwrap();
+        let owners: Vec<Pubkey> = streams.iter().map(|_| Pubkey::new_unique()).collect();
+
+        // deposits come first within a slot and closes last, and withdrawals stay short
+        // of the requested end slot, which rounding moves by at most half an interval
+        let margin = config().end_slot_interval / 2 + 1;
+        let mut actions = Vec::new();
+        for (user, stream) in streams.iter().enumerate() {
+            actions.push((stream.deposit_slot, Action::Deposit, user));
+
+            let first_withdrawal = stream.deposit_slot + 1;
+            let last_withdrawal = stream.end_slot.min(stream.close_slot) - margin;
+            let at = |fraction: f64| {
+                let offset = last_withdrawal.saturating_sub(first_withdrawal) as f64 * fraction;
+                Some(first_withdrawal + offset as u64).filter(|&slot| slot <= last_withdrawal)
+            };
+            for &fraction in &stream.withdrawals {
+                if let Some(slot) = at(fraction) {
+                    actions.push((slot, Action::Withdraw, user));
+                }
+            }
+            if let Some(slot) = stream.reduction.and_then(|(fraction, _)| at(fraction)) {
+                actions.push((slot, Action::Reduce, user));
+            }
+
+            actions.push((stream.close_slot, Action::Close, user));
+        }
+        actions.sort();
+
+        let mut deposited_a = 0;
+        let mut deposited_b = 0;
+        let mut paid_a = 0;
+        let mut paid_b = 0;
+        let mut received = vec![Paid::<u64>::default(); streams.len()];
+        let mut reduced = vec![0; streams.len()];
+        let mut windows = Vec::new();
+
+        for &(slot, action, user) in &actions {
+            let stream = &streams[user];
+            let owner = owners[user];
+
+            // (token A, token B) paid out by the action
+            let (amount_a, amount_b) = match (action, stream.sells_a) {
+                (Action::Deposit, true) => {
+                    sim.deposit_token_a(owner, stream.amount, stream.end_slot, slot).unwrap();
+                    deposited_a += stream.amount;
+                    (0, 0)
+                }
+                (Action::Deposit, false) => {
+                    sim.deposit_token_b(owner, stream.amount, stream.end_slot, slot).unwrap();
+                    deposited_b += stream.amount;
+                    (0, 0)
+                }
+                (Action::Withdraw, true) => {
+                    let amount_b = sim.withdraw_swapped_token_b(owner, slot);
+                    prop_assert!(amount_b.is_ok(), "withdrawal at {slot}: {amount_b:?}");
+                    (0, amount_b.unwrap())
+                }
+                (Action::Withdraw, false) => {
+                    let amount_a = sim.withdraw_swapped_token_a(owner, slot);
+                    prop_assert!(amount_a.is_ok(), "withdrawal at {slot}: {amount_a:?}");
+                    (amount_a.unwrap(), 0)
+                }
+                (Action::Reduce, true) => {
+                    let position = &sim.positions_a[&owner];
+                    let unstreamed = position.get_volume() as u128
+                        * (position.end_slot - slot) as u128
+                        / VOLUME_PRECISION as u128;
+                    let amount = (unstreamed as f64 * stream.reduction.unwrap().1) as u64;
+                    windows.push((user, Window {
+                        sells_a: true,
+                        rate: position.get_volume() as f64 / VOLUME_PRECISION as f64,
+                        start_slot: position.start_slot,
+                        end_slot: slot,
+                        stop_slot: slot,
+                    }));
+
+                    let payout = sim.reduce_position_a(owner, amount, slot);
+                    prop_assert!(payout.is_ok(), "reduction of {amount} at {slot}: {payout:?}");
+                    reduced[user] += amount;
+                    let payout = payout.unwrap();
+                    (payout.amount_a, payout.amount_b)
+                }
+                (Action::Close, true) => {
+                    let position = &sim.positions_a[&owner];
+                    windows.push((user, Window {
+                        sells_a: true,
+                        rate: position.get_volume() as f64 / VOLUME_PRECISION as f64,
+                        start_slot: position.start_slot,
+                        end_slot: position.end_slot,
+                        stop_slot: slot.min(position.end_slot),
+                    }));
+
+                    let payout = sim.close_position_a(owner, slot);
+                    prop_assert!(payout.is_ok(), "close at {slot}: {payout:?}");
+                    let payout = payout.unwrap();
+                    (payout.amount_a, payout.amount_b)
+                }
+                (Action::Reduce, false) => {
+                    let position = &sim.positions_b[&owner];
+                    let unstreamed = position.get_volume() as u128
+                        * (position.end_slot - slot) as u128
+                        / VOLUME_PRECISION as u128;
+                    let amount = (unstreamed as f64 * stream.reduction.unwrap().1) as u64;
+                    windows.push((user, Window {
+                        sells_a: false,
+                        rate: position.get_volume() as f64 / VOLUME_PRECISION as f64,
+                        start_slot: position.start_slot,
+                        end_slot: slot,
+                        stop_slot: slot,
+                    }));
+
+                    let payout = sim.reduce_position_b(owner, amount, slot);
+                    prop_assert!(payout.is_ok(), "reduction of {amount} at {slot}: {payout:?}");
+                    reduced[user] += amount;
+                    let payout = payout.unwrap();
+                    (payout.amount_a, payout.amount_b)
+                }
+                (Action::Close, false) => {
+                    let position = &sim.positions_b[&owner];
+                    windows.push((user, Window {
+                        sells_a: false,
+                        rate: position.get_volume() as f64 / VOLUME_PRECISION as f64,
+                        start_slot: position.start_slot,
+                        end_slot: position.end_slot,
+                        stop_slot: slot.min(position.end_slot),
+                    }));
+
+                    let payout = sim.close_position_b(owner, slot);
+                    prop_assert!(payout.is_ok(), "close at {slot}: {payout:?}");
+                    let payout = payout.unwrap();
+                    (payout.amount_a, payout.amount_b)
+                }
+            };
+
+            paid_a += amount_a;
+            paid_b += amount_b;
+            if stream.sells_a {
+                received[user].bought += amount_b;
+                received[user].refunded += amount_a;
+            } else {
+                received[user].bought += amount_a;
+                received[user].refunded += amount_b;
+            }
+
+            if let Err(violation) = sim.check_invariants() {
+                prop_assert!(false, "after {action:?} of {user} at {slot}: {violation}");
+            }
+        }
+
+        // everything deposited is either still in a treasury or was paid out
+        prop_assert_eq!(paid_a + sim.treasury_a, deposited_a);
+        prop_assert_eq!(paid_b + sim.treasury_b, deposited_b);
+
+        // with every position closed, what's left is exactly the tracked dust
+        prop_assert_eq!(sim.bookkeeping.dust_a, sim.treasury_a as u128 * DUST_PRECISION);
+        prop_assert_eq!(sim.bookkeeping.dust_b, sim.treasury_b as u128 * DUST_PRECISION);
+
+        sim.close_market(actions.last().unwrap().0).unwrap();
+        sim.sweep_dust().unwrap();
+        prop_assert_eq!((sim.treasury_a, sim.treasury_b), (0, 0));
+
+        // a reduction hands back exactly the amount asked for on top of what's owed
+        let mut ideal: Vec<Paid<f64>> = reduced
+            .iter()
+            .map(|&amount| Paid { bought: 0.0, refunded: amount as f64 })
+            .collect();
+        let (users, windows): (Vec<usize>, Vec<Window>) = windows.into_iter().unzip();
+        for (user, paid) in users.into_iter().zip(ideal_payouts(&windows)) {
+            ideal[user].bought += paid.bought;
+            ideal[user].refunded += paid.refunded;
+        }
+
+        for (user, (paid, ideal)) in received.iter().zip(&ideal).enumerate() {
+            let payouts = actions
+                .iter()
+                .filter(|&&(_, action, u)| u == user && action != Action::Deposit)
+                .count();
+
+            prop_assert!(
+                (paid.bought as f64 - ideal.bought).abs() <= rounding(payouts),
+                "user {user} bought {} instead of {}", paid.bought, ideal.bought
+            );
+            prop_assert!(
+                (paid.refunded as f64 - ideal.refunded).abs() <= rounding(payouts),
+                "user {user} got back {} instead of {}", paid.refunded, ideal.refunded
+            );
+        }
+    }
+}
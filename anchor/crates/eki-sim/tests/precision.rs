@@ -0,0 +1,104 @@
+// Two streams over the same window trade everything they stream against each other, so
+// each position is owed exactly what the other side streamed. Payouts have to land within
+// one base unit of that, however lopsided the volumes and however often the bookkeeping
+// is updated in between.
+
+use anchor_lang::prelude::Pubkey;
+use eki::error::CustomErrorCode;
+use eki::{TimeMode, VOLUME_PRECISION};
+use eki_sim::{MarketConfig, Simulator};
+
+const START_SLOT: u64 = 100;
+const END_SLOT: u64 = 1_100;
+
+fn simulator() -> Simulator {
+    Simulator::new(MarketConfig {
+        start_slot: START_SLOT,
+        end_slot_interval: 100,
+        time_mode: TimeMode::Slot,
+        exits_length: 100,
+        prices_length: 64,
+        decimals_a: 6,
+        decimals_b: 6,
+    })
+    .unwrap()
+}
+
+// Streams both amounts from the market start to END_SLOT, updating the bookkeeping every
+// crank_interval slots, and checks both payouts against the exact amounts
+fn assert_exact_payouts(amount_a: u64, amount_b: u64, crank_interval: u64) {
+    let mut sim = simulator();
+    let (alice, bob) = (Pubkey::new_unique(), Pubkey::new_unique());
+
+    sim.deposit_token_a(alice, amount_a, END_SLOT, START_SLOT)
+        .unwrap();
+    sim.deposit_token_b(bob, amount_b, END_SLOT, START_SLOT)
+        .unwrap();
+
+    let duration = (END_SLOT - START_SLOT) as u128;
+    let streamed_a = sim.positions_a[&alice].get_volume() as u128 * duration;
+    let streamed_b = sim.positions_b[&bob].get_volume() as u128 * duration;
+
+    for slot in (START_SLOT..END_SLOT).step_by(crank_interval as usize) {
+        sim.crank(slot).unwrap();
+    }
+
+    let paid_b = sim.close_position_a(alice, END_SLOT).unwrap().amount_b as u128;
+    let paid_a = sim.close_position_b(bob, END_SLOT).unwrap().amount_a as u128;
+
+    // payouts round down, and truncated prices can take them just below a whole amount
+    let precision = VOLUME_PRECISION as u128;
+    assert!(
+        paid_b * precision <= streamed_b && streamed_b - paid_b * precision <= precision,
+        "alice bought {paid_b} of {}",
+        streamed_b as f64 / precision as f64
+    );
+    assert!(
+        paid_a * precision <= streamed_a && streamed_a - paid_a * precision <= precision,
+        "bob bought {paid_a} of {}",
+        streamed_a as f64 / precision as f64
+    );
+}
+
+#[test]
+fn pays_balanced_streams_within_a_base_unit() {
+    assert_exact_payouts(1_000_000_007, 3_000_000_011, 1);
+    assert_exact_payouts(1_000_000_007, 3_000_000_011, 7);
+}
+
+#[test]
+fn pays_lopsided_streams_within_a_base_unit() {
+    // a price of a millionth of a base unit per slot used to truncate away entirely
+    assert_exact_payouts(1_000_000, 1_000_000_000_000_000, 1);
+    assert_exact_payouts(1_000_000_000_000_000, 1_000_001, 3);
+}
+
+#[test]
+fn rejects_volumes_beyond_u64() {
+    let mut sim = simulator();
+
+    let deposit = sim.deposit_token_a(Pubkey::new_unique(), u64::MAX, END_SLOT, START_SLOT);
+    assert_eq!(deposit, Err(CustomErrorCode::DepositTooLarge.into()));
+}
+
+#[test]
+fn rejects_deposits_without_volume() {
+    // a single base unit over two million slots streams less than a unit of volume
+    let mut sim = Simulator::new(MarketConfig {
+        start_slot: START_SLOT,
+        end_slot_interval: 100_000,
+        time_mode: TimeMode::Slot,
+        exits_length: 100,
+        prices_length: 64,
+        decimals_a: 0,
+        decimals_b: 0,
+    })
+    .unwrap();
+
+    let deposit = sim.deposit_token_a(Pubkey::new_unique(), 1, 2_000_000, START_SLOT);
+    assert_eq!(deposit, Err(CustomErrorCode::DepositTooSmall.into()));
+    let deposit = sim.deposit_token_b(Pubkey::new_unique(), 1, 2_000_000, START_SLOT);
+    assert_eq!(deposit, Err(CustomErrorCode::DepositTooSmall.into()));
+    assert_eq!(sim.bookkeeping.dust_a, 0);
+    assert_eq!(sim.bookkeeping.dust_b, 0);
+}
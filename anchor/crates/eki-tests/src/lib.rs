@@ -0,0 +1,375 @@
+// Harness running the eki program natively inside solana-program-test, with the SPL
+// token and associated token programs it calls into. Tests drive it through the
+// eki-client instruction builders and warp the clock between steps.
+
+use std::panic::{self, AssertUnwindSafe};
+use std::process;
+
+use anchor_lang::{AccountDeserialize, AnchorDeserialize, Result as AnchorResult};
+use anchor_spl::associated_token::spl_associated_token_account::instruction::create_associated_token_account;
+use anchor_spl::token::spl_token;
+use anchor_spl::token::spl_token::solana_program::program_pack::Pack;
+use eki_client::decode::{self, AlignedData};
+use eki_client::eki::{
+    self, Bookkeeping, Exits, InvariantReport, Market, PriceCheckpoint, Prices, TimeMode,
+};
+use eki_client::{instructions, MarketKeys};
+use solana_program_test::{processor, BanksClientError, ProgramTest, ProgramTestContext};
+use solana_sdk::account_info::AccountInfo;
+use solana_sdk::clock::Clock;
+use solana_sdk::entrypoint::ProgramResult;
+use solana_sdk::instruction::Instruction;
+use solana_sdk::native_token::LAMPORTS_PER_SOL;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::{Keypair, Signer};
+use solana_sdk::system_instruction;
+use solana_sdk::transaction::Transaction;
+
+pub const DECIMALS: u8 = 6;
+
+// Anchor ties the account infos to one lifetime, which the builtin signature doesn't.
+// A panicking builtin hangs the bank instead of failing the transaction, so a panic in
+// the program aborts the process, which tests and fuzzers report as a crash.
+fn process_instruction(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    data: &[u8],
+) -> ProgramResult {
+    let accounts = unsafe { std::mem::transmute::<&[AccountInfo], &[AccountInfo]>(accounts) };
+
+    match panic::catch_unwind(AssertUnwindSafe(|| eki::entry(program_id, accounts, data))) {
+        Ok(result) => result,
+        Err(_) => process::abort(),
+    }
+}
+
+pub struct TestMarket {
+    pub context: ProgramTestContext,
+    pub keys: MarketKeys,
+    mint_authority: Keypair,
+}
+
+impl TestMarket {
+    // Market over two fresh mints with exits and prices accounts of the given lengths
+    pub async fn new(
+        start_slot: u64,
+        end_slot_interval: u64,
+        exits_length: u64,
+        prices_length: u64,
+    ) -> Self {
+        Self::create(
+            start_slot,
+            end_slot_interval,
+            exits_length,
+            prices_length,
+            false,
+        )
+        .await
+    }
+
+    // Same as new, but token A is wrapped SOL
+    pub async fn native(
+        start_slot: u64,
+        end_slot_interval: u64,
+        exits_length: u64,
+        prices_length: u64,
+    ) -> Self {
+        Self::create(
+            start_slot,
+            end_slot_interval,
+            exits_length,
+            prices_length,
+            true,
+        )
+        .await
+    }
+
+    async fn create(
+        start_slot: u64,
+        end_slot_interval: u64,
+        exits_length: u64,
+        prices_length: u64,
+        native_a: bool,
+    ) -> Self {
+        let mut program_test = ProgramTest::new("eki", eki::ID, processor!(process_instruction));
+        program_test.prefer_bpf(false);
+
+        let context = program_test.start_with_context().await;
+        let mint_authority = Keypair::new();
+        let exits = Keypair::new();
+        let prices = Keypair::new();
+
+        let mut market = Self {
+            context,
+            keys: MarketKeys {
+                token_mint_a: Pubkey::default(),
+                token_mint_b: Pubkey::default(),
+                token_program_a: spl_token::ID,
+                token_program_b: spl_token::ID,
+                exits: exits.pubkey(),
+                prices: prices.pubkey(),
+            },
+            mint_authority,
+        };
+
+        market.keys.token_mint_a = if native_a {
+            spl_token::native_mint::ID
+        } else {
+            market.create_mint().await
+        };
+        market.keys.token_mint_b = market.create_mint().await;
+
+        let payer = market.payer();
+        let rent = market.context.banks_client.get_rent().await.unwrap();
+        let exits_space = Exits::space(exits_length as usize);
+        let prices_space = Prices::space(prices_length as usize);
+
+        market
+            .process(
+                &[
+                    system_instruction::create_account(
+                        &payer,
+                        &exits.pubkey(),
+                        rent.minimum_balance(exits_space),
+                        exits_space as u64,
+                        &eki::ID,
+                    ),
+                    system_instruction::create_account(
+                        &payer,
+                        &prices.pubkey(),
+                        rent.minimum_balance(prices_space),
+                        prices_space as u64,
+                        &eki::ID,
+                    ),
+                    instructions::initialize_exits(&payer, &exits.pubkey(), &prices.pubkey()),
+                    instructions::initialize_market(
+                        &market.keys,
+                        &payer,
+                        start_slot,
+                        end_slot_interval,
+                        TimeMode::Slot,
+                        exits_length,
+                        prices_length,
+                    ),
+                ],
+                &[&exits, &prices],
+            )
+            .await
+            .unwrap();
+
+        market
+    }
+
+    pub fn payer(&self) -> Pubkey {
+        self.context.payer.pubkey()
+    }
+
+    // Sends instructions paid by the context payer and signed by the given signers
+    pub async fn process(
+        &mut self,
+        instructions: &[Instruction],
+        signers: &[&Keypair],
+    ) -> Result<(), BanksClientError> {
+        let mut all_signers = vec![&self.context.payer];
+        all_signers.extend_from_slice(signers);
+
+        let transaction = Transaction::new_signed_with_payer(
+            instructions,
+            Some(&self.context.payer.pubkey()),
+            &all_signers,
+            self.context.last_blockhash,
+        );
+
+        self.context
+            .banks_client
+            .process_transaction(transaction)
+            .await
+    }
+
+    // Runs verify_invariants and decodes the report from the return data
+    pub async fn verify_invariants(&mut self) -> Result<InvariantReport, BanksClientError> {
+        let transaction = Transaction::new_signed_with_payer(
+            &[instructions::verify_invariants(&self.keys, &self.payer())],
+            Some(&self.context.payer.pubkey()),
+            &[&self.context.payer],
+            self.context.last_blockhash,
+        );
+
+        let result = self
+            .context
+            .banks_client
+            .process_transaction_with_metadata(transaction)
+            .await?;
+        result.result?;
+
+        let return_data = result.metadata.unwrap().return_data.unwrap();
+        Ok(InvariantReport::deserialize(&mut return_data.data.as_slice()).unwrap())
+    }
+
+    pub async fn slot(&mut self) -> u64 {
+        let clock: Clock = self.context.banks_client.get_sysvar().await.unwrap();
+        clock.slot
+    }
+
+    // Warping records a new blockhash, so transactions repeated after it aren't duplicates
+    pub async fn warp_to_slot(&mut self, slot: u64) {
+        self.context.warp_to_slot(slot).unwrap();
+        self.context.last_blockhash = self
+            .context
+            .banks_client
+            .get_latest_blockhash()
+            .await
+            .unwrap();
+    }
+
+    async fn create_mint(&mut self) -> Pubkey {
+        let mint = Keypair::new();
+        let payer = self.payer();
+        let rent = self.context.banks_client.get_rent().await.unwrap();
+
+        self.process(
+            &[
+                system_instruction::create_account(
+                    &payer,
+                    &mint.pubkey(),
+                    rent.minimum_balance(spl_token::state::Mint::LEN),
+                    spl_token::state::Mint::LEN as u64,
+                    &spl_token::ID,
+                ),
+                spl_token::instruction::initialize_mint2(
+                    &spl_token::ID,
+                    &mint.pubkey(),
+                    &self.mint_authority.pubkey(),
+                    None,
+                    DECIMALS,
+                )
+                .unwrap(),
+            ],
+            &[&mint],
+        )
+        .await
+        .unwrap();
+
+        mint.pubkey()
+    }
+
+    // New user with SOL for fees and rent, and token accounts holding the given amounts
+    pub async fn user(&mut self, amount_a: u64, amount_b: u64) -> Keypair {
+        let user = Keypair::new();
+        let payer = self.payer();
+        let authority = self.mint_authority.insecure_clone();
+        let mut signers = vec![];
+
+        let mut instructions = vec![system_instruction::transfer(
+            &payer,
+            &user.pubkey(),
+            10 * LAMPORTS_PER_SOL,
+        )];
+        for (mint, amount) in [
+            (self.keys.token_mint_a, amount_a),
+            (self.keys.token_mint_b, amount_b),
+        ] {
+            let token_account =
+                eki_client::pda::associated_token_account(&user.pubkey(), &mint, &spl_token::ID);
+
+            instructions.push(create_associated_token_account(
+                &payer,
+                &user.pubkey(),
+                &mint,
+                &spl_token::ID,
+            ));
+            if amount > 0 && mint == spl_token::native_mint::ID {
+                instructions.push(system_instruction::transfer(&payer, &token_account, amount));
+                instructions.push(
+                    spl_token::instruction::sync_native(&spl_token::ID, &token_account).unwrap(),
+                );
+            } else if amount > 0 {
+                instructions.push(
+                    spl_token::instruction::mint_to(
+                        &spl_token::ID,
+                        &mint,
+                        &token_account,
+                        &authority.pubkey(),
+                        &[],
+                        amount,
+                    )
+                    .unwrap(),
+                );
+                signers = vec![&authority];
+            }
+        }
+
+        self.process(&instructions, &signers).await.unwrap();
+        user
+    }
+
+    pub async fn balance(&mut self, token_account: Pubkey) -> u64 {
+        match self
+            .context
+            .banks_client
+            .get_account(token_account)
+            .await
+            .unwrap()
+        {
+            Some(account) => {
+                spl_token::state::Account::unpack(&account.data)
+                    .unwrap()
+                    .amount
+            }
+            None => 0,
+        }
+    }
+
+    pub async fn lamports(&mut self, address: &Pubkey) -> u64 {
+        self.context
+            .banks_client
+            .get_balance(*address)
+            .await
+            .unwrap()
+    }
+
+    pub async fn balance_a(&mut self, owner: &Pubkey) -> u64 {
+        self.balance(self.keys.token_account_a(owner)).await
+    }
+
+    pub async fn balance_b(&mut self, owner: &Pubkey) -> u64 {
+        self.balance(self.keys.token_account_b(owner)).await
+    }
+
+    async fn data(&mut self, address: Pubkey) -> Option<Vec<u8>> {
+        self.context
+            .banks_client
+            .get_account(address)
+            .await
+            .unwrap()
+            .map(|account| account.data)
+    }
+
+    pub async fn account<T: AccountDeserialize>(&mut self, address: Pubkey) -> AnchorResult<T> {
+        let data = self.data(address).await.unwrap_or_default();
+        decode::account(&data)
+    }
+
+    pub async fn market(&mut self) -> Market {
+        self.account(self.keys.market()).await.unwrap()
+    }
+
+    pub async fn bookkeeping(&mut self) -> Bookkeeping {
+        self.account(self.keys.bookkeeping()).await.unwrap()
+    }
+
+    // Header and both exit volumes of a ring buffer index
+    pub async fn exits_bucket(&mut self, index: usize) -> (Exits, u64, u64) {
+        let mut data = AlignedData::new(&self.data(self.keys.exits).await.unwrap());
+        let exits = decode::exits(data.as_bytes_mut()).unwrap();
+
+        (*exits, exits.token_a[index], exits.token_b[index])
+    }
+
+    pub async fn checkpoint(&mut self, bucket: u64) -> Option<PriceCheckpoint> {
+        let mut data = AlignedData::new(&self.data(self.keys.prices).await.unwrap());
+        let prices = decode::prices(data.as_bytes_mut()).unwrap();
+
+        decode::checkpoint(&prices, bucket)
+    }
+}
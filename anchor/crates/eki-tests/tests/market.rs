@@ -0,0 +1,979 @@
+use eki_client::eki::error::CustomErrorCode;
+use eki_client::eki::{
+    PositionA, TimeMode, DUST_PRECISION, FILL_PRECISION, MINIMUM_DEPOSIT_AMOUNT,
+    MINIMUM_EXITS_HORIZON_SECONDS, MINIMUM_EXITS_LENGTH, VOLUME_PRECISION,
+};
+use eki_client::instructions;
+use eki_tests::{TestMarket, DECIMALS};
+use solana_program_test::BanksClientError;
+use solana_sdk::instruction::InstructionError;
+use solana_sdk::native_token::LAMPORTS_PER_SOL;
+use solana_sdk::signature::Signer;
+use solana_sdk::system_instruction;
+use solana_sdk::transaction::TransactionError;
+
+const START_SLOT: u64 = 100;
+const PRICES_LENGTH: u64 = 16;
+
+// Whole tokens in base units
+fn tokens(amount: u64) -> u64 {
+    amount * 10u64.pow(DECIMALS as u32)
+}
+
+// Smallest ring buffer covering the exits horizon at the given interval
+fn exits_length(end_slot_interval: u64) -> u64 {
+    let horizon = TimeMode::Slot.duration_from_seconds(MINIMUM_EXITS_HORIZON_SECONDS);
+
+    horizon
+        .div_ceil(end_slot_interval)
+        .max(MINIMUM_EXITS_LENGTH)
+}
+
+// Program error a transaction failed with
+fn custom_error(result: Result<(), BanksClientError>) -> Option<u32> {
+    match result {
+        Err(BanksClientError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(code),
+        ))) => Some(code),
+        _ => None,
+    }
+}
+
+async fn market(end_slot_interval: u64) -> TestMarket {
+    TestMarket::new(
+        START_SLOT,
+        end_slot_interval,
+        exits_length(end_slot_interval),
+        PRICES_LENGTH,
+    )
+    .await
+}
+
+#[tokio::test]
+async fn trades_both_sides() {
+    let mut market = market(100).await;
+    let keys = market.keys;
+
+    // A sells 1 token per slot until 1100, B sells 4 tokens per slot until 600
+    let alice = market.user(tokens(1000), 0).await;
+    let bob = market.user(0, tokens(2000)).await;
+    market
+        .process(
+            &[instructions::deposit_token_a(
+                &keys,
+                &alice.pubkey(),
+                Some(keys.token_account_a(&alice.pubkey())),
+                tokens(1000),
+                1100,
+            )],
+            &[&alice],
+        )
+        .await
+        .unwrap();
+    market
+        .process(
+            &[instructions::deposit_token_b(
+                &keys,
+                &bob.pubkey(),
+                Some(keys.token_account_b(&bob.pubkey())),
+                tokens(2000),
+                600,
+            )],
+            &[&bob],
+        )
+        .await
+        .unwrap();
+
+    let deposited = market.market().await;
+    assert_eq!(deposited.token_a_volume, VOLUME_PRECISION * tokens(1));
+    assert_eq!(deposited.token_b_volume, VOLUME_PRECISION * tokens(4));
+
+    market.warp_to_slot(300).await;
+    market
+        .process(
+            &[instructions::withdraw_swapped_token_b(
+                &keys,
+                &alice.pubkey(),
+                None,
+                false,
+            )],
+            &[&alice],
+        )
+        .await
+        .unwrap();
+    market
+        .process(
+            &[instructions::withdraw_swapped_token_a(
+                &keys,
+                &bob.pubkey(),
+                None,
+                false,
+            )],
+            &[&bob],
+        )
+        .await
+        .unwrap();
+    assert_eq!(market.balance_b(&alice.pubkey()).await, tokens(800));
+    assert_eq!(market.balance_a(&bob.pubkey()).await, tokens(200));
+
+    // B has streamed everything by its end slot
+    market.warp_to_slot(700).await;
+    market
+        .process(
+            &[instructions::close_position_b(
+                &keys,
+                &bob.pubkey(),
+                None,
+                false,
+            )],
+            &[&bob],
+        )
+        .await
+        .unwrap();
+    assert_eq!(market.balance_a(&bob.pubkey()).await, tokens(500));
+    assert_eq!(market.balance_b(&bob.pubkey()).await, 0);
+
+    // A finds no counterparty after 600 and gets that part of the deposit back
+    market.warp_to_slot(1200).await;
+    market
+        .process(
+            &[instructions::close_position_a(
+                &keys,
+                &alice.pubkey(),
+                None,
+                false,
+            )],
+            &[&alice],
+        )
+        .await
+        .unwrap();
+    assert_eq!(market.balance_a(&alice.pubkey()).await, tokens(500));
+    assert_eq!(market.balance_b(&alice.pubkey()).await, tokens(2000));
+
+    assert_eq!(market.balance(keys.treasury_a()).await, 0);
+    assert_eq!(market.balance(keys.treasury_b()).await, 0);
+    let closed = market.market().await;
+    assert_eq!(closed.token_a_volume, 0);
+    assert_eq!(closed.token_b_volume, 0);
+}
+
+#[tokio::test]
+async fn refunds_slots_without_counterparty() {
+    let mut market = market(100).await;
+    let keys = market.keys;
+
+    let alice = market.user(tokens(1000), 0).await;
+    let bob = market.user(0, tokens(500)).await;
+    market
+        .process(
+            &[instructions::deposit_token_a(
+                &keys,
+                &alice.pubkey(),
+                Some(keys.token_account_a(&alice.pubkey())),
+                tokens(1000),
+                1100,
+            )],
+            &[&alice],
+        )
+        .await
+        .unwrap();
+
+    // B joins halfway at the same rate, so the first half of A's stream doesn't trade
+    market.warp_to_slot(600).await;
+    market
+        .process(
+            &[instructions::deposit_token_b(
+                &keys,
+                &bob.pubkey(),
+                Some(keys.token_account_b(&bob.pubkey())),
+                tokens(500),
+                1100,
+            )],
+            &[&bob],
+        )
+        .await
+        .unwrap();
+
+    market.warp_to_slot(1200).await;
+    market
+        .process(
+            &[instructions::close_position_a(
+                &keys,
+                &alice.pubkey(),
+                None,
+                false,
+            )],
+            &[&alice],
+        )
+        .await
+        .unwrap();
+    market
+        .process(
+            &[instructions::close_position_b(
+                &keys,
+                &bob.pubkey(),
+                None,
+                false,
+            )],
+            &[&bob],
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(market.balance_a(&alice.pubkey()).await, tokens(500));
+    assert_eq!(market.balance_b(&alice.pubkey()).await, tokens(500));
+    assert_eq!(market.balance_a(&bob.pubkey()).await, tokens(500));
+    assert_eq!(market.balance_b(&bob.pubkey()).await, 0);
+    // 100..600 before B joined
+    assert_eq!(market.bookkeeping().await.unfilled_a, 500 * FILL_PRECISION);
+}
+
+// Withdrawing after the end slot, once the crank has moved the bookkeeping past it, used
+// to take the bookkeeping back to the end slot and abort on the underflow
+#[tokio::test]
+async fn pays_withdrawals_after_the_end_slot_from_the_checkpoint() {
+    let mut market = market(100).await;
+    let keys = market.keys;
+
+    // alice and carol sell 1 token A per slot each, bob 1 token B until 200. Carol's exit
+    // at 300 has the crank move the bookkeeping past bob's end slot.
+    let alice = market.user(tokens(1000), 0).await;
+    let bob = market.user(0, tokens(100)).await;
+    let carol = market.user(tokens(200), 0).await;
+    market
+        .process(
+            &[
+                instructions::deposit_token_a(
+                    &keys,
+                    &alice.pubkey(),
+                    Some(keys.token_account_a(&alice.pubkey())),
+                    tokens(1000),
+                    1100,
+                ),
+                instructions::deposit_token_b(
+                    &keys,
+                    &bob.pubkey(),
+                    Some(keys.token_account_b(&bob.pubkey())),
+                    tokens(100),
+                    200,
+                ),
+                instructions::deposit_token_a(
+                    &keys,
+                    &carol.pubkey(),
+                    Some(keys.token_account_a(&carol.pubkey())),
+                    tokens(200),
+                    300,
+                ),
+            ],
+            &[&alice, &bob, &carol],
+        )
+        .await
+        .unwrap();
+
+    market.warp_to_slot(350).await;
+    let payer = market.payer();
+    market
+        .process(&[instructions::update_bookkeeping(&keys, &payer)], &[])
+        .await
+        .unwrap();
+    assert_eq!(market.bookkeeping().await.last_slot, 300);
+
+    market
+        .process(
+            &[instructions::withdraw_swapped_token_a(
+                &keys,
+                &bob.pubkey(),
+                None,
+                false,
+            )],
+            &[&bob],
+        )
+        .await
+        .unwrap();
+    assert_eq!(market.balance_a(&bob.pubkey()).await, tokens(200));
+    // the checkpoint stays until the position is closed
+    assert!(market.checkpoint(1).await.is_some());
+
+    market
+        .process(
+            &[instructions::close_position_b(
+                &keys,
+                &bob.pubkey(),
+                None,
+                false,
+            )],
+            &[&bob],
+        )
+        .await
+        .unwrap();
+    assert_eq!(market.balance_a(&bob.pubkey()).await, tokens(200));
+    assert_eq!(market.balance_b(&bob.pubkey()).await, 0);
+    assert!(market.checkpoint(1).await.is_none());
+}
+
+#[tokio::test]
+async fn refunds_lone_stream_closed_early() {
+    let mut market = market(100).await;
+    let keys = market.keys;
+
+    let amount = tokens(MINIMUM_DEPOSIT_AMOUNT);
+    let alice = market.user(amount, 0).await;
+    market
+        .process(
+            &[instructions::deposit_token_a(
+                &keys,
+                &alice.pubkey(),
+                Some(keys.token_account_a(&alice.pubkey())),
+                amount,
+                1100,
+            )],
+            &[&alice],
+        )
+        .await
+        .unwrap();
+
+    market.warp_to_slot(600).await;
+    market
+        .process(
+            &[instructions::close_position_a(
+                &keys,
+                &alice.pubkey(),
+                None,
+                false,
+            )],
+            &[&alice],
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(market.balance_a(&alice.pubkey()).await, amount);
+    assert_eq!(market.balance_b(&alice.pubkey()).await, 0);
+    assert_eq!(market.market().await.token_a_volume, 0);
+}
+
+#[tokio::test]
+async fn refunds_position_closed_before_the_market_starts() {
+    let mut market = market(100).await;
+    let keys = market.keys;
+
+    let amount = tokens(MINIMUM_DEPOSIT_AMOUNT);
+    let alice = market.user(amount, 0).await;
+    market
+        .process(
+            &[instructions::deposit_token_a(
+                &keys,
+                &alice.pubkey(),
+                Some(keys.token_account_a(&alice.pubkey())),
+                amount,
+                1100,
+            )],
+            &[&alice],
+        )
+        .await
+        .unwrap();
+
+    market.warp_to_slot(START_SLOT - 10).await;
+    market
+        .process(
+            &[instructions::close_position_a(
+                &keys,
+                &alice.pubkey(),
+                None,
+                false,
+            )],
+            &[&alice],
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(market.balance_a(&alice.pubkey()).await, amount);
+    assert_eq!(market.market().await.token_a_volume, 0);
+    assert_eq!(market.bookkeeping().await.last_slot, START_SLOT);
+}
+
+#[tokio::test]
+async fn reuses_exits_buckets_after_wrapping() {
+    let end_slot_interval = 3000;
+    let length = exits_length(end_slot_interval);
+    let mut market = market(end_slot_interval).await;
+    let keys = market.keys;
+
+    // every round ends two buckets after it starts, so four rounds run the ring buffer
+    // around more than once
+    let round_slots = 2 * end_slot_interval;
+    let amount = tokens(1000);
+
+    for round in 0..4 {
+        let round_start = round * round_slots + START_SLOT;
+        let end_slot = (round + 1) * round_slots;
+
+        market.warp_to_slot(round_start).await;
+        let alice = market.user(amount, 0).await;
+        let bob = market.user(0, amount).await;
+        market
+            .process(
+                &[instructions::deposit_token_a(
+                    &keys,
+                    &alice.pubkey(),
+                    Some(keys.token_account_a(&alice.pubkey())),
+                    amount,
+                    end_slot,
+                )],
+                &[&alice],
+            )
+            .await
+            .unwrap();
+        market
+            .process(
+                &[instructions::deposit_token_b(
+                    &keys,
+                    &bob.pubkey(),
+                    Some(keys.token_account_b(&bob.pubkey())),
+                    amount,
+                    end_slot,
+                )],
+                &[&bob],
+            )
+            .await
+            .unwrap();
+
+        let duration = end_slot - round_start;
+        let volume = VOLUME_PRECISION * amount / duration;
+        let bucket = end_slot / end_slot_interval;
+        let (exits, exiting_a, exiting_b) = market.exits_bucket((bucket % length) as usize).await;
+        assert_eq!(exits.length, length);
+        assert_eq!(exiting_a, volume);
+        assert_eq!(exiting_b, volume);
+
+        market.warp_to_slot(end_slot + START_SLOT / 2).await;
+        market
+            .process(
+                &[instructions::close_position_a(
+                    &keys,
+                    &alice.pubkey(),
+                    None,
+                    false,
+                )],
+                &[&alice],
+            )
+            .await
+            .unwrap();
+        market
+            .process(
+                &[instructions::close_position_b(
+                    &keys,
+                    &bob.pubkey(),
+                    None,
+                    false,
+                )],
+                &[&bob],
+            )
+            .await
+            .unwrap();
+
+        // both sides stream at the same rate, so each receives its volume over the duration
+        let received = volume * duration / VOLUME_PRECISION;
+        assert_eq!(market.balance_b(&alice.pubkey()).await, received);
+        assert_eq!(market.balance_a(&bob.pubkey()).await, received);
+
+        let (exits, exiting_a, exiting_b) = market.exits_bucket((bucket % length) as usize).await;
+        assert_eq!(exits.pointer, bucket);
+        assert_eq!(exiting_a, 0);
+        assert_eq!(exiting_b, 0);
+    }
+
+    let (exits, _, _) = market.exits_bucket(0).await;
+    assert!(exits.pointer > 2 * length);
+}
+
+#[tokio::test]
+async fn verifies_invariants() {
+    let mut market = market(100).await;
+    let keys = market.keys;
+
+    let alice = market.user(tokens(1000), 0).await;
+    let bob = market.user(0, tokens(2000)).await;
+    market
+        .process(
+            &[
+                instructions::deposit_token_a(
+                    &keys,
+                    &alice.pubkey(),
+                    Some(keys.token_account_a(&alice.pubkey())),
+                    tokens(1000),
+                    1100,
+                ),
+                instructions::deposit_token_b(
+                    &keys,
+                    &bob.pubkey(),
+                    Some(keys.token_account_b(&bob.pubkey())),
+                    tokens(2000),
+                    600,
+                ),
+            ],
+            &[&alice, &bob],
+        )
+        .await
+        .unwrap();
+
+    // nothing has streamed before the start, so the whole deposits are owed
+    market.warp_to_slot(START_SLOT - 10).await;
+    let report = market.verify_invariants().await.unwrap();
+    assert_eq!(report.required_a, tokens(1000));
+    assert_eq!(report.required_b, tokens(2000));
+
+    // A has 800 slots left at 1 token per slot, B 300 slots at 4 tokens per slot
+    market.warp_to_slot(300).await;
+    let report = market.verify_invariants().await.unwrap();
+    assert_eq!(report.slot, 300);
+    assert_eq!(report.exits_volume_a, report.token_a_volume);
+    assert_eq!(report.exits_volume_b, report.token_b_volume);
+    assert_eq!(report.treasury_a, tokens(1000));
+    assert_eq!(report.treasury_b, tokens(2000));
+    assert_eq!(report.required_a, tokens(800));
+    assert_eq!(report.required_b, tokens(1200));
+
+    // B's bucket has been processed, so only A's stream is left
+    market.warp_to_slot(700).await;
+    let report = market.verify_invariants().await.unwrap();
+    assert_eq!(report.exits_volume_a, VOLUME_PRECISION * tokens(1));
+    assert_eq!(report.exits_volume_b, 0);
+    assert_eq!(report.token_b_volume, 0);
+    assert_eq!(report.required_a, tokens(400));
+    assert_eq!(report.required_b, 0);
+}
+
+#[tokio::test]
+async fn reduces_position_and_keeps_streaming() {
+    let mut market = market(100).await;
+    let keys = market.keys;
+
+    // A sells 1 token per slot and B 2 tokens per slot, both until 1100
+    let alice = market.user(tokens(1000), 0).await;
+    let bob = market.user(0, tokens(2000)).await;
+    market
+        .process(
+            &[
+                instructions::deposit_token_a(
+                    &keys,
+                    &alice.pubkey(),
+                    Some(keys.token_account_a(&alice.pubkey())),
+                    tokens(1000),
+                    1100,
+                ),
+                instructions::deposit_token_b(
+                    &keys,
+                    &bob.pubkey(),
+                    Some(keys.token_account_b(&bob.pubkey())),
+                    tokens(2000),
+                    1100,
+                ),
+            ],
+            &[&alice, &bob],
+        )
+        .await
+        .unwrap();
+
+    // A has 800 tokens left to stream and has to keep some of them
+    market.warp_to_slot(300).await;
+    let reduce = market
+        .process(
+            &[instructions::reduce_position_a(
+                &keys,
+                &alice.pubkey(),
+                tokens(800),
+                false,
+            )],
+            &[&alice],
+        )
+        .await;
+    assert_eq!(
+        custom_error(reduce),
+        Some(CustomErrorCode::InvalidReduceAmount.into())
+    );
+
+    // taking out half of it pays what was swapped so far and halves the volume
+    market
+        .process(
+            &[instructions::reduce_position_a(
+                &keys,
+                &alice.pubkey(),
+                tokens(400),
+                false,
+            )],
+            &[&alice],
+        )
+        .await
+        .unwrap();
+    assert_eq!(market.balance_a(&alice.pubkey()).await, tokens(400));
+    assert_eq!(market.balance_b(&alice.pubkey()).await, tokens(400));
+
+    // the position keeps what it filled before the reduce in its totals
+    let position_a = eki_client::pda::position_a(&keys.market(), &alice.pubkey()).0;
+    let position = market.account::<PositionA>(position_a).await.unwrap();
+    assert_eq!(position.filled, tokens(200));
+    assert_eq!(position.refunded, 0);
+
+    let report = market.verify_invariants().await.unwrap();
+    assert_eq!(report.token_a_volume, VOLUME_PRECISION * tokens(1) / 2);
+    assert_eq!(report.exits_volume_a, report.token_a_volume);
+    assert_eq!(report.required_a, tokens(400));
+
+    // A now buys 4 tokens of B per token of A until the end slot
+    market.warp_to_slot(1200).await;
+    let reduce = market
+        .process(
+            &[instructions::reduce_position_a(
+                &keys,
+                &alice.pubkey(),
+                tokens(1),
+                false,
+            )],
+            &[&alice],
+        )
+        .await;
+    assert_eq!(
+        custom_error(reduce),
+        Some(CustomErrorCode::EndSlotAlreadyPassed.into())
+    );
+
+    market
+        .process(
+            &[
+                instructions::close_position_a(&keys, &alice.pubkey(), None, false),
+                instructions::close_position_b(&keys, &bob.pubkey(), None, false),
+            ],
+            &[&alice, &bob],
+        )
+        .await
+        .unwrap();
+    assert_eq!(market.balance_a(&alice.pubkey()).await, tokens(400));
+    assert_eq!(market.balance_b(&alice.pubkey()).await, tokens(2000));
+    assert_eq!(market.balance_a(&bob.pubkey()).await, tokens(600));
+    assert_eq!(market.balance_b(&bob.pubkey()).await, 0);
+}
+
+#[tokio::test]
+async fn sweeps_dust_of_closed_market() {
+    let mut market = market(100).await;
+    let keys = market.keys;
+    let authority = market.payer();
+
+    // odd amounts over an odd duration don't divide into whole volumes and payouts
+    market.warp_to_slot(133).await;
+    let alice = market.user(tokens(1000) + 7, 0).await;
+    let bob = market.user(0, tokens(3000) + 11).await;
+    market
+        .process(
+            &[
+                instructions::deposit_token_a(
+                    &keys,
+                    &alice.pubkey(),
+                    Some(keys.token_account_a(&alice.pubkey())),
+                    tokens(1000) + 7,
+                    1100,
+                ),
+                instructions::deposit_token_b(
+                    &keys,
+                    &bob.pubkey(),
+                    Some(keys.token_account_b(&bob.pubkey())),
+                    tokens(3000) + 11,
+                    1100,
+                ),
+            ],
+            &[&alice, &bob],
+        )
+        .await
+        .unwrap();
+
+    let sweep = market
+        .process(&[instructions::sweep_dust(&keys, &authority)], &[])
+        .await;
+    assert_eq!(
+        custom_error(sweep),
+        Some(CustomErrorCode::MarketNotClosed.into())
+    );
+
+    market.warp_to_slot(1200).await;
+    market
+        .process(
+            &[
+                instructions::close_position_a(&keys, &alice.pubkey(), None, false),
+                instructions::close_position_b(&keys, &bob.pubkey(), None, false),
+            ],
+            &[&alice, &bob],
+        )
+        .await
+        .unwrap();
+
+    // all that's left is dust
+    let bookkeeping = market.bookkeeping().await;
+    let treasury_a = market.balance(keys.treasury_a()).await;
+    let treasury_b = market.balance(keys.treasury_b()).await;
+    assert_eq!(bookkeeping.dust_a, treasury_a as u128 * DUST_PRECISION);
+    assert_eq!(bookkeeping.dust_b, treasury_b as u128 * DUST_PRECISION);
+
+    market
+        .process(&[instructions::close_market(&keys, &authority)], &[])
+        .await
+        .unwrap();
+
+    let carol = market.user(tokens(10), 0).await;
+    let deposit = market
+        .process(
+            &[instructions::deposit_token_a(
+                &keys,
+                &carol.pubkey(),
+                Some(keys.token_account_a(&carol.pubkey())),
+                tokens(10),
+                2000,
+            )],
+            &[&carol],
+        )
+        .await;
+    assert_eq!(
+        custom_error(deposit),
+        Some(CustomErrorCode::MarketClosed.into())
+    );
+
+    let sweep = market
+        .process(&[instructions::sweep_dust(&keys, &bob.pubkey())], &[&bob])
+        .await;
+    assert_eq!(
+        custom_error(sweep),
+        Some(CustomErrorCode::InvalidAuthority.into())
+    );
+
+    market
+        .process(&[instructions::sweep_dust(&keys, &authority)], &[])
+        .await
+        .unwrap();
+    assert_eq!(market.balance_a(&authority).await, treasury_a);
+    assert_eq!(market.balance_b(&authority).await, treasury_b);
+    assert_eq!(market.balance(keys.treasury_a()).await, 0);
+    assert_eq!(market.balance(keys.treasury_b()).await, 0);
+}
+
+#[tokio::test]
+async fn unwraps_only_the_payout() {
+    let mut market = TestMarket::native(START_SLOT, 100, exits_length(100), PRICES_LENGTH).await;
+    let keys = market.keys;
+
+    // bob already holds some wrapped SOL, which unwrapping the payout must leave alone
+    let alice = market.user(tokens(1000), 0).await;
+    let bob = market.user(tokens(5), tokens(1000)).await;
+    market
+        .process(
+            &[
+                instructions::deposit_token_a(
+                    &keys,
+                    &alice.pubkey(),
+                    Some(keys.token_account_a(&alice.pubkey())),
+                    tokens(1000),
+                    1100,
+                ),
+                instructions::deposit_token_b(
+                    &keys,
+                    &bob.pubkey(),
+                    Some(keys.token_account_b(&bob.pubkey())),
+                    tokens(1000),
+                    1100,
+                ),
+            ],
+            &[&alice, &bob],
+        )
+        .await
+        .unwrap();
+
+    market.warp_to_slot(1200).await;
+    let position_b = eki_client::pda::position_b(&keys.market(), &bob.pubkey()).0;
+    let position_rent = market.lamports(&position_b).await;
+    let lamports = market.lamports(&bob.pubkey()).await;
+    market
+        .process(
+            &[instructions::close_position_b(
+                &keys,
+                &bob.pubkey(),
+                None,
+                true,
+            )],
+            &[&bob],
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(
+        market.lamports(&bob.pubkey()).await - lamports,
+        tokens(1000) + position_rent
+    );
+    assert_eq!(market.balance_a(&bob.pubkey()).await, tokens(5));
+    let unwrap_account = eki_client::pda::unwrap_account(&keys.market(), &bob.pubkey()).0;
+    assert_eq!(market.lamports(&unwrap_account).await, 0);
+}
+
+#[tokio::test]
+async fn unwraps_into_a_prefunded_account() {
+    let mut market = TestMarket::native(START_SLOT, 100, exits_length(100), PRICES_LENGTH).await;
+    let keys = market.keys;
+
+    let alice = market.user(tokens(1000), 0).await;
+    let bob = market.user(0, tokens(1000)).await;
+    market
+        .process(
+            &[
+                instructions::deposit_token_a(
+                    &keys,
+                    &alice.pubkey(),
+                    Some(keys.token_account_a(&alice.pubkey())),
+                    tokens(1000),
+                    1100,
+                ),
+                instructions::deposit_token_b(
+                    &keys,
+                    &bob.pubkey(),
+                    Some(keys.token_account_b(&bob.pubkey())),
+                    tokens(1000),
+                    1100,
+                ),
+            ],
+            &[&alice, &bob],
+        )
+        .await
+        .unwrap();
+
+    // lamports sent to the unwrap account ahead of time used to block every unwrap
+    let unwrap_account = eki_client::pda::unwrap_account(&keys.market(), &bob.pubkey()).0;
+    let gift = LAMPORTS_PER_SOL / 1000;
+    let payer = market.payer();
+    market
+        .process(
+            &[system_instruction::transfer(&payer, &unwrap_account, gift)],
+            &[],
+        )
+        .await
+        .unwrap();
+
+    market.warp_to_slot(1200).await;
+    let position_b = eki_client::pda::position_b(&keys.market(), &bob.pubkey()).0;
+    let position_rent = market.lamports(&position_b).await;
+    let lamports = market.lamports(&bob.pubkey()).await;
+    market
+        .process(
+            &[instructions::close_position_b(
+                &keys,
+                &bob.pubkey(),
+                None,
+                true,
+            )],
+            &[&bob],
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(
+        market.lamports(&bob.pubkey()).await - lamports,
+        tokens(1000) + position_rent + gift
+    );
+    assert_eq!(market.lamports(&unwrap_account).await, 0);
+}
+
+#[tokio::test]
+async fn archives_checkpoints_to_unblock_a_full_prices_table() {
+    let mut market = TestMarket::new(START_SLOT, 100, exits_length(100), 2).await;
+    let keys = market.keys;
+
+    // three positions ending in different buckets need three checkpoints
+    let alice = market.user(tokens(200), 0).await;
+    let bob = market.user(0, tokens(300)).await;
+    let carol = market.user(tokens(400), 0).await;
+    let dave = market.user(0, tokens(1000)).await;
+    market
+        .process(
+            &[
+                instructions::deposit_token_a(
+                    &keys,
+                    &alice.pubkey(),
+                    Some(keys.token_account_a(&alice.pubkey())),
+                    tokens(200),
+                    300,
+                ),
+                instructions::deposit_token_b(
+                    &keys,
+                    &bob.pubkey(),
+                    Some(keys.token_account_b(&bob.pubkey())),
+                    tokens(300),
+                    400,
+                ),
+                instructions::deposit_token_a(
+                    &keys,
+                    &carol.pubkey(),
+                    Some(keys.token_account_a(&carol.pubkey())),
+                    tokens(400),
+                    500,
+                ),
+                instructions::deposit_token_b(
+                    &keys,
+                    &dave.pubkey(),
+                    Some(keys.token_account_b(&dave.pubkey())),
+                    tokens(1000),
+                    1100,
+                ),
+            ],
+            &[&alice, &bob, &carol, &dave],
+        )
+        .await
+        .unwrap();
+
+    market.warp_to_slot(600).await;
+    let withdraw = instructions::withdraw_swapped_token_a(&keys, &dave.pubkey(), None, false);
+    let result = market
+        .process(std::slice::from_ref(&withdraw), &[&dave])
+        .await;
+    assert_eq!(
+        custom_error(result),
+        Some(CustomErrorCode::PricesFull.into())
+    );
+
+    // the crank processes what fits, the rest once the table has been archived
+    let payer = market.payer();
+    market
+        .process(&[instructions::update_bookkeeping(&keys, &payer)], &[])
+        .await
+        .unwrap();
+    let (exits, _, _) = market.exits_bucket(0).await;
+    assert_eq!(exits.pointer, 3);
+    market
+        .process(
+            &[
+                instructions::archive_prices(&keys, &payer, 0, 0),
+                instructions::update_bookkeeping(&keys, &payer),
+            ],
+            &[],
+        )
+        .await
+        .unwrap();
+    assert!(market.checkpoint(2).await.is_none());
+    assert!(market.checkpoint(4).await.is_some());
+
+    // a new blockhash, so the same withdrawal isn't taken for the failed one
+    market.warp_to_slot(650).await;
+    market.process(&[withdraw], &[&dave]).await.unwrap();
+    assert_eq!(market.balance_a(&dave.pubkey()).await, tokens(350));
+
+    let archive = eki_client::pda::price_archive(&keys.market(), 0, 0).0;
+    market
+        .process(
+            &[
+                instructions::close_position_a(&keys, &alice.pubkey(), Some(archive), false),
+                instructions::close_position_b(&keys, &bob.pubkey(), Some(archive), false),
+                instructions::close_position_a(&keys, &carol.pubkey(), None, false),
+            ],
+            &[&alice, &bob, &carol],
+        )
+        .await
+        .unwrap();
+    assert_eq!(market.balance_b(&alice.pubkey()).await, tokens(200));
+    assert_eq!(market.balance_a(&bob.pubkey()).await, tokens(250));
+    assert_eq!(market.balance_b(&carol.pubkey()).await, tokens(500));
+}
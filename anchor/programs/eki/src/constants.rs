@@ -5,14 +5,33 @@ pub const SEED: &str = "anchor";
 
 pub const TREASURY_A_SEED: &str = "treasury_a";
 pub const TREASURY_B_SEED: &str = "treasury_b";
+pub const LP_MINT_SEED: &str = "lp_mint";
+pub const UNWRAP_SEED: &str = "unwrap";
 
 pub const ANCHOR_DISCRIMINATOR: usize = 8;
 pub const MINIMUM_TRADE_DURATION_SECONDS: u64 = 10;
+pub const SLOT_DURATION_MS: u64 = 400;
+pub const MINIMUM_EXITS_HORIZON_SECONDS: u64 = 60 * 60;
 pub const MINIMUM_DEPOSIT_AMOUNT: u64 = 1;
-pub const BOOKKEEPING_PRECISION_FACTOR: u64 = 1_000_000;
+pub const BOOKKEEPING_PRECISION_FACTOR: u128 = 1_000_000_000_000_000_000;
 pub const VOLUME_PRECISION: u64 = 1_000_000;
+pub const FILL_PRECISION: u64 = 1_000_000;
+// Payouts round down and what's left of a token is kept as dust in these units, which
+// divide the swapped and refunded amounts of positions exactly
+pub const DUST_PRECISION: u128 = VOLUME_PRECISION as u128 * BOOKKEEPING_PRECISION_FACTOR;
+// refunds scale fill shares up to the bookkeeping precision without rounding
+const _: () = assert!(
+    BOOKKEEPING_PRECISION_FACTOR / FILL_PRECISION as u128 * FILL_PRECISION as u128
+        == BOOKKEEPING_PRECISION_FACTOR
+);
+pub const LP_MINT_DECIMALS: u8 = 6;
+// Shares of the first deposit that are never minted, so the pool can't be emptied and
+// the value of a share can't be inflated to round later deposits down to nothing
+pub const MINIMUM_LIQUIDITY: u64 = 1_000;
+// Part of every instant swap input, in basis points, that stays in the pool for its
+// liquidity providers
+pub const SWAP_FEE_BPS: u64 = 30;
 
-// implementation is much easier if EXITS_LENGHT and PRICES_LENGTH are the same
-// Maybe update prices update to handle different sizes, which would allow larger EXITS_LENGTH
-pub const EXITS_LENGTH: usize = 420000;
-pub const PRICES_LENGTH: usize = 420000;
+// Exits need the current bucket and at least one ahead of it
+pub const MINIMUM_EXITS_LENGTH: u64 = 2;
+pub const MINIMUM_PRICES_LENGTH: u64 = 1;
@@ -8,12 +8,78 @@ pub enum CustomErrorCode {
     #[msg("Account is too small")]
     AccountTooSmall,
 
-    #[msg("Slot interval has to be power of 10")]
+    #[msg("Slot interval has to be positive")]
     InvalidSlotInterval,
 
     #[msg("Deposit amount is too small")]
     DepositTooSmall,
 
+    #[msg("Trade duration is shorter than the minimum")]
+    TradeDurationTooShort,
+
     #[msg("No tokens have been swapped yet")]
     NoTokensSwapped,
+
+    #[msg("Depositor token account is required for non-native mints")]
+    MissingDepositorTokenAccount,
+
+    #[msg("Only native mints can be unwrapped")]
+    NotNativeMint,
+
+    #[msg("Mint does not belong to the market")]
+    InvalidMint,
+
+    #[msg("Treasury does not belong to the market")]
+    InvalidTreasury,
+
+    #[msg("Token program does not match the market")]
+    InvalidTokenProgram,
+
+    #[msg("Exits ring buffer does not cover the minimum horizon")]
+    ExitsHorizonTooShort,
+
+    #[msg("End slot is beyond the exits ring buffer horizon")]
+    EndSlotBeyondHorizon,
+
+    #[msg("No space left for price checkpoints")]
+    PricesFull,
+
+    #[msg("Price checkpoint for the end slot is missing")]
+    MissingPriceCheckpoint,
+
+    #[msg("Ring buffer length is too small")]
+    InvalidBufferLength,
+
+    #[msg("Price checkpoints of this epoch can't be archived yet")]
+    EpochNotArchivable,
+
+    #[msg("Price archive does not belong to the market")]
+    InvalidPriceArchive,
+
+    #[msg("Not enough liquidity in the pool")]
+    InsufficientLiquidity,
+
+    #[msg("Slippage limit exceeded")]
+    SlippageExceeded,
+
+    #[msg("Market volumes don't match the exits buckets")]
+    VolumeMismatch,
+
+    #[msg("Treasury doesn't cover outstanding claims")]
+    TreasuryShortfall,
+
+    #[msg("Signer is not the market authority")]
+    InvalidAuthority,
+
+    #[msg("Market is closed")]
+    MarketClosed,
+
+    #[msg("Market is still open")]
+    MarketNotClosed,
+
+    #[msg("Deposit amount is too large for its duration")]
+    DepositTooLarge,
+
+    #[msg("Reduce amount has to leave part of the unspent deposit")]
+    InvalidReduceAmount,
 }
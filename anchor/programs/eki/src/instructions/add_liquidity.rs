@@ -0,0 +1,176 @@
+use anchor_lang::prelude::*;
+use anchor_spl::associated_token::AssociatedToken;
+use anchor_spl::token::Token;
+use anchor_spl::token_interface::{mint_to, Mint, MintTo, TokenAccount, TokenInterface};
+
+use crate::constants::*;
+use crate::error::*;
+use crate::state::*;
+
+use super::{trade_up_to, transfer_tokens};
+
+#[derive(Accounts)]
+pub struct AddLiquidity<'info> {
+    #[account(mut)]
+    pub provider: Signer<'info>,
+
+    #[account(
+      mut,
+      associated_token::mint = token_mint_a,
+      associated_token::authority = provider,
+      associated_token::token_program = token_program_a
+    )]
+    pub provider_token_account_a: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+      mut,
+      associated_token::mint = token_mint_b,
+      associated_token::authority = provider,
+      associated_token::token_program = token_program_b
+    )]
+    pub provider_token_account_b: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+      init_if_needed,
+      payer = provider,
+      associated_token::mint = lp_mint,
+      associated_token::authority = provider,
+      associated_token::token_program = lp_token_program
+    )]
+    pub provider_lp_token_account: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    pub token_mint_a: Box<InterfaceAccount<'info, Mint>>,
+
+    pub token_mint_b: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(
+      mut,
+      seeds = [LP_MINT_SEED.as_bytes(), market.key().as_ref()],
+      bump
+    )]
+    pub lp_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(
+      mut,
+      has_one = token_mint_a @ CustomErrorCode::InvalidMint,
+      has_one = token_mint_b @ CustomErrorCode::InvalidMint,
+      has_one = treasury_a @ CustomErrorCode::InvalidTreasury,
+      has_one = treasury_b @ CustomErrorCode::InvalidTreasury,
+      has_one = token_program_a @ CustomErrorCode::InvalidTokenProgram,
+      has_one = token_program_b @ CustomErrorCode::InvalidTokenProgram,
+      seeds = [Market::SEED_PREFIX.as_bytes()],
+      bump = market.bump
+    )]
+    pub market: Box<Account<'info, Market>>,
+
+    #[account(
+      mut,
+      seeds = [TREASURY_A_SEED.as_bytes(), market.key().as_ref()],
+      bump
+    )]
+    pub treasury_a: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+      mut,
+      seeds = [TREASURY_B_SEED.as_bytes(), market.key().as_ref()],
+      bump
+    )]
+    pub treasury_b: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+      mut,
+      seeds = [Bookkeeping::SEED_PREFIX.as_bytes(), market.key().as_ref()],
+      bump = bookkeeping.bump
+  )]
+    pub bookkeeping: Box<Account<'info, Bookkeeping>>,
+
+    #[account(mut)]
+    pub exits: AccountLoader<'info, Exits>,
+
+    #[account(mut)]
+    pub prices: AccountLoader<'info, Prices>,
+
+    pub token_program_a: Interface<'info, TokenInterface>,
+    pub token_program_b: Interface<'info, TokenInterface>,
+    pub lp_token_program: Program<'info, Token>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+}
+
+impl<'info> AddLiquidity<'info> {
+    // Streams have to trade against the old reserves up to now before they change
+    pub fn update_bookkeeping(&mut self, current_slot: u64) -> Result<()> {
+        let mut exits_data = self.exits.as_ref().try_borrow_mut_data()?;
+        let mut exits = ExitsBuffer::load(&mut exits_data)?;
+        let mut prices_data = self.prices.as_ref().try_borrow_mut_data()?;
+        let mut prices = PricesBuffer::load(&mut prices_data)?;
+
+        trade_up_to(
+            &mut self.market,
+            &mut self.bookkeeping,
+            &mut exits,
+            &mut prices,
+            current_slot,
+        )
+    }
+
+    pub fn add_liquidity(
+        &mut self,
+        max_amount_a: u64,
+        max_amount_b: u64,
+        min_shares: u64,
+        remaining_accounts: &[AccountInfo<'info>],
+    ) -> Result<()> {
+        let (shares, amount_a, amount_b) =
+            self.bookkeeping.pool.deposit(max_amount_a, max_amount_b)?;
+
+        if shares < min_shares {
+            return Err(CustomErrorCode::SlippageExceeded.into());
+        }
+
+        transfer_tokens(
+            &self.provider_token_account_a,
+            &self.treasury_a,
+            &amount_a,
+            &self.token_mint_a,
+            &self.provider,
+            &self.token_program_a,
+            remaining_accounts,
+        )?;
+
+        transfer_tokens(
+            &self.provider_token_account_b,
+            &self.treasury_b,
+            &amount_b,
+            &self.token_mint_b,
+            &self.provider,
+            &self.token_program_b,
+            remaining_accounts,
+        )?;
+
+        let seeds = &[Market::SEED_PREFIX.as_bytes(), &[self.market.bump]];
+        let signer_seeds = [&seeds[..]];
+
+        let mint_accounts = MintTo {
+            mint: self.lp_mint.to_account_info(),
+            to: self.provider_lp_token_account.to_account_info(),
+            authority: self.market.to_account_info(),
+        };
+
+        let cpi_context = CpiContext::new_with_signer(
+            self.lp_token_program.to_account_info(),
+            mint_accounts,
+            &signer_seeds,
+        );
+
+        mint_to(cpi_context, shares)?;
+
+        msg!(
+            "Added {} token A and {} token B for {} shares",
+            amount_a,
+            amount_b,
+            shares
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,85 @@
+use crate::constants::*;
+use crate::error::*;
+use crate::state::*;
+use anchor_lang::prelude::*;
+
+#[derive(Accounts)]
+#[instruction(epoch: u64, page: u64)]
+pub struct ArchivePrices<'info> {
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    #[account(
+      seeds = [Market::SEED_PREFIX.as_bytes()],
+      bump = market.bump
+    )]
+    pub market: Box<Account<'info, Market>>,
+
+    pub exits: AccountLoader<'info, Exits>,
+
+    #[account(mut)]
+    pub prices: AccountLoader<'info, Prices>,
+
+    #[account(
+      init_if_needed,
+      payer = payer,
+      space = ANCHOR_DISCRIMINATOR + PriceArchive::INIT_SPACE,
+      seeds = [
+        PriceArchive::SEED_PREFIX.as_bytes(),
+        market.key().as_ref(),
+        &epoch.to_le_bytes(),
+        &page.to_le_bytes()
+      ],
+      bump
+    )]
+    pub price_archive: AccountLoader<'info, PriceArchive>,
+
+    pub system_program: Program<'info, System>,
+}
+
+impl<'info> ArchivePrices<'info> {
+    // Moves checkpoints of an epoch of the exits ring buffer into an archive page, until
+    // either the page is full or no checkpoint of that epoch is left in the table
+    pub fn archive_prices(&mut self, epoch: u64, page: u64) -> Result<()> {
+        let exits = self.exits.load()?;
+        let length = exits.length;
+
+        // checkpoints of processed buckets don't change anymore, so they can be archived
+        // before their epoch has passed, which lets the crank make room in a full table
+        let first_bucket = epoch * length;
+        if first_bucket > exits.pointer {
+            return Err(CustomErrorCode::EpochNotArchivable.into());
+        }
+        let end_bucket = (first_bucket + length).min(exits.pointer + 1);
+
+        let mut archive = match self.price_archive.load_init() {
+            Ok(mut archive) => {
+                archive.market = self.market.key();
+                archive.epoch = epoch;
+                archive.page = page;
+                archive
+            }
+            Err(_) => self.price_archive.load_mut()?,
+        };
+
+        let mut prices_data = self.prices.as_ref().try_borrow_mut_data()?;
+        let mut prices = PricesBuffer::load(&mut prices_data)?;
+
+        prices.drain(first_bucket..end_bucket, |checkpoint| {
+            if archive.is_full() {
+                return false;
+            }
+
+            archive.push(checkpoint);
+            true
+        });
+
+        msg!(
+            "Archived {} price checkpoints of epoch {} in page {}",
+            archive.len,
+            epoch,
+            page
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,32 @@
+use anchor_lang::prelude::*;
+
+use crate::error::*;
+use crate::state::*;
+
+#[derive(Accounts)]
+pub struct CloseMarket<'info> {
+    pub authority: Signer<'info>,
+
+    #[account(
+      mut,
+      has_one = authority @ CustomErrorCode::InvalidAuthority,
+      seeds = [Market::SEED_PREFIX.as_bytes()],
+      bump = market.bump
+    )]
+    pub market: Box<Account<'info, Market>>,
+}
+
+impl<'info> CloseMarket<'info> {
+    // Open positions keep streaming until their end slot, only new ones are rejected
+    pub fn close_market(&mut self) -> Result<()> {
+        if self.market.end_slot.is_some() {
+            return Err(CustomErrorCode::MarketClosed.into());
+        }
+
+        let current_slot = self.market.current_slot(&Clock::get()?);
+        self.market.end_slot = Some(current_slot.max(self.market.start_slot));
+
+        msg!("Market closed at slot {}", current_slot);
+        Ok(())
+    }
+}
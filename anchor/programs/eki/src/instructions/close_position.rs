@@ -1,22 +1,27 @@
 use anchor_lang::prelude::*;
 use anchor_spl::associated_token::AssociatedToken;
-use anchor_spl::token_interface::{
-    transfer_checked, Mint, TokenAccount, TokenInterface, TransferChecked,
-};
+use anchor_spl::token_interface::{Mint, TokenAccount, TokenInterface};
 
 use crate::constants::*;
+use crate::error::*;
 use crate::state::*;
 
+use super::{
+    apply_close_a, apply_close_b, is_native_mint, load_price_archive,
+    transfer_tokens_from_treasury, unwrap_sol,
+};
+
 #[derive(Accounts)]
 pub struct ClosePositionA<'info> {
     #[account(mut)]
     pub signer: Signer<'info>,
 
     #[account(
-      mut,
+      init_if_needed,
+      payer = signer,
       associated_token::mint = token_mint_a,
       associated_token::authority = signer,
-      associated_token::token_program = token_program
+      associated_token::token_program = token_program_a
     )]
     pub signer_token_account_a: Box<InterfaceAccount<'info, TokenAccount>>,
 
@@ -25,20 +30,22 @@ pub struct ClosePositionA<'info> {
       payer = signer,
       associated_token::mint = token_mint_b,
       associated_token::authority = signer,
-      associated_token::token_program = token_program
+      associated_token::token_program = token_program_b
     )]
     pub signer_token_account_b: Box<InterfaceAccount<'info, TokenAccount>>,
 
-    #[account(mut)]
     pub token_mint_a: Box<InterfaceAccount<'info, Mint>>,
 
-    #[account(mut)]
     pub token_mint_b: Box<InterfaceAccount<'info, Mint>>,
 
     #[account(
       mut,
-      has_one = treasury_a,
-      has_one = treasury_b,
+      has_one = token_mint_a @ CustomErrorCode::InvalidMint,
+      has_one = token_mint_b @ CustomErrorCode::InvalidMint,
+      has_one = treasury_a @ CustomErrorCode::InvalidTreasury,
+      has_one = treasury_b @ CustomErrorCode::InvalidTreasury,
+      has_one = token_program_a @ CustomErrorCode::InvalidTokenProgram,
+      has_one = token_program_b @ CustomErrorCode::InvalidTokenProgram,
       seeds = [Market::SEED_PREFIX.as_bytes()],
       bump = market.bump
     )]
@@ -79,173 +86,111 @@ pub struct ClosePositionA<'info> {
     #[account(mut)]
     pub prices: AccountLoader<'info, Prices>,
 
-    pub token_program: Interface<'info, TokenInterface>,
+    // Only needed when the checkpoint of the end bucket has been archived
+    #[account(mut)]
+    pub price_archive: Option<AccountLoader<'info, PriceArchive>>,
+
+    #[account(
+      mut,
+      seeds = [UNWRAP_SEED.as_bytes(), market.key().as_ref(), signer.key().as_ref()],
+      bump
+    )]
+    /// CHECK: Created and closed again when unwrapping native payouts
+    pub unwrap_account: UncheckedAccount<'info>,
+
+    pub token_program_a: Interface<'info, TokenInterface>,
+    pub token_program_b: Interface<'info, TokenInterface>,
     pub associated_token_program: Program<'info, AssociatedToken>,
     pub system_program: Program<'info, System>,
 }
 
 impl<'info> ClosePositionA<'info> {
-    pub fn update_exits(&mut self, current_slot: u64) -> Result<()> {
-        let mut exits = self.exits.load_mut()?;
-        let mut prices = self.prices.load_mut()?;
-
-        // Store what volume is not removed anymore from market at position end slot
-        let exit_slot = self.position_a.end_slot;
-        let exit_amount = self.position_a.get_volume();
-
-        let position =
-            ((exit_slot - exits.start_slot) / self.market.end_slot_interval) % EXITS_LENGTH as u64;
-        exits.token_a[position as usize] -= exit_amount;
-        if current_slot <= self.market.start_slot {
-            return Ok(());
-        }
-        let mut quotient =
-            (current_slot - exits.start_slot) / self.market.end_slot_interval / EXITS_LENGTH as u64;
-        let mut new_pointer = ((current_slot - exits.start_slot) / self.market.end_slot_interval)
-            % EXITS_LENGTH as u64;
-
-        let old_pointer = exits.pointer;
-        exits.pointer = new_pointer;
-
-        if new_pointer < old_pointer {
-            new_pointer += EXITS_LENGTH as u64;
-            quotient -= 1;
-        }
-        // start from old_pointer + 1 because old_pointer was handled before with new_pointer
-        for i in (old_pointer + 1)..=new_pointer {
-            let p = i % EXITS_LENGTH as u64;
-
-            let slot = i * self.market.end_slot_interval
-                + exits.start_slot
-                + quotient * self.market.end_slot_interval * EXITS_LENGTH as u64;
-
-            // update bookkeeping account to current state before trade
-            self.bookkeeping
-                .update(self.market.token_a_volume, self.market.token_b_volume, slot);
-
-            self.market.token_a_volume -= exits.token_a[p as usize];
-            self.market.token_b_volume -= exits.token_b[p as usize];
-
-            prices.a_per_b[p as usize] = self.bookkeeping.a_per_b;
-            prices.b_per_a[p as usize] = self.bookkeeping.b_per_a;
-            prices.no_trade_slots[p as usize] = self.bookkeeping.no_trade_slots;
-        }
-
-        Ok(())
+    pub fn close_position(
+        &mut self,
+        current_slot: u64,
+        remaining_accounts: &[AccountInfo<'info>],
+    ) -> Result<(u64, u64)> {
+        let (amount_a, amount_b) = {
+            let mut exits_data = self.exits.as_ref().try_borrow_mut_data()?;
+            let mut exits = ExitsBuffer::load(&mut exits_data)?;
+            let mut prices_data = self.prices.as_ref().try_borrow_mut_data()?;
+            let mut prices = PricesBuffer::load(&mut prices_data)?;
+            let mut price_archive =
+                load_price_archive(self.price_archive.as_ref(), &self.market.key())?;
+
+            apply_close_a(
+                &mut self.market,
+                &mut self.bookkeeping,
+                &mut exits,
+                &mut prices,
+                price_archive.as_deref_mut(),
+                &mut self.position_a,
+                current_slot,
+            )?
+        };
+
+        transfer_tokens_from_treasury(
+            &self.treasury_b,
+            &self.signer_token_account_b,
+            &amount_b,
+            &self.token_mint_b,
+            &self.market,
+            &self.token_program_b,
+            remaining_accounts,
+        )?;
+
+        transfer_tokens_from_treasury(
+            &self.treasury_a,
+            &self.signer_token_account_a,
+            &amount_a,
+            &self.token_mint_a,
+            &self.market,
+            &self.token_program_a,
+            remaining_accounts,
+        )?;
+
+        Ok((amount_a, amount_b))
     }
 
-    pub fn withdraw_tokens(&mut self, current_slot: u64) -> Result<()> {
-        if current_slot < self.position_a.end_slot {
-            self.bookkeeping.update(
-                self.market.token_a_volume,
-                self.market.token_b_volume,
-                current_slot,
-            );
-
-            let amount_b = self.position_a.get_volume() / VOLUME_PRECISION
-                * (self.bookkeeping.b_per_a - self.position_a.bookkeeping)
-                / BOOKKEEPING_PRECISION_FACTOR;
-
-            self.position_a.total_no_trades +=
-                self.bookkeeping.no_trade_slots - self.position_a.no_trade_slots;
-
-            let seeds = &[Market::SEED_PREFIX.as_bytes(), &[self.market.bump]];
-            let signer_seeds = [&seeds[..]];
-
-            let accounts = TransferChecked {
-                from: self.treasury_b.to_account_info(),
-                to: self.signer_token_account_b.to_account_info(),
-                mint: self.token_mint_b.to_account_info(),
-                authority: self.market.to_account_info(),
-            };
-
-            let cpi_context = CpiContext::new_with_signer(
-                self.token_program.to_account_info(),
-                accounts,
-                &signer_seeds,
-            );
-
-            transfer_checked(cpi_context, amount_b, self.token_mint_b.decimals)?;
-
-            // Transfer remaining deposit
-            let amount_a = (self.position_a.end_slot - current_slot
-                + self.position_a.total_no_trades)
-                * self.position_a.get_volume()
-                / VOLUME_PRECISION;
-
-            let accounts = TransferChecked {
-                from: self.treasury_a.to_account_info(),
-                to: self.signer_token_account_a.to_account_info(),
-                mint: self.token_mint_a.to_account_info(),
-                authority: self.market.to_account_info(),
-            };
-
-            let cpi_context = CpiContext::new_with_signer(
-                self.token_program.to_account_info(),
-                accounts,
-                &signer_seeds,
-            );
-
-            transfer_checked(cpi_context, amount_a, self.token_mint_a.decimals)?;
-        } else {
-            let exits = self.exits.load_mut()?;
-            let prices = self.prices.load_mut()?;
-
-            let pointer = ((self.position_a.end_slot - exits.start_slot)
-                / self.market.end_slot_interval)
-                % EXITS_LENGTH as u64;
-
-            let amount_b = self.position_a.get_volume() / VOLUME_PRECISION
-                * (prices.b_per_a[pointer as usize] - self.position_a.bookkeeping)
-                / BOOKKEEPING_PRECISION_FACTOR;
-
-            self.position_a.total_no_trades +=
-                prices.no_trade_slots[pointer as usize] - self.position_a.no_trade_slots;
-
-            let seeds = &[Market::SEED_PREFIX.as_bytes(), &[self.market.bump]];
-            let signer_seeds = [&seeds[..]];
-
-            let accounts = TransferChecked {
-                from: self.treasury_b.to_account_info(),
-                to: self.signer_token_account_b.to_account_info(),
-                mint: self.token_mint_b.to_account_info(),
-                authority: self.market.to_account_info(),
-            };
-
-            let cpi_context = CpiContext::new_with_signer(
-                self.token_program.to_account_info(),
-                accounts,
-                &signer_seeds,
-            );
-
-            transfer_checked(cpi_context, amount_b, self.token_mint_b.decimals)?;
-
-            // Transfer remaining deposit
-            let amount_a =
-                self.position_a.total_no_trades * self.position_a.get_volume() / VOLUME_PRECISION;
-
-            let accounts = TransferChecked {
-                from: self.treasury_a.to_account_info(),
-                to: self.signer_token_account_a.to_account_info(),
-                mint: self.token_mint_a.to_account_info(),
-                authority: self.market.to_account_info(),
-            };
-
-            let cpi_context = CpiContext::new_with_signer(
-                self.token_program.to_account_info(),
-                accounts,
-                &signer_seeds,
-            );
-
-            transfer_checked(cpi_context, amount_a, self.token_mint_a.decimals)?;
+    pub fn unwrap_native_tokens(
+        &self,
+        bumps: &ClosePositionABumps,
+        amount_a: u64,
+        amount_b: u64,
+    ) -> Result<()> {
+        let native_a = is_native_mint(&self.token_mint_a.key());
+        let native_b = is_native_mint(&self.token_mint_b.key());
+
+        if !native_a && !native_b {
+            return Err(CustomErrorCode::NotNativeMint.into());
         }
 
-        Ok(())
-    }
+        if native_a {
+            unwrap_sol(
+                &self.signer_token_account_a,
+                &self.unwrap_account,
+                bumps.unwrap_account,
+                &self.token_mint_a,
+                &self.signer,
+                amount_a,
+                &self.market.key(),
+                &self.token_program_a,
+                &self.system_program,
+            )?;
+        }
 
-    pub fn update_market(&mut self, current_slot: u64) -> Result<()> {
-        if current_slot < self.position_a.end_slot {
-            self.market.token_a_volume -= self.position_a.get_volume();
+        if native_b {
+            unwrap_sol(
+                &self.signer_token_account_b,
+                &self.unwrap_account,
+                bumps.unwrap_account,
+                &self.token_mint_b,
+                &self.signer,
+                amount_b,
+                &self.market.key(),
+                &self.token_program_b,
+                &self.system_program,
+            )?;
         }
 
         Ok(())
@@ -262,28 +207,31 @@ pub struct ClosePositionB<'info> {
       payer = signer,
       associated_token::mint = token_mint_a,
       associated_token::authority = signer,
-      associated_token::token_program = token_program
+      associated_token::token_program = token_program_a
     )]
     pub signer_token_account_a: Box<InterfaceAccount<'info, TokenAccount>>,
 
     #[account(
-      mut,
+      init_if_needed,
+      payer = signer,
       associated_token::mint = token_mint_b,
       associated_token::authority = signer,
-      associated_token::token_program = token_program
+      associated_token::token_program = token_program_b
     )]
     pub signer_token_account_b: Box<InterfaceAccount<'info, TokenAccount>>,
 
-    #[account(mut)]
     pub token_mint_a: Box<InterfaceAccount<'info, Mint>>,
 
-    #[account(mut)]
     pub token_mint_b: Box<InterfaceAccount<'info, Mint>>,
 
     #[account(
       mut,
-      has_one = treasury_a,
-      has_one = treasury_b,
+      has_one = token_mint_a @ CustomErrorCode::InvalidMint,
+      has_one = token_mint_b @ CustomErrorCode::InvalidMint,
+      has_one = treasury_a @ CustomErrorCode::InvalidTreasury,
+      has_one = treasury_b @ CustomErrorCode::InvalidTreasury,
+      has_one = token_program_a @ CustomErrorCode::InvalidTokenProgram,
+      has_one = token_program_b @ CustomErrorCode::InvalidTokenProgram,
       seeds = [Market::SEED_PREFIX.as_bytes()],
       bump = market.bump
     )]
@@ -324,178 +272,113 @@ pub struct ClosePositionB<'info> {
     #[account(mut)]
     pub prices: AccountLoader<'info, Prices>,
 
-    pub token_program: Interface<'info, TokenInterface>,
+    // Only needed when the checkpoint of the end bucket has been archived
+    #[account(mut)]
+    pub price_archive: Option<AccountLoader<'info, PriceArchive>>,
+
+    #[account(
+      mut,
+      seeds = [UNWRAP_SEED.as_bytes(), market.key().as_ref(), signer.key().as_ref()],
+      bump
+    )]
+    /// CHECK: Created and closed again when unwrapping native payouts
+    pub unwrap_account: UncheckedAccount<'info>,
+
+    pub token_program_a: Interface<'info, TokenInterface>,
+    pub token_program_b: Interface<'info, TokenInterface>,
     pub associated_token_program: Program<'info, AssociatedToken>,
     pub system_program: Program<'info, System>,
 }
 
 impl<'info> ClosePositionB<'info> {
-    pub fn update_exits(&mut self, current_slot: u64) -> Result<()> {
-        let mut exits = self.exits.load_mut()?;
-        let mut prices = self.prices.load_mut()?;
-
-        // Store what volume is not removed anymore from market at position end slot
-        let exit_slot = self.position_b.end_slot;
-        let exit_amount = self.position_b.get_volume();
-
-        let position =
-            ((exit_slot - exits.start_slot) / self.market.end_slot_interval) % EXITS_LENGTH as u64;
-        exits.token_b[position as usize] -= exit_amount;
-        if current_slot <= self.market.start_slot {
-            return Ok(());
-        }
-        let mut quotient =
-            (current_slot - exits.start_slot) / self.market.end_slot_interval / EXITS_LENGTH as u64;
-        let mut new_pointer = ((current_slot - exits.start_slot) / self.market.end_slot_interval)
-            % EXITS_LENGTH as u64;
-
-        let old_pointer = exits.pointer;
-        exits.pointer = new_pointer;
+    pub fn close_position(
+        &mut self,
+        current_slot: u64,
+        remaining_accounts: &[AccountInfo<'info>],
+    ) -> Result<(u64, u64)> {
+        let (amount_a, amount_b) = {
+            let mut exits_data = self.exits.as_ref().try_borrow_mut_data()?;
+            let mut exits = ExitsBuffer::load(&mut exits_data)?;
+            let mut prices_data = self.prices.as_ref().try_borrow_mut_data()?;
+            let mut prices = PricesBuffer::load(&mut prices_data)?;
+            let mut price_archive =
+                load_price_archive(self.price_archive.as_ref(), &self.market.key())?;
+
+            apply_close_b(
+                &mut self.market,
+                &mut self.bookkeeping,
+                &mut exits,
+                &mut prices,
+                price_archive.as_deref_mut(),
+                &mut self.position_b,
+                current_slot,
+            )?
+        };
+
+        transfer_tokens_from_treasury(
+            &self.treasury_a,
+            &self.signer_token_account_a,
+            &amount_a,
+            &self.token_mint_a,
+            &self.market,
+            &self.token_program_a,
+            remaining_accounts,
+        )?;
+
+        transfer_tokens_from_treasury(
+            &self.treasury_b,
+            &self.signer_token_account_b,
+            &amount_b,
+            &self.token_mint_b,
+            &self.market,
+            &self.token_program_b,
+            remaining_accounts,
+        )?;
+
+        Ok((amount_a, amount_b))
+    }
 
-        if new_pointer < old_pointer {
-            new_pointer += EXITS_LENGTH as u64;
-            quotient -= 1;
+    pub fn unwrap_native_tokens(
+        &self,
+        bumps: &ClosePositionBBumps,
+        amount_a: u64,
+        amount_b: u64,
+    ) -> Result<()> {
+        let native_a = is_native_mint(&self.token_mint_a.key());
+        let native_b = is_native_mint(&self.token_mint_b.key());
+
+        if !native_a && !native_b {
+            return Err(CustomErrorCode::NotNativeMint.into());
         }
-        // start from old_pointer + 1 because old_pointer was handled before with new_pointer
-        for i in (old_pointer + 1)..=new_pointer {
-            let p = i % EXITS_LENGTH as u64;
-
-            let slot = i * self.market.end_slot_interval
-                + exits.start_slot
-                + quotient * self.market.end_slot_interval * EXITS_LENGTH as u64;
 
-            // update bookkeeping account to current state before trade
-            self.bookkeeping
-                .update(self.market.token_a_volume, self.market.token_b_volume, slot);
-
-            self.market.token_a_volume -= exits.token_a[p as usize];
-            self.market.token_b_volume -= exits.token_b[p as usize];
-
-            prices.a_per_b[p as usize] = self.bookkeeping.a_per_b;
-            prices.b_per_a[p as usize] = self.bookkeeping.b_per_a;
-            prices.no_trade_slots[p as usize] = self.bookkeeping.no_trade_slots;
+        if native_a {
+            unwrap_sol(
+                &self.signer_token_account_a,
+                &self.unwrap_account,
+                bumps.unwrap_account,
+                &self.token_mint_a,
+                &self.signer,
+                amount_a,
+                &self.market.key(),
+                &self.token_program_a,
+                &self.system_program,
+            )?;
         }
 
-        Ok(())
-    }
-
-    pub fn withdraw_tokens(&mut self, current_slot: u64) -> Result<()> {
-        if current_slot < self.position_b.end_slot {
-            self.bookkeeping.update(
-                self.market.token_a_volume,
-                self.market.token_b_volume,
-                current_slot,
-            );
-
-            let amount_a = self.position_b.get_volume() / VOLUME_PRECISION
-                * (self.bookkeeping.a_per_b - self.position_b.bookkeeping)
-                / BOOKKEEPING_PRECISION_FACTOR;
-
-            self.position_b.bookkeeping = self.bookkeeping.a_per_b;
-
-            self.position_b.total_no_trades +=
-                self.bookkeeping.no_trade_slots - self.position_b.no_trade_slots;
-
-            self.position_b.no_trade_slots = self.bookkeeping.no_trade_slots;
-
-            let seeds = &[Market::SEED_PREFIX.as_bytes(), &[self.market.bump]];
-            let signer_seeds = [&seeds[..]];
-
-            let accounts = TransferChecked {
-                from: self.treasury_a.to_account_info(),
-                to: self.signer_token_account_a.to_account_info(),
-                mint: self.token_mint_a.to_account_info(),
-                authority: self.market.to_account_info(),
-            };
-
-            let cpi_context = CpiContext::new_with_signer(
-                self.token_program.to_account_info(),
-                accounts,
-                &signer_seeds,
-            );
-
-            transfer_checked(cpi_context, amount_a, self.token_mint_a.decimals)?;
-
-            // Transfer remaining deposit
-            let amount_b = (self.position_b.end_slot - current_slot
-                + self.position_b.total_no_trades) as u128
-                * self.position_b.get_volume() as u128
-                / VOLUME_PRECISION as u128;
-
-            let accounts = TransferChecked {
-                from: self.treasury_b.to_account_info(),
-                to: self.signer_token_account_b.to_account_info(),
-                mint: self.token_mint_b.to_account_info(),
-                authority: self.market.to_account_info(),
-            };
-
-            let cpi_context = CpiContext::new_with_signer(
-                self.token_program.to_account_info(),
-                accounts,
-                &signer_seeds,
-            );
-
-            transfer_checked(cpi_context, amount_b as u64, self.token_mint_b.decimals)?;
-        } else {
-            let exits = self.exits.load_mut()?;
-            let prices = self.prices.load_mut()?;
-
-            let pointer = ((self.position_b.end_slot - exits.start_slot)
-                / self.market.end_slot_interval)
-                % EXITS_LENGTH as u64;
-
-            let amount_a = self.position_b.get_volume() / VOLUME_PRECISION
-                * (prices.a_per_b[pointer as usize] - self.position_b.bookkeeping)
-                / BOOKKEEPING_PRECISION_FACTOR;
-
-            self.position_b.total_no_trades +=
-                prices.no_trade_slots[pointer as usize] - self.position_b.no_trade_slots;
-
-            let seeds = &[Market::SEED_PREFIX.as_bytes(), &[self.market.bump]];
-            let signer_seeds = [&seeds[..]];
-
-            let accounts = TransferChecked {
-                from: self.treasury_a.to_account_info(),
-                to: self.signer_token_account_a.to_account_info(),
-                mint: self.token_mint_a.to_account_info(),
-                authority: self.market.to_account_info(),
-            };
-
-            let cpi_context = CpiContext::new_with_signer(
-                self.token_program.to_account_info(),
-                accounts,
-                &signer_seeds,
-            );
-
-            transfer_checked(cpi_context, amount_a, self.token_mint_a.decimals)?;
-
-            // Transfer remaining deposit
-            let amount_b =
-                self.position_b.total_no_trades * self.position_b.get_volume() / VOLUME_PRECISION;
-
-            let accounts = TransferChecked {
-                from: self.treasury_b.to_account_info(),
-                to: self.signer_token_account_b.to_account_info(),
-                mint: self.token_mint_b.to_account_info(),
-                authority: self.market.to_account_info(),
-            };
-
-            let cpi_context = CpiContext::new_with_signer(
-                self.token_program.to_account_info(),
-                accounts,
-                &signer_seeds,
-            );
-
-            transfer_checked(cpi_context, amount_b, self.token_mint_b.decimals)?;
+        if native_b {
+            unwrap_sol(
+                &self.signer_token_account_b,
+                &self.unwrap_account,
+                bumps.unwrap_account,
+                &self.token_mint_b,
+                &self.signer,
+                amount_b,
+                &self.market.key(),
+                &self.token_program_b,
+                &self.system_program,
+            )?;
         }
 
         Ok(())
     }
-
-    pub fn update_market(&mut self, current_slot: u64) -> Result<()> {
-        if current_slot < self.position_b.end_slot {
-            self.market.token_b_volume -= self.position_b.get_volume();
-        }
-        Ok(())
-    }
 }
@@ -6,7 +6,10 @@ use crate::constants::*;
 use crate::error::*;
 use crate::state::*;
 
-use super::transfer_tokens;
+use super::{
+    apply_deposit_a, apply_deposit_b, is_native_mint, new_position_a, new_position_b,
+    transfer_tokens, wrap_sol_to_treasury,
+};
 
 #[derive(Accounts)]
 pub struct DepositTokenA<'info> {
@@ -17,15 +20,17 @@ pub struct DepositTokenA<'info> {
       mut,
       associated_token::mint = token_mint_a,
       associated_token::authority = depositor,
-      associated_token::token_program = token_program
+      associated_token::token_program = token_program_a
     )]
-    pub depositor_token_account: Box<InterfaceAccount<'info, TokenAccount>>,
+    pub depositor_token_account: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
 
     pub token_mint_a: Box<InterfaceAccount<'info, Mint>>,
 
     #[account(
       mut,
-      has_one = treasury_a,
+      has_one = token_mint_a @ CustomErrorCode::InvalidMint,
+      has_one = treasury_a @ CustomErrorCode::InvalidTreasury,
+      has_one = token_program_a @ CustomErrorCode::InvalidTokenProgram,
       seeds = [Market::SEED_PREFIX.as_bytes()],
       bump = market.bump
     )]
@@ -59,7 +64,7 @@ pub struct DepositTokenA<'info> {
     #[account(mut)]
     pub prices: AccountLoader<'info, Prices>,
 
-    pub token_program: Interface<'info, TokenInterface>,
+    pub token_program_a: Interface<'info, TokenInterface>,
     pub associated_token_program: Program<'info, AssociatedToken>,
     pub system_program: Program<'info, System>,
 }
@@ -69,125 +74,67 @@ impl<'info> DepositTokenA<'info> {
         &mut self,
         bumps: &DepositTokenABumps,
         amount: u64,
-        mut end_slot: u64,
+        end_slot: u64,
         current_slot: u64,
     ) -> Result<()> {
         msg!("Creating position...");
 
-        if amount < MINIMUM_DEPOSIT_AMOUNT * u64::pow(10, self.token_mint_a.decimals as u32) {
-            return Err(CustomErrorCode::DepositTooSmall.into());
-        }
-
-        let start_slot: u64;
-        if current_slot < self.market.start_slot {
-            start_slot = self.market.start_slot;
-        } else {
-            start_slot = current_slot;
-        }
-
-        let end_slot_interval = self.market.end_slot_interval;
-
-        // Restrict end slot to be only every power of 10th slot
-        end_slot = (end_slot + end_slot_interval / 2) / end_slot_interval;
-        end_slot *= end_slot_interval;
-
-        if end_slot < start_slot + end_slot_interval {
-            return Err(CustomErrorCode::EndSlotAlreadyPassed.into());
-        }
-
-        self.position_a.set_inner(PositionA::new(
+        let position = new_position_a(
+            &self.market,
+            self.exits.load()?.length,
+            self.token_mint_a.decimals,
             amount,
-            start_slot,
             end_slot,
+            current_slot,
             bumps.position_a,
-        ));
+        )?;
+        self.position_a.set_inner(position);
 
         msg!("Position created ending at slot {}", end_slot);
         Ok(())
     }
 
-    pub fn transfer_tokens_to_treasury(&self, amount: u64) -> Result<()> {
-        transfer_tokens(
-            &self.depositor_token_account,
-            &self.treasury_a,
-            &amount,
-            &self.token_mint_a,
-            &self.depositor,
-            &self.token_program,
-        )
-    }
-
-    pub fn update_exits(&mut self, current_slot: u64) -> Result<()> {
-        let mut exits = self.exits.load_mut()?;
-        let mut prices = self.prices.load_mut()?;
-
-        // Store what volume is removed from market at which slot
-        let exit_slot = self.position_a.end_slot;
-        let exit_amount = self.position_a.get_volume();
-
-        let position =
-            ((exit_slot - exits.start_slot) / self.market.end_slot_interval) % EXITS_LENGTH as u64;
-        exits.token_a[position as usize] += exit_amount;
-
-        if current_slot <= self.market.start_slot {
-            return Ok(());
-        }
-
-        // Update bookkeeping account up to most current slot that satisfies end_slot_interval
-        let mut quotient =
-            (current_slot - exits.start_slot) / self.market.end_slot_interval / EXITS_LENGTH as u64;
-        let mut new_pointer = ((current_slot - exits.start_slot) / self.market.end_slot_interval)
-            % EXITS_LENGTH as u64;
-
-        let old_pointer = exits.pointer;
-        exits.pointer = new_pointer;
-
-        if new_pointer < old_pointer {
-            new_pointer += EXITS_LENGTH as u64;
-            quotient -= 1;
-        }
-
-        // start from old_pointer + 1 because old_pointer was handled before with new_pointer
-        for i in (old_pointer + 1)..=new_pointer {
-            let p = i % EXITS_LENGTH as u64;
-
-            let slot = i * self.market.end_slot_interval
-                + exits.start_slot
-                + quotient * self.market.end_slot_interval * EXITS_LENGTH as u64;
-
-            self.bookkeeping
-                .update(self.market.token_a_volume, self.market.token_b_volume, slot);
-
-            self.market.token_a_volume -= exits.token_a[p as usize];
-            self.market.token_b_volume -= exits.token_b[p as usize];
-
-            prices.a_per_b[p as usize] = self.bookkeeping.a_per_b;
-            prices.b_per_a[p as usize] = self.bookkeeping.b_per_a;
-            prices.no_trade_slots[p as usize] = self.bookkeeping.no_trade_slots;
+    pub fn transfer_tokens_to_treasury(
+        &self,
+        amount: u64,
+        remaining_accounts: &[AccountInfo<'info>],
+    ) -> Result<()> {
+        match &self.depositor_token_account {
+            Some(depositor_token_account) => transfer_tokens(
+                depositor_token_account,
+                &self.treasury_a,
+                &amount,
+                &self.token_mint_a,
+                &self.depositor,
+                &self.token_program_a,
+                remaining_accounts,
+            ),
+            // Native deposits are wrapped directly into the treasury
+            None if is_native_mint(&self.token_mint_a.key()) => wrap_sol_to_treasury(
+                &self.depositor,
+                &self.treasury_a,
+                &amount,
+                &self.token_program_a,
+                &self.system_program,
+            ),
+            None => Err(CustomErrorCode::MissingDepositorTokenAccount.into()),
         }
-
-        Ok(())
     }
 
-    pub fn update_market(&mut self, current_slot: u64) -> Result<()> {
-        let old_volume_a = self.market.token_a_volume;
-
-        // update market account
-        self.market.token_a_volume += self.position_a.get_volume();
-
-        if current_slot <= self.market.start_slot {
-            return Ok(());
-        }
-
-        // update bookkeeping account to current state before trade
-        self.bookkeeping
-            .update(old_volume_a, self.market.token_b_volume, current_slot);
-
-        // store bookkeeping and no_trade_slots in position
-        self.position_a.bookkeeping = self.bookkeeping.b_per_a;
-        self.position_a.no_trade_slots = self.bookkeeping.no_trade_slots;
-
-        Ok(())
+    pub fn open_position(&mut self, current_slot: u64) -> Result<()> {
+        let mut exits_data = self.exits.as_ref().try_borrow_mut_data()?;
+        let mut exits = ExitsBuffer::load(&mut exits_data)?;
+        let mut prices_data = self.prices.as_ref().try_borrow_mut_data()?;
+        let mut prices = PricesBuffer::load(&mut prices_data)?;
+
+        apply_deposit_a(
+            &mut self.market,
+            &mut self.bookkeeping,
+            &mut exits,
+            &mut prices,
+            &mut self.position_a,
+            current_slot,
+        )
     }
 }
 
@@ -200,15 +147,17 @@ pub struct DepositTokenB<'info> {
       mut,
       associated_token::mint = token_mint_b,
       associated_token::authority = depositor,
-      associated_token::token_program = token_program
+      associated_token::token_program = token_program_b
     )]
-    pub depositor_token_account: Box<InterfaceAccount<'info, TokenAccount>>,
+    pub depositor_token_account: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
 
     pub token_mint_b: Box<InterfaceAccount<'info, Mint>>,
 
     #[account(
       mut,
-      has_one = treasury_b,
+      has_one = token_mint_b @ CustomErrorCode::InvalidMint,
+      has_one = treasury_b @ CustomErrorCode::InvalidTreasury,
+      has_one = token_program_b @ CustomErrorCode::InvalidTokenProgram,
       seeds = [Market::SEED_PREFIX.as_bytes()],
       bump = market.bump
     )]
@@ -242,7 +191,7 @@ pub struct DepositTokenB<'info> {
     #[account(mut)]
     pub prices: AccountLoader<'info, Prices>,
 
-    pub token_program: Interface<'info, TokenInterface>,
+    pub token_program_b: Interface<'info, TokenInterface>,
     pub associated_token_program: Program<'info, AssociatedToken>,
     pub system_program: Program<'info, System>,
 }
@@ -252,123 +201,66 @@ impl<'info> DepositTokenB<'info> {
         &mut self,
         bumps: &DepositTokenBBumps,
         amount: u64,
-        mut end_slot: u64,
+        end_slot: u64,
         current_slot: u64,
     ) -> Result<()> {
         msg!("Creating position...");
 
-        if amount < MINIMUM_DEPOSIT_AMOUNT * u64::pow(10, self.token_mint_b.decimals as u32) {
-            return Err(CustomErrorCode::DepositTooSmall.into());
-        }
-
-        let start_slot: u64;
-        if current_slot < self.market.start_slot {
-            start_slot = self.market.start_slot;
-        } else {
-            start_slot = current_slot;
-        }
-
-        let end_slot_interval = self.market.end_slot_interval;
-
-        // Restrict end slot to be only every power of 10th slot
-        end_slot = (end_slot + end_slot_interval / 2) / end_slot_interval;
-        end_slot *= end_slot_interval;
-
-        if end_slot < start_slot + end_slot_interval {
-            return Err(CustomErrorCode::EndSlotAlreadyPassed.into());
-        }
-
-        self.position_b.set_inner(PositionB::new(
+        let position = new_position_b(
+            &self.market,
+            self.exits.load()?.length,
+            self.token_mint_b.decimals,
             amount,
-            start_slot,
             end_slot,
+            current_slot,
             bumps.position_b,
-        ));
+        )?;
+        self.position_b.set_inner(position);
 
         msg!("Position created ending at slot {}", end_slot);
         Ok(())
     }
 
-    pub fn transfer_tokens_to_treasury(&self, amount: u64) -> Result<()> {
-        transfer_tokens(
-            &self.depositor_token_account,
-            &self.treasury_b,
-            &amount,
-            &self.token_mint_b,
-            &self.depositor,
-            &self.token_program,
-        )
-    }
-
-    pub fn update_exits(&mut self, current_slot: u64) -> Result<()> {
-        let mut exits = self.exits.load_mut()?;
-        let mut prices = self.prices.load_mut()?;
-
-        let exit_slot = self.position_b.end_slot;
-        let exit_amount = self.position_b.get_volume();
-
-        let position =
-            ((exit_slot - exits.start_slot) / self.market.end_slot_interval) % EXITS_LENGTH as u64;
-        exits.token_b[position as usize] += exit_amount;
-
-        if current_slot <= self.market.start_slot {
-            return Ok(());
-        }
-
-        let mut quotient =
-            (current_slot - exits.start_slot) / self.market.end_slot_interval / EXITS_LENGTH as u64;
-        let mut new_pointer = ((current_slot - exits.start_slot) / self.market.end_slot_interval)
-            % EXITS_LENGTH as u64;
-
-        let old_pointer = exits.pointer;
-        exits.pointer = new_pointer;
-
-        if new_pointer < old_pointer {
-            new_pointer += EXITS_LENGTH as u64;
-            quotient -= 1;
-        }
-
-        // start from old_pointer + 1 because old_pointer was handled before with new_pointer
-        for i in (old_pointer + 1)..=new_pointer {
-            let p = i % EXITS_LENGTH as u64;
-
-            let slot = i * self.market.end_slot_interval
-                + exits.start_slot
-                + quotient * self.market.end_slot_interval * EXITS_LENGTH as u64;
-
-            // update bookkeeping account to current state before trade
-            self.bookkeeping
-                .update(self.market.token_a_volume, self.market.token_b_volume, slot);
-
-            self.market.token_a_volume -= exits.token_a[p as usize];
-            self.market.token_b_volume -= exits.token_b[p as usize];
-
-            prices.a_per_b[p as usize] = self.bookkeeping.a_per_b;
-            prices.b_per_a[p as usize] = self.bookkeeping.b_per_a;
-            prices.no_trade_slots[p as usize] = self.bookkeeping.no_trade_slots;
+    pub fn transfer_tokens_to_treasury(
+        &self,
+        amount: u64,
+        remaining_accounts: &[AccountInfo<'info>],
+    ) -> Result<()> {
+        match &self.depositor_token_account {
+            Some(depositor_token_account) => transfer_tokens(
+                depositor_token_account,
+                &self.treasury_b,
+                &amount,
+                &self.token_mint_b,
+                &self.depositor,
+                &self.token_program_b,
+                remaining_accounts,
+            ),
+            // Native deposits are wrapped directly into the treasury
+            None if is_native_mint(&self.token_mint_b.key()) => wrap_sol_to_treasury(
+                &self.depositor,
+                &self.treasury_b,
+                &amount,
+                &self.token_program_b,
+                &self.system_program,
+            ),
+            None => Err(CustomErrorCode::MissingDepositorTokenAccount.into()),
         }
-
-        Ok(())
     }
 
-    pub fn update_market(&mut self, current_slot: u64) -> Result<()> {
-        let old_volume_b = self.market.token_b_volume;
-
-        // update market account
-        self.market.token_b_volume += self.position_b.get_volume();
-
-        if current_slot <= self.market.start_slot {
-            return Ok(());
-        }
-
-        // update bookkeeping account to current state before trade
-        self.bookkeeping
-            .update(self.market.token_a_volume, old_volume_b, current_slot);
-
-        // store bookkeeping and no_trade_slots in position
-        self.position_b.bookkeeping = self.bookkeeping.a_per_b;
-        self.position_b.no_trade_slots = self.bookkeeping.no_trade_slots;
-
-        Ok(())
+    pub fn open_position(&mut self, current_slot: u64) -> Result<()> {
+        let mut exits_data = self.exits.as_ref().try_borrow_mut_data()?;
+        let mut exits = ExitsBuffer::load(&mut exits_data)?;
+        let mut prices_data = self.prices.as_ref().try_borrow_mut_data()?;
+        let mut prices = PricesBuffer::load(&mut prices_data)?;
+
+        apply_deposit_b(
+            &mut self.market,
+            &mut self.bookkeeping,
+            &mut exits,
+            &mut prices,
+            &mut self.position_b,
+            current_slot,
+        )
     }
 }
@@ -0,0 +1,380 @@
+use anchor_lang::prelude::*;
+
+use crate::error::*;
+use crate::state::*;
+use crate::MINIMUM_DEPOSIT_AMOUNT;
+
+use super::{advance_exits, settle_checkpoint};
+
+// What the position instructions do to the market state, apart from moving tokens. The
+// instructions load their accounts and transfer the amounts these return, and the
+// simulator runs the same functions over its own copy of the state.
+
+// New position for a deposit of amount, once it passed the market's checks
+pub fn new_position_a(
+    market: &Market,
+    exits_length: u64,
+    decimals: u8,
+    amount: u64,
+    end_slot: u64,
+    current_slot: u64,
+    bump: u8,
+) -> Result<PositionA> {
+    if amount < MINIMUM_DEPOSIT_AMOUNT * u64::pow(10, decimals as u32) {
+        return Err(CustomErrorCode::DepositTooSmall.into());
+    }
+
+    let (start_slot, end_slot) = market.position_slots(end_slot, current_slot, exits_length)?;
+
+    let position = PositionA::new(amount, start_slot, end_slot, bump);
+    position.check_volume()?;
+
+    Ok(position)
+}
+
+pub fn new_position_b(
+    market: &Market,
+    exits_length: u64,
+    decimals: u8,
+    amount: u64,
+    end_slot: u64,
+    current_slot: u64,
+    bump: u8,
+) -> Result<PositionB> {
+    if amount < MINIMUM_DEPOSIT_AMOUNT * u64::pow(10, decimals as u32) {
+        return Err(CustomErrorCode::DepositTooSmall.into());
+    }
+
+    let (start_slot, end_slot) = market.position_slots(end_slot, current_slot, exits_length)?;
+
+    let position = PositionB::new(amount, start_slot, end_slot, bump);
+    position.check_volume()?;
+
+    Ok(position)
+}
+
+// Adds the volume of a new position to the market and schedules its exit
+pub fn apply_deposit_a(
+    market: &mut Market,
+    bookkeeping: &mut Bookkeeping,
+    exits: &mut ExitsBuffer,
+    prices: &mut PricesBuffer,
+    position: &mut PositionA,
+    current_slot: u64,
+) -> Result<()> {
+    // Process passed buckets first so the new exit can't be taken for one of them
+    if current_slot > market.start_slot {
+        advance_exits(market, bookkeeping, exits, prices, current_slot)?;
+    }
+
+    let volume = position.get_volume();
+
+    let bucket = exits.bucket(position.end_slot, market.end_slot_interval);
+    exits.token_a[bucket] += volume;
+    exits.mark(bucket);
+
+    bookkeeping.dust_a += position.volume_dust();
+
+    let old_volume_a = market.token_a_volume;
+    market.token_a_volume += volume;
+
+    if current_slot > market.start_slot {
+        // the position only trades from here on
+        bookkeeping.update(old_volume_a, market.token_b_volume, current_slot);
+
+        position.bookkeeping = bookkeeping.b_per_a;
+        position.unfilled = bookkeeping.unfilled_a;
+    }
+
+    Ok(())
+}
+
+pub fn apply_deposit_b(
+    market: &mut Market,
+    bookkeeping: &mut Bookkeeping,
+    exits: &mut ExitsBuffer,
+    prices: &mut PricesBuffer,
+    position: &mut PositionB,
+    current_slot: u64,
+) -> Result<()> {
+    // Process passed buckets first so the new exit can't be taken for one of them
+    if current_slot > market.start_slot {
+        advance_exits(market, bookkeeping, exits, prices, current_slot)?;
+    }
+
+    let volume = position.get_volume();
+
+    let bucket = exits.bucket(position.end_slot, market.end_slot_interval);
+    exits.token_b[bucket] += volume;
+    exits.mark(bucket);
+
+    bookkeeping.dust_b += position.volume_dust();
+
+    let old_volume_b = market.token_b_volume;
+    market.token_b_volume += volume;
+
+    if current_slot > market.start_slot {
+        // the position only trades from here on
+        bookkeeping.update(market.token_a_volume, old_volume_b, current_slot);
+
+        position.bookkeeping = bookkeeping.a_per_b;
+        position.unfilled = bookkeeping.unfilled_b;
+    }
+
+    Ok(())
+}
+
+// Settles the token A a token B position has received so far and returns it. Positions
+// that already ended are paid up to the checkpoint of their end bucket, which stays in
+// place until they're closed.
+pub fn apply_withdraw_a(
+    market: &mut Market,
+    bookkeeping: &mut Bookkeeping,
+    exits: &mut ExitsBuffer,
+    prices: &mut PricesBuffer,
+    price_archive: Option<&mut PriceArchive>,
+    position: &mut PositionB,
+    current_slot: u64,
+) -> Result<u64> {
+    if current_slot <= market.start_slot {
+        return Err(CustomErrorCode::NoTokensSwapped.into());
+    }
+
+    advance_exits(market, bookkeeping, exits, prices, current_slot)?;
+
+    let (price, unfilled) = if current_slot < position.end_slot {
+        bookkeeping.update(market.token_a_volume, market.token_b_volume, current_slot);
+
+        (bookkeeping.a_per_b, bookkeeping.unfilled_b)
+    } else {
+        let bucket = exits.bucket_id(position.end_slot, market.end_slot_interval);
+        let checkpoint = settle_checkpoint(prices, price_archive, bucket, 0, 0)?;
+
+        (checkpoint.a_per_b.into(), checkpoint.unfilled_b)
+    };
+
+    let amount_a = bookkeeping.payout_a(position.swapped_value(price));
+
+    position.bookkeeping = price;
+    position.settle_fill(unfilled, current_slot);
+
+    Ok(amount_a)
+}
+
+pub fn apply_withdraw_b(
+    market: &mut Market,
+    bookkeeping: &mut Bookkeeping,
+    exits: &mut ExitsBuffer,
+    prices: &mut PricesBuffer,
+    price_archive: Option<&mut PriceArchive>,
+    position: &mut PositionA,
+    current_slot: u64,
+) -> Result<u64> {
+    if current_slot <= market.start_slot {
+        return Err(CustomErrorCode::NoTokensSwapped.into());
+    }
+
+    advance_exits(market, bookkeeping, exits, prices, current_slot)?;
+
+    let (price, unfilled) = if current_slot < position.end_slot {
+        bookkeeping.update(market.token_a_volume, market.token_b_volume, current_slot);
+
+        (bookkeeping.b_per_a, bookkeeping.unfilled_a)
+    } else {
+        let bucket = exits.bucket_id(position.end_slot, market.end_slot_interval);
+        let checkpoint = settle_checkpoint(prices, price_archive, bucket, 0, 0)?;
+
+        (checkpoint.b_per_a.into(), checkpoint.unfilled_a)
+    };
+
+    let amount_b = bookkeeping.payout_b(position.swapped_value(price));
+
+    position.bookkeeping = price;
+    position.settle_fill(unfilled, current_slot);
+
+    Ok(amount_b)
+}
+
+// Takes the position out of the market and returns its deposit refund and what it
+// received, as (amount_a, amount_b). Positions that already ended have left the market
+// with their exits bucket and settle against its checkpoint instead.
+pub fn apply_close_a(
+    market: &mut Market,
+    bookkeeping: &mut Bookkeeping,
+    exits: &mut ExitsBuffer,
+    prices: &mut PricesBuffer,
+    price_archive: Option<&mut PriceArchive>,
+    position: &mut PositionA,
+    current_slot: u64,
+) -> Result<(u64, u64)> {
+    if current_slot > market.start_slot {
+        advance_exits(market, bookkeeping, exits, prices, current_slot)?;
+    }
+
+    let volume = position.get_volume();
+
+    if current_slot < position.end_slot {
+        // nothing streamed before the position started
+        let slot = current_slot.max(position.start_slot);
+
+        let bucket = exits.bucket(position.end_slot, market.end_slot_interval);
+        exits.token_a[bucket] -= volume;
+        exits.unmark_if_empty(bucket);
+
+        bookkeeping.update(market.token_a_volume, market.token_b_volume, slot);
+
+        let amount_b = bookkeeping.payout_b(position.swapped_value(bookkeeping.b_per_a));
+
+        position.settle_fill(bookkeeping.unfilled_a, slot);
+
+        let amount_a = bookkeeping.payout_a(position.refund_value(position.end_slot - slot));
+
+        market.token_a_volume -= volume;
+
+        Ok((amount_a, amount_b))
+    } else {
+        let bucket = exits.bucket_id(position.end_slot, market.end_slot_interval);
+        let checkpoint = settle_checkpoint(prices, price_archive, bucket, volume, 0)?;
+
+        let amount_b = bookkeeping.payout_b(position.swapped_value(checkpoint.b_per_a.into()));
+
+        position.settle_fill(checkpoint.unfilled_a, current_slot);
+
+        let amount_a = bookkeeping.payout_a(position.refund_value(0));
+
+        Ok((amount_a, amount_b))
+    }
+}
+
+pub fn apply_close_b(
+    market: &mut Market,
+    bookkeeping: &mut Bookkeeping,
+    exits: &mut ExitsBuffer,
+    prices: &mut PricesBuffer,
+    price_archive: Option<&mut PriceArchive>,
+    position: &mut PositionB,
+    current_slot: u64,
+) -> Result<(u64, u64)> {
+    if current_slot > market.start_slot {
+        advance_exits(market, bookkeeping, exits, prices, current_slot)?;
+    }
+
+    let volume = position.get_volume();
+
+    if current_slot < position.end_slot {
+        // nothing streamed before the position started
+        let slot = current_slot.max(position.start_slot);
+
+        let bucket = exits.bucket(position.end_slot, market.end_slot_interval);
+        exits.token_b[bucket] -= volume;
+        exits.unmark_if_empty(bucket);
+
+        bookkeeping.update(market.token_a_volume, market.token_b_volume, slot);
+
+        let amount_a = bookkeeping.payout_a(position.swapped_value(bookkeeping.a_per_b));
+
+        position.settle_fill(bookkeeping.unfilled_b, slot);
+
+        let amount_b = bookkeeping.payout_b(position.refund_value(position.end_slot - slot));
+
+        market.token_b_volume -= volume;
+
+        Ok((amount_a, amount_b))
+    } else {
+        let bucket = exits.bucket_id(position.end_slot, market.end_slot_interval);
+        let checkpoint = settle_checkpoint(prices, price_archive, bucket, 0, volume)?;
+
+        let amount_a = bookkeeping.payout_a(position.swapped_value(checkpoint.a_per_b.into()));
+
+        position.settle_fill(checkpoint.unfilled_b, current_slot);
+
+        let amount_b = bookkeeping.payout_b(position.refund_value(0));
+
+        Ok((amount_a, amount_b))
+    }
+}
+
+// Pays out what the position is owed so far, then hands back amount of the deposit it
+// hasn't streamed yet. The rest keeps streaming at a lower volume until the end slot.
+// Returns what is paid out, as (amount_a, amount_b).
+pub fn apply_reduce_a(
+    market: &mut Market,
+    bookkeeping: &mut Bookkeeping,
+    exits: &mut ExitsBuffer,
+    prices: &mut PricesBuffer,
+    position: &mut PositionA,
+    amount: u64,
+    current_slot: u64,
+) -> Result<(u64, u64)> {
+    if current_slot >= position.end_slot {
+        return Err(CustomErrorCode::EndSlotAlreadyPassed.into());
+    }
+
+    if current_slot > market.start_slot {
+        advance_exits(market, bookkeeping, exits, prices, current_slot)?;
+    }
+
+    let slot = current_slot.max(position.start_slot);
+
+    bookkeeping.update(market.token_a_volume, market.token_b_volume, slot);
+
+    let amount_b = bookkeeping.payout_b(position.swapped_value(bookkeeping.b_per_a));
+
+    position.bookkeeping = bookkeeping.b_per_a;
+    position.settle_fill(bookkeeping.unfilled_a, slot);
+
+    // the unfilled share so far is refunded at the volume it was streamed with
+    let refund_a = bookkeeping.payout_a(position.refund_value(0));
+
+    let volume = position.get_volume();
+    bookkeeping.dust_a += position.reduce(amount, slot)?;
+    let removed = volume - position.get_volume();
+
+    // the lower volume leaves the market at the same end slot
+    let bucket = exits.bucket(position.end_slot, market.end_slot_interval);
+    exits.token_a[bucket] -= removed;
+    market.token_a_volume -= removed;
+
+    Ok((amount + refund_a, amount_b))
+}
+
+pub fn apply_reduce_b(
+    market: &mut Market,
+    bookkeeping: &mut Bookkeeping,
+    exits: &mut ExitsBuffer,
+    prices: &mut PricesBuffer,
+    position: &mut PositionB,
+    amount: u64,
+    current_slot: u64,
+) -> Result<(u64, u64)> {
+    if current_slot >= position.end_slot {
+        return Err(CustomErrorCode::EndSlotAlreadyPassed.into());
+    }
+
+    if current_slot > market.start_slot {
+        advance_exits(market, bookkeeping, exits, prices, current_slot)?;
+    }
+
+    let slot = current_slot.max(position.start_slot);
+
+    bookkeeping.update(market.token_a_volume, market.token_b_volume, slot);
+
+    let amount_a = bookkeeping.payout_a(position.swapped_value(bookkeeping.a_per_b));
+
+    position.bookkeeping = bookkeeping.a_per_b;
+    position.settle_fill(bookkeeping.unfilled_b, slot);
+
+    // the unfilled share so far is refunded at the volume it was streamed with
+    let refund_b = bookkeeping.payout_b(position.refund_value(0));
+
+    let volume = position.get_volume();
+    bookkeeping.dust_b += position.reduce(amount, slot)?;
+    let removed = volume - position.get_volume();
+
+    // the lower volume leaves the market at the same end slot
+    let bucket = exits.bucket(position.end_slot, market.end_slot_interval);
+    exits.token_b[bucket] -= removed;
+    market.token_b_volume -= removed;
+
+    Ok((amount_a, amount + refund_b))
+}
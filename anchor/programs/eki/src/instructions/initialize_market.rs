@@ -9,10 +9,10 @@ pub struct InitializeMarket<'info> {
     #[account(mut)]
     pub signer: Signer<'info>,
 
-    #[account(mint::token_program = token_program)]
+    #[account(mint::token_program = token_program_a)]
     pub token_mint_a: Box<InterfaceAccount<'info, Mint>>,
 
-    #[account(mint::token_program = token_program)]
+    #[account(mint::token_program = token_program_b)]
     pub token_mint_b: Box<InterfaceAccount<'info, Mint>>,
 
     #[account(
@@ -28,6 +28,7 @@ pub struct InitializeMarket<'info> {
         init,
         token::mint = token_mint_a,
         token::authority = market,
+        token::token_program = token_program_a,
         payer = signer,
         seeds = [TREASURY_A_SEED.as_bytes(), market.key().as_ref()],
         bump
@@ -38,6 +39,7 @@ pub struct InitializeMarket<'info> {
         init,
         token::mint = token_mint_b,
         token::authority = market,
+        token::token_program = token_program_b,
         payer = signer,
         seeds = [TREASURY_B_SEED.as_bytes(), market.key().as_ref()],
         bump
@@ -59,7 +61,8 @@ pub struct InitializeMarket<'info> {
     #[account(mut)]
     pub exits: AccountLoader<'info, Exits>,
 
-    pub token_program: Interface<'info, TokenInterface>,
+    pub token_program_a: Interface<'info, TokenInterface>,
+    pub token_program_b: Interface<'info, TokenInterface>,
     pub system_program: Program<'info, System>,
 }
 
@@ -69,51 +72,58 @@ impl<'info> InitializeMarket<'info> {
         bumps: &InitializeMarketBumps,
         mut start_slot: u64,
         end_slot_interval: u64,
+        time_mode: TimeMode,
+        exits_length: u64,
+        prices_length: u64,
     ) -> Result<()> {
         msg!("Creating market...");
 
-        if !is_power_of_ten(end_slot_interval) {
+        if end_slot_interval == 0 {
             return Err(CustomErrorCode::InvalidSlotInterval.into());
         }
 
-        let current_slot = Clock::get().unwrap().slot;
+        if exits_length < MINIMUM_EXITS_LENGTH || prices_length < MINIMUM_PRICES_LENGTH {
+            return Err(CustomErrorCode::InvalidBufferLength.into());
+        }
+
+        // Exits are stored in a ring buffer, so positions can only end within its horizon
+        let horizon = end_slot_interval.saturating_mul(exits_length);
+        if horizon < time_mode.duration_from_seconds(MINIMUM_EXITS_HORIZON_SECONDS) {
+            return Err(CustomErrorCode::ExitsHorizonTooShort.into());
+        }
+
+        let current_slot = time_mode.current_slot(&Clock::get()?);
         if start_slot < current_slot {
             start_slot = current_slot;
         }
 
         self.market.set_inner(Market::new(
+            self.signer.key(),
+            self.token_mint_a.key(),
+            self.token_mint_b.key(),
             self.treasury_a.key(),
             self.treasury_b.key(),
+            self.token_program_a.key(),
+            self.token_program_b.key(),
             start_slot,
             end_slot_interval,
+            time_mode,
             bumps.market,
         ));
 
         self.bookkeeping
             .set_inner(Bookkeeping::new(start_slot, bumps.bookkeeping));
 
-        let mut exits = self.exits.load_mut()?;
         let mut exits_start_slot = start_slot / end_slot_interval;
         exits_start_slot *= end_slot_interval;
-        exits.new(exits_start_slot);
 
-        let mut prices = self.prices.load_mut()?;
-        prices.new();
+        let mut exits_data = self.exits.as_ref().try_borrow_mut_data()?;
+        ExitsBuffer::init(&mut exits_data, exits_length as usize, exits_start_slot)?;
+
+        let mut prices_data = self.prices.as_ref().try_borrow_mut_data()?;
+        PricesBuffer::init(&mut prices_data, prices_length as usize)?;
 
         msg!("Market created starting at slot {}", start_slot);
         Ok(())
     }
 }
-
-fn is_power_of_ten(n: u64) -> bool {
-    if n == 0 {
-        return false;
-    }
-
-    let mut num = n;
-    while num % 10 == 0 {
-        num /= 10;
-    }
-
-    num == 1
-}
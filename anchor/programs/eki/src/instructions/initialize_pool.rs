@@ -0,0 +1,39 @@
+use anchor_lang::prelude::*;
+use anchor_spl::token::Token;
+use anchor_spl::token_interface::Mint;
+
+use crate::{constants::*, state::*};
+
+#[derive(Accounts)]
+pub struct InitializePool<'info> {
+    #[account(mut)]
+    pub signer: Signer<'info>,
+
+    #[account(
+      seeds = [Market::SEED_PREFIX.as_bytes()],
+      bump = market.bump
+    )]
+    pub market: Box<Account<'info, Market>>,
+
+    // LP shares always live in the SPL token program, whatever the market tokens use
+    #[account(
+      init,
+      payer = signer,
+      mint::decimals = LP_MINT_DECIMALS,
+      mint::authority = market,
+      mint::token_program = lp_token_program,
+      seeds = [LP_MINT_SEED.as_bytes(), market.key().as_ref()],
+      bump
+    )]
+    pub lp_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    pub lp_token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+}
+
+impl<'info> InitializePool<'info> {
+    pub fn initialize_pool(&mut self) -> Result<()> {
+        msg!("Pool created with LP mint {}", self.lp_mint.key());
+        Ok(())
+    }
+}
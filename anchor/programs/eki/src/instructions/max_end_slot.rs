@@ -0,0 +1,23 @@
+use crate::state::*;
+use anchor_lang::prelude::*;
+
+#[derive(Accounts)]
+pub struct MaxEndSlot<'info> {
+    #[account(
+      seeds = [Market::SEED_PREFIX.as_bytes()],
+      bump = market.bump
+    )]
+    pub market: Box<Account<'info, Market>>,
+
+    pub exits: AccountLoader<'info, Exits>,
+}
+
+impl<'info> MaxEndSlot<'info> {
+    pub fn max_end_slot(&self) -> Result<u64> {
+        let current_slot = self.market.current_slot(&Clock::get()?);
+
+        Ok(self
+            .market
+            .max_end_slot(current_slot, self.exits.load()?.length))
+    }
+}
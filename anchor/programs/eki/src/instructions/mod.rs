@@ -1,15 +1,37 @@
+pub mod add_liquidity;
+pub mod archive_prices;
+pub mod close_market;
 pub mod close_position;
 pub mod deposit_token;
+pub mod flows;
 pub mod initialize_exits;
 pub mod initialize_market;
+pub mod initialize_pool;
+pub mod max_end_slot;
+pub mod reduce_position;
+pub mod remove_liquidity;
 pub mod shared;
+pub mod swap_exact_in;
+pub mod sweep_dust;
 pub mod update_bookkeeping;
+pub mod verify_invariants;
 pub mod withdraw_swapped_tokens;
 
+pub use add_liquidity::*;
+pub use archive_prices::*;
+pub use close_market::*;
 pub use close_position::*;
 pub use deposit_token::*;
+pub use flows::*;
 pub use initialize_exits::*;
 pub use initialize_market::*;
+pub use initialize_pool::*;
+pub use max_end_slot::*;
+pub use reduce_position::*;
+pub use remove_liquidity::*;
 pub use shared::*;
+pub use swap_exact_in::*;
+pub use sweep_dust::*;
 pub use update_bookkeeping::*;
+pub use verify_invariants::*;
 pub use withdraw_swapped_tokens::*;
@@ -0,0 +1,373 @@
+use anchor_lang::prelude::*;
+use anchor_spl::associated_token::AssociatedToken;
+use anchor_spl::token_interface::{Mint, TokenAccount, TokenInterface};
+
+use crate::constants::*;
+use crate::error::*;
+use crate::state::*;
+
+use super::{
+    apply_reduce_a, apply_reduce_b, is_native_mint, transfer_tokens_from_treasury, unwrap_sol,
+};
+
+#[derive(Accounts)]
+pub struct ReducePositionA<'info> {
+    #[account(mut)]
+    pub signer: Signer<'info>,
+
+    #[account(
+      init_if_needed,
+      payer = signer,
+      associated_token::mint = token_mint_a,
+      associated_token::authority = signer,
+      associated_token::token_program = token_program_a
+    )]
+    pub signer_token_account_a: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+      init_if_needed,
+      payer = signer,
+      associated_token::mint = token_mint_b,
+      associated_token::authority = signer,
+      associated_token::token_program = token_program_b
+    )]
+    pub signer_token_account_b: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    pub token_mint_a: Box<InterfaceAccount<'info, Mint>>,
+
+    pub token_mint_b: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(
+      mut,
+      has_one = token_mint_a @ CustomErrorCode::InvalidMint,
+      has_one = token_mint_b @ CustomErrorCode::InvalidMint,
+      has_one = treasury_a @ CustomErrorCode::InvalidTreasury,
+      has_one = treasury_b @ CustomErrorCode::InvalidTreasury,
+      has_one = token_program_a @ CustomErrorCode::InvalidTokenProgram,
+      has_one = token_program_b @ CustomErrorCode::InvalidTokenProgram,
+      seeds = [Market::SEED_PREFIX.as_bytes()],
+      bump = market.bump
+    )]
+    pub market: Box<Account<'info, Market>>,
+
+    #[account(
+      mut,
+      seeds = [PositionA::SEED_PREFIX.as_bytes(), market.key().as_ref(), signer.key().as_ref()],
+      bump = position_a.bump
+    )]
+    pub position_a: Box<Account<'info, PositionA>>,
+
+    #[account(
+      mut,
+      seeds = [TREASURY_A_SEED.as_bytes(), market.key().as_ref()],
+      bump
+    )]
+    pub treasury_a: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+      mut,
+      seeds = [TREASURY_B_SEED.as_bytes(), market.key().as_ref()],
+      bump
+    )]
+    pub treasury_b: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+      mut,
+      seeds = [Bookkeeping::SEED_PREFIX.as_bytes(), market.key().as_ref()],
+      bump = bookkeeping.bump
+  )]
+    pub bookkeeping: Box<Account<'info, Bookkeeping>>,
+
+    #[account(mut)]
+    pub exits: AccountLoader<'info, Exits>,
+
+    #[account(mut)]
+    pub prices: AccountLoader<'info, Prices>,
+
+    #[account(
+      mut,
+      seeds = [UNWRAP_SEED.as_bytes(), market.key().as_ref(), signer.key().as_ref()],
+      bump
+    )]
+    /// CHECK: Created and closed again when unwrapping native payouts
+    pub unwrap_account: UncheckedAccount<'info>,
+
+    pub token_program_a: Interface<'info, TokenInterface>,
+    pub token_program_b: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+}
+
+impl<'info> ReducePositionA<'info> {
+    pub fn reduce_position(
+        &mut self,
+        amount: u64,
+        current_slot: u64,
+        remaining_accounts: &[AccountInfo<'info>],
+    ) -> Result<(u64, u64)> {
+        let (amount_a, amount_b) = {
+            let mut exits_data = self.exits.as_ref().try_borrow_mut_data()?;
+            let mut exits = ExitsBuffer::load(&mut exits_data)?;
+            let mut prices_data = self.prices.as_ref().try_borrow_mut_data()?;
+            let mut prices = PricesBuffer::load(&mut prices_data)?;
+
+            apply_reduce_a(
+                &mut self.market,
+                &mut self.bookkeeping,
+                &mut exits,
+                &mut prices,
+                &mut self.position_a,
+                amount,
+                current_slot,
+            )?
+        };
+
+        transfer_tokens_from_treasury(
+            &self.treasury_b,
+            &self.signer_token_account_b,
+            &amount_b,
+            &self.token_mint_b,
+            &self.market,
+            &self.token_program_b,
+            remaining_accounts,
+        )?;
+
+        msg!("Reducing position by {} tokens", amount);
+        transfer_tokens_from_treasury(
+            &self.treasury_a,
+            &self.signer_token_account_a,
+            &amount_a,
+            &self.token_mint_a,
+            &self.market,
+            &self.token_program_a,
+            remaining_accounts,
+        )?;
+
+        Ok((amount_a, amount_b))
+    }
+
+    pub fn unwrap_native_tokens(
+        &self,
+        bumps: &ReducePositionABumps,
+        amount_a: u64,
+        amount_b: u64,
+    ) -> Result<()> {
+        let native_a = is_native_mint(&self.token_mint_a.key());
+        let native_b = is_native_mint(&self.token_mint_b.key());
+
+        if !native_a && !native_b {
+            return Err(CustomErrorCode::NotNativeMint.into());
+        }
+
+        if native_a {
+            unwrap_sol(
+                &self.signer_token_account_a,
+                &self.unwrap_account,
+                bumps.unwrap_account,
+                &self.token_mint_a,
+                &self.signer,
+                amount_a,
+                &self.market.key(),
+                &self.token_program_a,
+                &self.system_program,
+            )?;
+        }
+
+        if native_b {
+            unwrap_sol(
+                &self.signer_token_account_b,
+                &self.unwrap_account,
+                bumps.unwrap_account,
+                &self.token_mint_b,
+                &self.signer,
+                amount_b,
+                &self.market.key(),
+                &self.token_program_b,
+                &self.system_program,
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Accounts)]
+pub struct ReducePositionB<'info> {
+    #[account(mut)]
+    pub signer: Signer<'info>,
+
+    #[account(
+      init_if_needed,
+      payer = signer,
+      associated_token::mint = token_mint_a,
+      associated_token::authority = signer,
+      associated_token::token_program = token_program_a
+    )]
+    pub signer_token_account_a: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+      init_if_needed,
+      payer = signer,
+      associated_token::mint = token_mint_b,
+      associated_token::authority = signer,
+      associated_token::token_program = token_program_b
+    )]
+    pub signer_token_account_b: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    pub token_mint_a: Box<InterfaceAccount<'info, Mint>>,
+
+    pub token_mint_b: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(
+      mut,
+      has_one = token_mint_a @ CustomErrorCode::InvalidMint,
+      has_one = token_mint_b @ CustomErrorCode::InvalidMint,
+      has_one = treasury_a @ CustomErrorCode::InvalidTreasury,
+      has_one = treasury_b @ CustomErrorCode::InvalidTreasury,
+      has_one = token_program_a @ CustomErrorCode::InvalidTokenProgram,
+      has_one = token_program_b @ CustomErrorCode::InvalidTokenProgram,
+      seeds = [Market::SEED_PREFIX.as_bytes()],
+      bump = market.bump
+    )]
+    pub market: Box<Account<'info, Market>>,
+
+    #[account(
+      mut,
+      seeds = [PositionB::SEED_PREFIX.as_bytes(), market.key().as_ref(), signer.key().as_ref()],
+      bump = position_b.bump
+    )]
+    pub position_b: Box<Account<'info, PositionB>>,
+
+    #[account(
+      mut,
+      seeds = [TREASURY_A_SEED.as_bytes(), market.key().as_ref()],
+      bump
+    )]
+    pub treasury_a: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+      mut,
+      seeds = [TREASURY_B_SEED.as_bytes(), market.key().as_ref()],
+      bump
+    )]
+    pub treasury_b: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+      mut,
+      seeds = [Bookkeeping::SEED_PREFIX.as_bytes(), market.key().as_ref()],
+      bump = bookkeeping.bump
+  )]
+    pub bookkeeping: Box<Account<'info, Bookkeeping>>,
+
+    #[account(mut)]
+    pub exits: AccountLoader<'info, Exits>,
+
+    #[account(mut)]
+    pub prices: AccountLoader<'info, Prices>,
+
+    #[account(
+      mut,
+      seeds = [UNWRAP_SEED.as_bytes(), market.key().as_ref(), signer.key().as_ref()],
+      bump
+    )]
+    /// CHECK: Created and closed again when unwrapping native payouts
+    pub unwrap_account: UncheckedAccount<'info>,
+
+    pub token_program_a: Interface<'info, TokenInterface>,
+    pub token_program_b: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+}
+
+impl<'info> ReducePositionB<'info> {
+    pub fn reduce_position(
+        &mut self,
+        amount: u64,
+        current_slot: u64,
+        remaining_accounts: &[AccountInfo<'info>],
+    ) -> Result<(u64, u64)> {
+        let (amount_a, amount_b) = {
+            let mut exits_data = self.exits.as_ref().try_borrow_mut_data()?;
+            let mut exits = ExitsBuffer::load(&mut exits_data)?;
+            let mut prices_data = self.prices.as_ref().try_borrow_mut_data()?;
+            let mut prices = PricesBuffer::load(&mut prices_data)?;
+
+            apply_reduce_b(
+                &mut self.market,
+                &mut self.bookkeeping,
+                &mut exits,
+                &mut prices,
+                &mut self.position_b,
+                amount,
+                current_slot,
+            )?
+        };
+
+        transfer_tokens_from_treasury(
+            &self.treasury_a,
+            &self.signer_token_account_a,
+            &amount_a,
+            &self.token_mint_a,
+            &self.market,
+            &self.token_program_a,
+            remaining_accounts,
+        )?;
+
+        msg!("Reducing position by {} tokens", amount);
+        transfer_tokens_from_treasury(
+            &self.treasury_b,
+            &self.signer_token_account_b,
+            &amount_b,
+            &self.token_mint_b,
+            &self.market,
+            &self.token_program_b,
+            remaining_accounts,
+        )?;
+
+        Ok((amount_a, amount_b))
+    }
+
+    pub fn unwrap_native_tokens(
+        &self,
+        bumps: &ReducePositionBBumps,
+        amount_a: u64,
+        amount_b: u64,
+    ) -> Result<()> {
+        let native_a = is_native_mint(&self.token_mint_a.key());
+        let native_b = is_native_mint(&self.token_mint_b.key());
+
+        if !native_a && !native_b {
+            return Err(CustomErrorCode::NotNativeMint.into());
+        }
+
+        if native_a {
+            unwrap_sol(
+                &self.signer_token_account_a,
+                &self.unwrap_account,
+                bumps.unwrap_account,
+                &self.token_mint_a,
+                &self.signer,
+                amount_a,
+                &self.market.key(),
+                &self.token_program_a,
+                &self.system_program,
+            )?;
+        }
+
+        if native_b {
+            unwrap_sol(
+                &self.signer_token_account_b,
+                &self.unwrap_account,
+                bumps.unwrap_account,
+                &self.token_mint_b,
+                &self.signer,
+                amount_b,
+                &self.market.key(),
+                &self.token_program_b,
+                &self.system_program,
+            )?;
+        }
+
+        Ok(())
+    }
+}
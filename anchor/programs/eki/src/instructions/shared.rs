@@ -1,8 +1,23 @@
+use std::cell::RefMut;
+
 use anchor_lang::prelude::*;
+use anchor_lang::solana_program::program_pack::Pack;
+use anchor_lang::system_program::{
+    allocate, assign, create_account, transfer, Allocate, Assign, CreateAccount, Transfer,
+};
+use anchor_spl::token::spl_token;
+use anchor_spl::token_2022::spl_token_2022::{self, onchain::invoke_transfer_checked};
 use anchor_spl::token_interface::{
-    transfer_checked, Mint, TokenAccount, TokenInterface, TransferChecked,
+    close_account, initialize_account3, sync_native, transfer_checked, CloseAccount,
+    InitializeAccount3, Mint, SyncNative, TokenAccount, TokenInterface, TransferChecked,
 };
 
+use crate::constants::UNWRAP_SEED;
+use crate::error::CustomErrorCode;
+use crate::state::*;
+
+// Remaining accounts are forwarded so that mints with a transfer hook extension can
+// resolve their extra account metas. Mints without a hook ignore them.
 pub fn transfer_tokens<'info>(
     from: &InterfaceAccount<'info, TokenAccount>,
     to: &InterfaceAccount<'info, TokenAccount>,
@@ -10,15 +25,340 @@ pub fn transfer_tokens<'info>(
     mint: &InterfaceAccount<'info, Mint>,
     authority: &Signer<'info>,
     token_program: &Interface<'info, TokenInterface>,
+    remaining_accounts: &[AccountInfo<'info>],
+) -> Result<()> {
+    invoke_transfer_checked(
+        token_program.key,
+        from.to_account_info(),
+        mint.to_account_info(),
+        to.to_account_info(),
+        authority.to_account_info(),
+        remaining_accounts,
+        *amount,
+        mint.decimals,
+        &[],
+    )
+    .map_err(Into::into)
+}
+
+pub fn transfer_tokens_from_treasury<'info>(
+    treasury: &InterfaceAccount<'info, TokenAccount>,
+    to: &InterfaceAccount<'info, TokenAccount>,
+    amount: &u64,
+    mint: &InterfaceAccount<'info, Mint>,
+    market: &Account<'info, Market>,
+    token_program: &Interface<'info, TokenInterface>,
+    remaining_accounts: &[AccountInfo<'info>],
+) -> Result<()> {
+    let seeds = &[Market::SEED_PREFIX.as_bytes(), &[market.bump]];
+    let signer_seeds = [&seeds[..]];
+
+    invoke_transfer_checked(
+        token_program.key,
+        treasury.to_account_info(),
+        mint.to_account_info(),
+        to.to_account_info(),
+        market.to_account_info(),
+        remaining_accounts,
+        *amount,
+        mint.decimals,
+        &signer_seeds,
+    )
+    .map_err(Into::into)
+}
+
+pub fn is_native_mint(mint: &Pubkey) -> bool {
+    *mint == spl_token::native_mint::ID || *mint == spl_token_2022::native_mint::ID
+}
+
+// Moves lamports straight into a native treasury and syncs its token amount, so
+// depositors don't need to hold wrapped SOL.
+pub fn wrap_sol_to_treasury<'info>(
+    depositor: &Signer<'info>,
+    treasury: &InterfaceAccount<'info, TokenAccount>,
+    amount: &u64,
+    token_program: &Interface<'info, TokenInterface>,
+    system_program: &Program<'info, System>,
+) -> Result<()> {
+    let transfer_accounts = Transfer {
+        from: depositor.to_account_info(),
+        to: treasury.to_account_info(),
+    };
+
+    let cpi_context = CpiContext::new(system_program.to_account_info(), transfer_accounts);
+
+    transfer(cpi_context, *amount)?;
+
+    let sync_accounts = SyncNative {
+        account: treasury.to_account_info(),
+    };
+
+    let cpi_context = CpiContext::new(token_program.to_account_info(), sync_accounts);
+
+    sync_native(cpi_context)
+}
+
+// Unwraps amount of the owner's wrapped SOL to lamports. The amount is moved into a temporary
+// token account which is then closed to the owner, so whatever else the owner's wrapped SOL
+// account held stays wrapped.
+#[allow(clippy::too_many_arguments)]
+pub fn unwrap_sol<'info>(
+    token_account: &InterfaceAccount<'info, TokenAccount>,
+    unwrap_account: &UncheckedAccount<'info>,
+    unwrap_bump: u8,
+    mint: &InterfaceAccount<'info, Mint>,
+    owner: &Signer<'info>,
+    amount: u64,
+    market: &Pubkey,
+    token_program: &Interface<'info, TokenInterface>,
+    system_program: &Program<'info, System>,
 ) -> Result<()> {
+    if amount == 0 {
+        return Ok(());
+    }
+
+    let seeds = &[
+        UNWRAP_SEED.as_bytes(),
+        market.as_ref(),
+        owner.key.as_ref(),
+        &[unwrap_bump],
+    ];
+    let signer_seeds = [&seeds[..]];
+
+    let space = spl_token::state::Account::LEN;
+    let rent = Rent::get()?.minimum_balance(space);
+    let lamports = unwrap_account.lamports();
+
+    // Anyone can send lamports to the address, which would make create_account fail, so a
+    // funded account is topped up to rent and then allocated and assigned instead
+    if lamports == 0 {
+        let create_accounts = CreateAccount {
+            from: owner.to_account_info(),
+            to: unwrap_account.to_account_info(),
+        };
+
+        let cpi_context = CpiContext::new_with_signer(
+            system_program.to_account_info(),
+            create_accounts,
+            &signer_seeds,
+        );
+
+        create_account(cpi_context, rent, space as u64, token_program.key)?;
+    } else {
+        if lamports < rent {
+            let transfer_accounts = Transfer {
+                from: owner.to_account_info(),
+                to: unwrap_account.to_account_info(),
+            };
+
+            let cpi_context = CpiContext::new(system_program.to_account_info(), transfer_accounts);
+
+            transfer(cpi_context, rent - lamports)?;
+        }
+
+        let allocate_accounts = Allocate {
+            account_to_allocate: unwrap_account.to_account_info(),
+        };
+
+        let cpi_context = CpiContext::new_with_signer(
+            system_program.to_account_info(),
+            allocate_accounts,
+            &signer_seeds,
+        );
+
+        allocate(cpi_context, space as u64)?;
+
+        let assign_accounts = Assign {
+            account_to_assign: unwrap_account.to_account_info(),
+        };
+
+        let cpi_context = CpiContext::new_with_signer(
+            system_program.to_account_info(),
+            assign_accounts,
+            &signer_seeds,
+        );
+
+        assign(cpi_context, token_program.key)?;
+    }
+
+    let initialize_accounts = InitializeAccount3 {
+        account: unwrap_account.to_account_info(),
+        mint: mint.to_account_info(),
+        authority: owner.to_account_info(),
+    };
+
+    let cpi_context = CpiContext::new(token_program.to_account_info(), initialize_accounts);
+
+    initialize_account3(cpi_context)?;
+
     let transfer_accounts = TransferChecked {
-        from: from.to_account_info(),
+        from: token_account.to_account_info(),
         mint: mint.to_account_info(),
-        to: to.to_account_info(),
-        authority: authority.to_account_info(),
+        to: unwrap_account.to_account_info(),
+        authority: owner.to_account_info(),
     };
 
     let cpi_context = CpiContext::new(token_program.to_account_info(), transfer_accounts);
 
-    transfer_checked(cpi_context, *amount, mint.decimals)
+    transfer_checked(cpi_context, amount, mint.decimals)?;
+
+    let close_accounts = CloseAccount {
+        account: unwrap_account.to_account_info(),
+        destination: owner.to_account_info(),
+        authority: owner.to_account_info(),
+    };
+
+    let cpi_context = CpiContext::new(token_program.to_account_info(), close_accounts);
+
+    close_account(cpi_context)
+}
+
+// Same as crank_exits, for instructions that need every passed bucket processed. Fails
+// when the prices table fills up before that, until the crank has archived checkpoints
+// and caught up.
+pub fn advance_exits(
+    market: &mut Market,
+    bookkeeping: &mut Bookkeeping,
+    exits: &mut ExitsBuffer,
+    prices: &mut PricesBuffer,
+    current_slot: u64,
+) -> Result<()> {
+    if !crank_exits(market, bookkeeping, exits, prices, current_slot) {
+        return Err(CustomErrorCode::PricesFull.into());
+    }
+
+    Ok(())
+}
+
+// Streams trade against the pool reserves up to current_slot, so that instructions moving
+// the reserves only affect what they trade from then on
+pub fn trade_up_to(
+    market: &mut Market,
+    bookkeeping: &mut Bookkeeping,
+    exits: &mut ExitsBuffer,
+    prices: &mut PricesBuffer,
+    current_slot: u64,
+) -> Result<()> {
+    if current_slot <= market.start_slot {
+        return Ok(());
+    }
+
+    advance_exits(market, bookkeeping, exits, prices, current_slot)?;
+    bookkeeping.update(market.token_a_volume, market.token_b_volume, current_slot);
+
+    Ok(())
+}
+
+// Processes every non-empty exits bucket between the last processed one and the bucket
+// of current_slot: bookkeeping is brought up to each bucket boundary and the exiting
+// volume leaves the market. The prices at that boundary are checkpointed together with
+// the exiting volume, which positions settle against when they close, and the bucket is
+// cleared for the next round of the ring buffer. Volumes don't change between non-empty
+// buckets, so empty ones are skipped.
+//
+// Stops before the first bucket whose checkpoint doesn't fit into the prices table and
+// returns false, leaving the rest for after the table has been archived.
+pub fn crank_exits(
+    market: &mut Market,
+    bookkeeping: &mut Bookkeeping,
+    exits: &mut ExitsBuffer,
+    prices: &mut PricesBuffer,
+    current_slot: u64,
+) -> bool {
+    let length = exits.length;
+
+    let current_bucket = exits.bucket_id(current_slot, market.end_slot_interval);
+    if current_bucket <= exits.pointer {
+        return true;
+    }
+
+    // pending buckets are never more than one round of the ring buffer ahead of the last
+    // processed one, so anything after that is empty
+    let last_bucket = current_bucket.min(exits.pointer + length - 1);
+
+    // start from pointer + 1 because the pointer bucket was handled by a previous update
+    let mut bucket = exits.pointer + 1;
+    exits.pointer = current_bucket;
+
+    while bucket <= last_bucket {
+        // search up to the end of the ring buffer at most, the rest is covered after wrapping
+        let from = (bucket % length) as usize;
+        let to = (from as u64 + (last_bucket - bucket)).min(length - 1) as usize;
+
+        let Some(p) = exits.next_non_empty(from, to) else {
+            bucket += (to - from) as u64 + 1;
+            continue;
+        };
+
+        bucket += (p - from) as u64;
+        let slot = exits.start_slot + bucket * market.end_slot_interval;
+
+        // update bookkeeping account to current state before trade
+        bookkeeping.update(market.token_a_volume, market.token_b_volume, slot);
+
+        let inserted = prices.insert(PriceCheckpoint {
+            bucket,
+            a_per_b: bookkeeping.a_per_b.into(),
+            b_per_a: bookkeeping.b_per_a.into(),
+            outstanding_a: exits.token_a[p],
+            outstanding_b: exits.token_b[p],
+            unfilled_a: bookkeeping.unfilled_a,
+            unfilled_b: bookkeeping.unfilled_b,
+        });
+
+        // the bookkeeping is already at the bucket boundary, updating it there again
+        // when the bucket is retried changes nothing
+        if !inserted {
+            exits.pointer = bucket - 1;
+            return false;
+        }
+
+        market.token_a_volume -= exits.token_a[p];
+        market.token_b_volume -= exits.token_b[p];
+
+        exits.token_a[p] = 0;
+        exits.token_b[p] = 0;
+        exits.unmark_if_empty(p);
+
+        bucket += 1;
+    }
+
+    true
+}
+
+// Settles a closed position against the checkpoint of its end bucket, which is either
+// still in the prices table or has been moved to the given archive page
+pub fn settle_checkpoint(
+    prices: &mut PricesBuffer,
+    price_archive: Option<&mut PriceArchive>,
+    bucket: u64,
+    volume_a: u64,
+    volume_b: u64,
+) -> Result<PriceCheckpoint> {
+    if let Some(checkpoint) = prices.settle(bucket, volume_a, volume_b) {
+        return Ok(checkpoint);
+    }
+
+    let Some(price_archive) = price_archive else {
+        return Err(CustomErrorCode::MissingPriceCheckpoint.into());
+    };
+
+    price_archive.settle(bucket, volume_a, volume_b)
+}
+
+// Loads an optional price archive page, which has to belong to the market
+pub fn load_price_archive<'a>(
+    price_archive: Option<&'a AccountLoader<PriceArchive>>,
+    market: &Pubkey,
+) -> Result<Option<RefMut<'a, PriceArchive>>> {
+    let Some(price_archive) = price_archive else {
+        return Ok(None);
+    };
+
+    let archive = price_archive.load_mut()?;
+    if archive.market != *market {
+        return Err(CustomErrorCode::InvalidPriceArchive.into());
+    }
+
+    Ok(Some(archive))
 }
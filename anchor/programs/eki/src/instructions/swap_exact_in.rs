@@ -0,0 +1,163 @@
+use anchor_lang::prelude::*;
+use anchor_spl::associated_token::AssociatedToken;
+use anchor_spl::token_interface::{Mint, TokenAccount, TokenInterface};
+
+use crate::constants::*;
+use crate::error::*;
+use crate::state::*;
+
+use super::{trade_up_to, transfer_tokens, transfer_tokens_from_treasury};
+
+#[derive(Accounts)]
+pub struct SwapExactIn<'info> {
+    #[account(mut)]
+    pub trader: Signer<'info>,
+
+    #[account(
+      init_if_needed,
+      payer = trader,
+      associated_token::mint = token_mint_a,
+      associated_token::authority = trader,
+      associated_token::token_program = token_program_a
+    )]
+    pub trader_token_account_a: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+      init_if_needed,
+      payer = trader,
+      associated_token::mint = token_mint_b,
+      associated_token::authority = trader,
+      associated_token::token_program = token_program_b
+    )]
+    pub trader_token_account_b: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    pub token_mint_a: Box<InterfaceAccount<'info, Mint>>,
+
+    pub token_mint_b: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(
+      mut,
+      has_one = token_mint_a @ CustomErrorCode::InvalidMint,
+      has_one = token_mint_b @ CustomErrorCode::InvalidMint,
+      has_one = treasury_a @ CustomErrorCode::InvalidTreasury,
+      has_one = treasury_b @ CustomErrorCode::InvalidTreasury,
+      has_one = token_program_a @ CustomErrorCode::InvalidTokenProgram,
+      has_one = token_program_b @ CustomErrorCode::InvalidTokenProgram,
+      seeds = [Market::SEED_PREFIX.as_bytes()],
+      bump = market.bump
+    )]
+    pub market: Box<Account<'info, Market>>,
+
+    #[account(
+      mut,
+      seeds = [TREASURY_A_SEED.as_bytes(), market.key().as_ref()],
+      bump
+    )]
+    pub treasury_a: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+      mut,
+      seeds = [TREASURY_B_SEED.as_bytes(), market.key().as_ref()],
+      bump
+    )]
+    pub treasury_b: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+      mut,
+      seeds = [Bookkeeping::SEED_PREFIX.as_bytes(), market.key().as_ref()],
+      bump = bookkeeping.bump
+  )]
+    pub bookkeeping: Box<Account<'info, Bookkeeping>>,
+
+    #[account(mut)]
+    pub exits: AccountLoader<'info, Exits>,
+
+    #[account(mut)]
+    pub prices: AccountLoader<'info, Prices>,
+
+    pub token_program_a: Interface<'info, TokenInterface>,
+    pub token_program_b: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+}
+
+impl<'info> SwapExactIn<'info> {
+    // Streams have to trade against the old reserves up to now before the swap moves them
+    pub fn update_bookkeeping(&mut self, current_slot: u64) -> Result<()> {
+        let mut exits_data = self.exits.as_ref().try_borrow_mut_data()?;
+        let mut exits = ExitsBuffer::load(&mut exits_data)?;
+        let mut prices_data = self.prices.as_ref().try_borrow_mut_data()?;
+        let mut prices = PricesBuffer::load(&mut prices_data)?;
+
+        trade_up_to(
+            &mut self.market,
+            &mut self.bookkeeping,
+            &mut exits,
+            &mut prices,
+            current_slot,
+        )
+    }
+
+    // Trades instantly against the pool reserves, less the swap fee. Streams execute
+    // against the same reserves, so arbitrage pulling them back to the external price
+    // improves their fills. Proceeds that positions haven't withdrawn yet are owed at
+    // the prices they were filled at, so they're never a counterparty.
+    pub fn swap_exact_in(
+        &mut self,
+        amount_in: u64,
+        min_amount_out: u64,
+        a_to_b: bool,
+        remaining_accounts: &[AccountInfo<'info>],
+    ) -> Result<()> {
+        let amount_out = self.bookkeeping.pool.swap(amount_in, a_to_b)?;
+
+        if amount_out == 0 || amount_out < min_amount_out {
+            return Err(CustomErrorCode::SlippageExceeded.into());
+        }
+
+        if a_to_b {
+            transfer_tokens(
+                &self.trader_token_account_a,
+                &self.treasury_a,
+                &amount_in,
+                &self.token_mint_a,
+                &self.trader,
+                &self.token_program_a,
+                remaining_accounts,
+            )?;
+
+            transfer_tokens_from_treasury(
+                &self.treasury_b,
+                &self.trader_token_account_b,
+                &amount_out,
+                &self.token_mint_b,
+                &self.market,
+                &self.token_program_b,
+                remaining_accounts,
+            )?;
+        } else {
+            transfer_tokens(
+                &self.trader_token_account_b,
+                &self.treasury_b,
+                &amount_in,
+                &self.token_mint_b,
+                &self.trader,
+                &self.token_program_b,
+                remaining_accounts,
+            )?;
+
+            transfer_tokens_from_treasury(
+                &self.treasury_a,
+                &self.trader_token_account_a,
+                &amount_out,
+                &self.token_mint_a,
+                &self.market,
+                &self.token_program_a,
+                remaining_accounts,
+            )?;
+        }
+
+        msg!("Swapped {} for {} tokens", amount_in, amount_out);
+        Ok(())
+    }
+}
@@ -0,0 +1,111 @@
+use anchor_lang::prelude::*;
+use anchor_spl::associated_token::AssociatedToken;
+use anchor_spl::token_interface::{Mint, TokenAccount, TokenInterface};
+
+use crate::constants::*;
+use crate::error::*;
+use crate::state::*;
+
+use super::transfer_tokens_from_treasury;
+
+#[derive(Accounts)]
+pub struct SweepDust<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    #[account(
+      init_if_needed,
+      payer = authority,
+      associated_token::mint = token_mint_a,
+      associated_token::authority = authority,
+      associated_token::token_program = token_program_a
+    )]
+    pub authority_token_account_a: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+      init_if_needed,
+      payer = authority,
+      associated_token::mint = token_mint_b,
+      associated_token::authority = authority,
+      associated_token::token_program = token_program_b
+    )]
+    pub authority_token_account_b: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    pub token_mint_a: Box<InterfaceAccount<'info, Mint>>,
+
+    pub token_mint_b: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(
+      has_one = authority @ CustomErrorCode::InvalidAuthority,
+      has_one = token_mint_a @ CustomErrorCode::InvalidMint,
+      has_one = token_mint_b @ CustomErrorCode::InvalidMint,
+      has_one = treasury_a @ CustomErrorCode::InvalidTreasury,
+      has_one = treasury_b @ CustomErrorCode::InvalidTreasury,
+      has_one = token_program_a @ CustomErrorCode::InvalidTokenProgram,
+      has_one = token_program_b @ CustomErrorCode::InvalidTokenProgram,
+      seeds = [Market::SEED_PREFIX.as_bytes()],
+      bump = market.bump
+    )]
+    pub market: Box<Account<'info, Market>>,
+
+    #[account(
+      mut,
+      seeds = [TREASURY_A_SEED.as_bytes(), market.key().as_ref()],
+      bump
+    )]
+    pub treasury_a: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+      mut,
+      seeds = [TREASURY_B_SEED.as_bytes(), market.key().as_ref()],
+      bump
+    )]
+    pub treasury_b: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+      mut,
+      seeds = [Bookkeeping::SEED_PREFIX.as_bytes(), market.key().as_ref()],
+      bump = bookkeeping.bump
+  )]
+    pub bookkeeping: Box<Account<'info, Bookkeeping>>,
+
+    pub token_program_a: Interface<'info, TokenInterface>,
+    pub token_program_b: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+}
+
+impl<'info> SweepDust<'info> {
+    // Dust is what payouts rounded off, so no position can claim it. Positions still
+    // streaming or not closed yet keep their share of the treasuries.
+    pub fn sweep_dust(&mut self, remaining_accounts: &[AccountInfo<'info>]) -> Result<()> {
+        if self.market.end_slot.is_none() {
+            return Err(CustomErrorCode::MarketNotClosed.into());
+        }
+
+        let (amount_a, amount_b) = self.bookkeeping.sweep_dust();
+
+        transfer_tokens_from_treasury(
+            &self.treasury_a,
+            &self.authority_token_account_a,
+            &amount_a,
+            &self.token_mint_a,
+            &self.market,
+            &self.token_program_a,
+            remaining_accounts,
+        )?;
+
+        transfer_tokens_from_treasury(
+            &self.treasury_b,
+            &self.authority_token_account_b,
+            &amount_b,
+            &self.token_mint_b,
+            &self.market,
+            &self.token_program_b,
+            remaining_accounts,
+        )?;
+
+        msg!("Swept {} token A and {} token B", amount_a, amount_b);
+        Ok(())
+    }
+}
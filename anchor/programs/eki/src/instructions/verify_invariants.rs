@@ -0,0 +1,166 @@
+use anchor_lang::prelude::*;
+use anchor_spl::token_interface::TokenAccount;
+
+use crate::constants::*;
+use crate::error::*;
+use crate::state::*;
+
+use super::advance_exits;
+
+// Aggregate state of the market as seen by verify_invariants. The required amounts are
+// what the treasuries have to hold at least: the pool reserves, the deposits pending
+// positions haven't streamed yet and the whole tokens of rounding dust. Streamed tokens
+// that haven't been paid out are owed too, but they are only known per position, so the
+// requirement is a lower bound.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct InvariantReport {
+    pub slot: u64,
+    pub token_a_volume: u64,
+    pub token_b_volume: u64,
+    pub exits_volume_a: u64,
+    pub exits_volume_b: u64,
+    pub treasury_a: u64,
+    pub treasury_b: u64,
+    pub required_a: u64,
+    pub required_b: u64,
+}
+
+impl InvariantReport {
+    // Exits have to be advanced to current_slot, so every non-empty bucket is pending
+    pub fn new(
+        market: &Market,
+        bookkeeping: &Bookkeeping,
+        exits: &ExitsBuffer,
+        current_slot: u64,
+        treasury_a: u64,
+        treasury_b: u64,
+    ) -> Self {
+        let length = exits.length;
+        let first_bucket = exits.pointer + 1;
+
+        let mut exits_volume_a = 0;
+        let mut exits_volume_b = 0;
+        let mut unstreamed_a = 0u128;
+        let mut unstreamed_b = 0u128;
+
+        let mut from = 0;
+        while let Some(p) = exits.next_non_empty(from, length as usize - 1) {
+            // pending buckets lie within one round of the ring buffer after the pointer
+            let bucket = first_bucket + (p as u64 + length - first_bucket % length) % length;
+            let slot = exits.start_slot + bucket * market.end_slot_interval;
+            // nothing streams before the market starts
+            let streamed_to = current_slot.max(market.start_slot);
+            let remaining_slots = slot.saturating_sub(streamed_to) as u128;
+
+            exits_volume_a += exits.token_a[p];
+            exits_volume_b += exits.token_b[p];
+            unstreamed_a += exits.token_a[p] as u128 * remaining_slots;
+            unstreamed_b += exits.token_b[p] as u128 * remaining_slots;
+
+            from = p + 1;
+            if from == length as usize {
+                break;
+            }
+        }
+
+        let pool = &bookkeeping.pool;
+        Self {
+            slot: current_slot,
+            token_a_volume: market.token_a_volume,
+            token_b_volume: market.token_b_volume,
+            exits_volume_a,
+            exits_volume_b,
+            treasury_a,
+            treasury_b,
+            required_a: pool.reserve_a
+                + (unstreamed_a / VOLUME_PRECISION as u128) as u64
+                + (bookkeeping.dust_a / DUST_PRECISION) as u64,
+            required_b: pool.reserve_b
+                + (unstreamed_b / VOLUME_PRECISION as u128) as u64
+                + (bookkeeping.dust_b / DUST_PRECISION) as u64,
+        }
+    }
+
+    pub fn check(&self) -> Result<()> {
+        if self.token_a_volume != self.exits_volume_a || self.token_b_volume != self.exits_volume_b
+        {
+            return Err(CustomErrorCode::VolumeMismatch.into());
+        }
+
+        if self.treasury_a < self.required_a || self.treasury_b < self.required_b {
+            return Err(CustomErrorCode::TreasuryShortfall.into());
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Accounts)]
+pub struct VerifyInvariants<'info> {
+    #[account(mut)]
+    pub signer: Signer<'info>,
+
+    #[account(
+      mut,
+      has_one = treasury_a @ CustomErrorCode::InvalidTreasury,
+      has_one = treasury_b @ CustomErrorCode::InvalidTreasury,
+      seeds = [Market::SEED_PREFIX.as_bytes()],
+      bump = market.bump
+    )]
+    pub market: Box<Account<'info, Market>>,
+
+    #[account(
+      mut,
+      seeds = [Bookkeeping::SEED_PREFIX.as_bytes(), market.key().as_ref()],
+      bump = bookkeeping.bump
+    )]
+    pub bookkeeping: Box<Account<'info, Bookkeeping>>,
+
+    pub treasury_a: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    pub treasury_b: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(mut)]
+    pub exits: AccountLoader<'info, Exits>,
+
+    #[account(mut)]
+    pub prices: AccountLoader<'info, Prices>,
+}
+
+impl<'info> VerifyInvariants<'info> {
+    pub fn verify_invariants(&mut self) -> Result<InvariantReport> {
+        let current_slot = self.market.current_slot(&Clock::get()?);
+
+        let mut exits_data = self.exits.as_ref().try_borrow_mut_data()?;
+        let mut exits = ExitsBuffer::load(&mut exits_data)?;
+        let mut prices_data = self.prices.as_ref().try_borrow_mut_data()?;
+        let mut prices = PricesBuffer::load(&mut prices_data)?;
+
+        if current_slot > self.market.start_slot {
+            advance_exits(
+                &mut self.market,
+                &mut self.bookkeeping,
+                &mut exits,
+                &mut prices,
+                current_slot,
+            )?;
+        }
+
+        let report = InvariantReport::new(
+            &self.market,
+            &self.bookkeeping,
+            &exits,
+            current_slot,
+            self.treasury_a.amount,
+            self.treasury_b.amount,
+        );
+
+        // the return data is dropped when the instruction fails, so the report is logged
+        if let Err(error) = report.check() {
+            msg!("{:?}", report);
+            return Err(error);
+        }
+
+        Ok(report)
+    }
+}
@@ -1,13 +1,16 @@
 use anchor_lang::prelude::*;
 use anchor_spl::associated_token::AssociatedToken;
-use anchor_spl::token_interface::{
-    transfer_checked, Mint, TokenAccount, TokenInterface, TransferChecked,
-};
+use anchor_spl::token_interface::{Mint, TokenAccount, TokenInterface};
 
 use crate::constants::*;
 use crate::error::*;
 use crate::state::*;
 
+use super::{
+    apply_withdraw_a, apply_withdraw_b, is_native_mint, load_price_archive,
+    transfer_tokens_from_treasury, unwrap_sol,
+};
+
 #[derive(Accounts)]
 pub struct WithdrawSwappedTokensA<'info> {
     #[account(mut)]
@@ -18,7 +21,7 @@ pub struct WithdrawSwappedTokensA<'info> {
       payer = withdrawer,
       associated_token::mint = token_mint_a,
       associated_token::authority = withdrawer,
-      associated_token::token_program = token_program
+      associated_token::token_program = token_program_a
     )]
     pub withdrawer_token_account: Box<InterfaceAccount<'info, TokenAccount>>,
 
@@ -26,7 +29,9 @@ pub struct WithdrawSwappedTokensA<'info> {
 
     #[account(
       mut,
-      has_one = treasury_a,
+      has_one = token_mint_a @ CustomErrorCode::InvalidMint,
+      has_one = treasury_a @ CustomErrorCode::InvalidTreasury,
+      has_one = token_program_a @ CustomErrorCode::InvalidTokenProgram,
       seeds = [Market::SEED_PREFIX.as_bytes()],
       bump = market.bump
     )]
@@ -59,102 +64,85 @@ pub struct WithdrawSwappedTokensA<'info> {
     #[account(mut)]
     pub prices: AccountLoader<'info, Prices>,
 
-    pub token_program: Interface<'info, TokenInterface>,
+    // Only needed when the checkpoint of the end bucket has been archived
+    #[account(mut)]
+    pub price_archive: Option<AccountLoader<'info, PriceArchive>>,
+
+    #[account(
+      mut,
+      seeds = [UNWRAP_SEED.as_bytes(), market.key().as_ref(), withdrawer.key().as_ref()],
+      bump
+    )]
+    /// CHECK: Created and closed again when unwrapping native payouts
+    pub unwrap_account: UncheckedAccount<'info>,
+
+    pub token_program_a: Interface<'info, TokenInterface>,
     pub associated_token_program: Program<'info, AssociatedToken>,
     pub system_program: Program<'info, System>,
 }
 
 impl<'info> WithdrawSwappedTokensA<'info> {
-    pub fn update_exits(&mut self, current_slot: u64) -> Result<()> {
-        let mut exits = self.exits.load_mut()?;
-        let mut prices = self.prices.load_mut()?;
-
-        if current_slot <= self.market.start_slot {
-            return Err(CustomErrorCode::NoTokensSwapped.into());
-        }
-
-        let mut quotient =
-            (current_slot - exits.start_slot) / self.market.end_slot_interval / EXITS_LENGTH as u64;
-        let mut new_pointer = ((current_slot - exits.start_slot) / self.market.end_slot_interval)
-            % EXITS_LENGTH as u64;
-
-        let old_pointer = exits.pointer;
-        exits.pointer = new_pointer;
-
-        if new_pointer < old_pointer {
-            new_pointer += EXITS_LENGTH as u64;
-            quotient -= 1;
-        }
-
-        // start from old_pointer + 1 because old_pointer was handled before with new_pointer
-        for i in (old_pointer + 1)..=new_pointer {
-            let p = i % EXITS_LENGTH as u64;
-
-            let slot = i * self.market.end_slot_interval
-                + exits.start_slot
-                + quotient * self.market.end_slot_interval * EXITS_LENGTH as u64;
-
-            // update bookkeeping account to current state before trade
-            self.bookkeeping
-                .update(self.market.token_a_volume, self.market.token_b_volume, slot);
-
-            self.market.token_a_volume -= exits.token_a[p as usize];
-            self.market.token_b_volume -= exits.token_b[p as usize];
-
-            prices.a_per_b[p as usize] = self.bookkeeping.a_per_b;
-            prices.b_per_a[p as usize] = self.bookkeeping.b_per_a;
-            prices.no_trade_slots[p as usize] = self.bookkeeping.no_trade_slots;
-        }
-
-        Ok(())
-    }
-
-    pub fn withdraw_swapped_tokens(&mut self, current_slot: u64) -> Result<()> {
-        let bookkeeping_slot;
-        if current_slot < self.position_b.end_slot {
-            bookkeeping_slot = current_slot;
-        } else {
-            bookkeeping_slot = self.position_b.end_slot
-        }
-
-        self.bookkeeping.update(
-            self.market.token_a_volume,
-            self.market.token_b_volume,
-            bookkeeping_slot,
-        );
-
-        let amount_a = self.position_b.get_volume() / VOLUME_PRECISION
-            * (self.bookkeeping.a_per_b - self.position_b.bookkeeping)
-            / BOOKKEEPING_PRECISION_FACTOR;
-
-        self.position_b.bookkeeping = self.bookkeeping.a_per_b;
-
-        self.position_b.total_no_trades +=
-            self.bookkeeping.no_trade_slots - self.position_b.no_trade_slots;
-
-        self.position_b.no_trade_slots = self.bookkeeping.no_trade_slots;
-
-        let seeds = &[Market::SEED_PREFIX.as_bytes(), &[self.market.bump]];
-        let signer_seeds = [&seeds[..]];
-
-        let accounts = TransferChecked {
-            from: self.treasury_a.to_account_info(),
-            to: self.withdrawer_token_account.to_account_info(),
-            mint: self.token_mint_a.to_account_info(),
-            authority: self.market.to_account_info(),
+    pub fn withdraw_swapped_tokens(
+        &mut self,
+        current_slot: u64,
+        remaining_accounts: &[AccountInfo<'info>],
+    ) -> Result<u64> {
+        let amount_a = {
+            let mut exits_data = self.exits.as_ref().try_borrow_mut_data()?;
+            let mut exits = ExitsBuffer::load(&mut exits_data)?;
+            let mut prices_data = self.prices.as_ref().try_borrow_mut_data()?;
+            let mut prices = PricesBuffer::load(&mut prices_data)?;
+            let mut price_archive =
+                load_price_archive(self.price_archive.as_ref(), &self.market.key())?;
+
+            apply_withdraw_a(
+                &mut self.market,
+                &mut self.bookkeeping,
+                &mut exits,
+                &mut prices,
+                price_archive.as_deref_mut(),
+                &mut self.position_b,
+                current_slot,
+            )?
         };
 
-        let cpi_context = CpiContext::new_with_signer(
-            self.token_program.to_account_info(),
-            accounts,
-            &signer_seeds,
-        );
-
         msg!(
             "Withdrawing {} tokens",
             amount_a / u64::pow(10, self.token_mint_a.decimals as u32),
         );
-        transfer_checked(cpi_context, amount_a, self.token_mint_a.decimals)
+        transfer_tokens_from_treasury(
+            &self.treasury_a,
+            &self.withdrawer_token_account,
+            &amount_a,
+            &self.token_mint_a,
+            &self.market,
+            &self.token_program_a,
+            remaining_accounts,
+        )?;
+
+        Ok(amount_a)
+    }
+
+    pub fn unwrap_native_tokens(
+        &self,
+        bumps: &WithdrawSwappedTokensABumps,
+        amount: u64,
+    ) -> Result<()> {
+        if !is_native_mint(&self.token_mint_a.key()) {
+            return Err(CustomErrorCode::NotNativeMint.into());
+        }
+
+        unwrap_sol(
+            &self.withdrawer_token_account,
+            &self.unwrap_account,
+            bumps.unwrap_account,
+            &self.token_mint_a,
+            &self.withdrawer,
+            amount,
+            &self.market.key(),
+            &self.token_program_a,
+            &self.system_program,
+        )
     }
 }
 
@@ -168,7 +156,7 @@ pub struct WithdrawSwappedTokensB<'info> {
       payer = withdrawer,
       associated_token::mint = token_mint_b,
       associated_token::authority = withdrawer,
-      associated_token::token_program = token_program
+      associated_token::token_program = token_program_b
     )]
     pub withdrawer_token_account: Box<InterfaceAccount<'info, TokenAccount>>,
 
@@ -176,7 +164,9 @@ pub struct WithdrawSwappedTokensB<'info> {
 
     #[account(
       mut,
-      has_one = treasury_b,
+      has_one = token_mint_b @ CustomErrorCode::InvalidMint,
+      has_one = treasury_b @ CustomErrorCode::InvalidTreasury,
+      has_one = token_program_b @ CustomErrorCode::InvalidTokenProgram,
       seeds = [Market::SEED_PREFIX.as_bytes()],
       bump = market.bump
     )]
@@ -209,101 +199,84 @@ pub struct WithdrawSwappedTokensB<'info> {
     #[account(mut)]
     pub prices: AccountLoader<'info, Prices>,
 
-    pub token_program: Interface<'info, TokenInterface>,
+    // Only needed when the checkpoint of the end bucket has been archived
+    #[account(mut)]
+    pub price_archive: Option<AccountLoader<'info, PriceArchive>>,
+
+    #[account(
+      mut,
+      seeds = [UNWRAP_SEED.as_bytes(), market.key().as_ref(), withdrawer.key().as_ref()],
+      bump
+    )]
+    /// CHECK: Created and closed again when unwrapping native payouts
+    pub unwrap_account: UncheckedAccount<'info>,
+
+    pub token_program_b: Interface<'info, TokenInterface>,
     pub associated_token_program: Program<'info, AssociatedToken>,
     pub system_program: Program<'info, System>,
 }
 
 impl<'info> WithdrawSwappedTokensB<'info> {
-    pub fn update_exits(&mut self, current_slot: u64) -> Result<()> {
-        let mut exits = self.exits.load_mut()?;
-        let mut prices = self.prices.load_mut()?;
-
-        if current_slot <= self.market.start_slot {
-            return Err(CustomErrorCode::NoTokensSwapped.into());
-        }
-
-        let mut quotient =
-            (current_slot - exits.start_slot) / self.market.end_slot_interval / EXITS_LENGTH as u64;
-        let mut new_pointer = ((current_slot - exits.start_slot) / self.market.end_slot_interval)
-            % EXITS_LENGTH as u64;
-
-        let old_pointer = exits.pointer;
-        exits.pointer = new_pointer;
-
-        if new_pointer < old_pointer {
-            new_pointer += EXITS_LENGTH as u64;
-            quotient -= 1;
-        }
-
-        // start from old_pointer + 1 because old_pointer was handled before with new_pointer
-        for i in (old_pointer + 1)..=new_pointer {
-            let p = i % EXITS_LENGTH as u64;
-
-            let slot = i * self.market.end_slot_interval
-                + exits.start_slot
-                + quotient * self.market.end_slot_interval * EXITS_LENGTH as u64;
-
-            // update bookkeeping account to current state before trade
-            self.bookkeeping
-                .update(self.market.token_a_volume, self.market.token_b_volume, slot);
-
-            self.market.token_a_volume -= exits.token_a[p as usize];
-            self.market.token_b_volume -= exits.token_b[p as usize];
-
-            prices.a_per_b[p as usize] = self.bookkeeping.a_per_b;
-            prices.b_per_a[p as usize] = self.bookkeeping.b_per_a;
-            prices.no_trade_slots[p as usize] = self.bookkeeping.no_trade_slots;
-        }
-
-        Ok(())
-    }
-
-    pub fn withdraw_swapped_tokens(&mut self, current_slot: u64) -> Result<()> {
-        let bookkeeping_slot;
-        if current_slot < self.position_a.end_slot {
-            bookkeeping_slot = current_slot;
-        } else {
-            bookkeeping_slot = self.position_a.end_slot
-        }
-
-        self.bookkeeping.update(
-            self.market.token_a_volume,
-            self.market.token_b_volume,
-            bookkeeping_slot,
-        );
-
-        let amount_b = self.position_a.get_volume() / VOLUME_PRECISION
-            * (self.bookkeeping.b_per_a - self.position_a.bookkeeping)
-            / BOOKKEEPING_PRECISION_FACTOR;
-
-        self.position_a.bookkeeping = self.bookkeeping.b_per_a;
-
-        self.position_a.total_no_trades +=
-            self.bookkeeping.no_trade_slots - self.position_a.no_trade_slots;
-
-        self.position_a.no_trade_slots = self.bookkeeping.no_trade_slots;
-
-        let seeds = &[Market::SEED_PREFIX.as_bytes(), &[self.market.bump]];
-        let signer_seeds = [&seeds[..]];
-
-        let accounts = TransferChecked {
-            from: self.treasury_b.to_account_info(),
-            to: self.withdrawer_token_account.to_account_info(),
-            mint: self.token_mint_b.to_account_info(),
-            authority: self.market.to_account_info(),
+    pub fn withdraw_swapped_tokens(
+        &mut self,
+        current_slot: u64,
+        remaining_accounts: &[AccountInfo<'info>],
+    ) -> Result<u64> {
+        let amount_b = {
+            let mut exits_data = self.exits.as_ref().try_borrow_mut_data()?;
+            let mut exits = ExitsBuffer::load(&mut exits_data)?;
+            let mut prices_data = self.prices.as_ref().try_borrow_mut_data()?;
+            let mut prices = PricesBuffer::load(&mut prices_data)?;
+            let mut price_archive =
+                load_price_archive(self.price_archive.as_ref(), &self.market.key())?;
+
+            apply_withdraw_b(
+                &mut self.market,
+                &mut self.bookkeeping,
+                &mut exits,
+                &mut prices,
+                price_archive.as_deref_mut(),
+                &mut self.position_a,
+                current_slot,
+            )?
         };
 
-        let cpi_context = CpiContext::new_with_signer(
-            self.token_program.to_account_info(),
-            accounts,
-            &signer_seeds,
-        );
-
         msg!(
             "Withdrawing {} tokens",
             amount_b / u64::pow(10, self.token_mint_b.decimals as u32),
         );
-        transfer_checked(cpi_context, amount_b, self.token_mint_b.decimals)
+        transfer_tokens_from_treasury(
+            &self.treasury_b,
+            &self.withdrawer_token_account,
+            &amount_b,
+            &self.token_mint_b,
+            &self.market,
+            &self.token_program_b,
+            remaining_accounts,
+        )?;
+
+        Ok(amount_b)
+    }
+
+    pub fn unwrap_native_tokens(
+        &self,
+        bumps: &WithdrawSwappedTokensBBumps,
+        amount: u64,
+    ) -> Result<()> {
+        if !is_native_mint(&self.token_mint_b.key()) {
+            return Err(CustomErrorCode::NotNativeMint.into());
+        }
+
+        unwrap_sol(
+            &self.withdrawer_token_account,
+            &self.unwrap_account,
+            bumps.unwrap_account,
+            &self.token_mint_b,
+            &self.withdrawer,
+            amount,
+            &self.market.key(),
+            &self.token_program_b,
+            &self.system_program,
+        )
     }
 }
@@ -1,6 +1,7 @@
 pub mod constants;
 pub mod error;
 pub mod instructions;
+pub mod math;
 pub mod state;
 
 use anchor_lang::prelude::*;
@@ -25,74 +26,231 @@ pub mod eki {
         ctx: Context<InitializeMarket>,
         start_slot: u64,
         end_slot_interval: u64,
+        time_mode: TimeMode,
+        exits_length: u64,
+        prices_length: u64,
     ) -> Result<()> {
-        ctx.accounts
-            .initialize_market(&ctx.bumps, start_slot, end_slot_interval)
+        ctx.accounts.initialize_market(
+            &ctx.bumps,
+            start_slot,
+            end_slot_interval,
+            time_mode,
+            exits_length,
+            prices_length,
+        )
     }
 
-    pub fn deposit_token_a(ctx: Context<DepositTokenA>, amount: u64, end_slot: u64) -> Result<()> {
-        let current_slot = Clock::get().unwrap().slot;
+    pub fn deposit_token_a<'info>(
+        ctx: Context<'_, '_, '_, 'info, DepositTokenA<'info>>,
+        amount: u64,
+        end_slot: u64,
+    ) -> Result<()> {
+        let current_slot = ctx.accounts.market.current_slot(&Clock::get()?);
 
         ctx.accounts
             .initialize_position_account(&ctx.bumps, amount, end_slot, current_slot)?;
 
-        ctx.accounts.transfer_tokens_to_treasury(amount)?;
-
-        ctx.accounts.update_exits(current_slot)?;
+        ctx.accounts
+            .transfer_tokens_to_treasury(amount, ctx.remaining_accounts)?;
 
-        ctx.accounts.update_market(current_slot)
+        ctx.accounts.open_position(current_slot)
     }
 
-    pub fn deposit_token_b(ctx: Context<DepositTokenB>, amount: u64, end_slot: u64) -> Result<()> {
-        let current_slot = Clock::get().unwrap().slot;
+    pub fn deposit_token_b<'info>(
+        ctx: Context<'_, '_, '_, 'info, DepositTokenB<'info>>,
+        amount: u64,
+        end_slot: u64,
+    ) -> Result<()> {
+        let current_slot = ctx.accounts.market.current_slot(&Clock::get()?);
 
         ctx.accounts
             .initialize_position_account(&ctx.bumps, amount, end_slot, current_slot)?;
 
-        ctx.accounts.transfer_tokens_to_treasury(amount)?;
+        ctx.accounts
+            .transfer_tokens_to_treasury(amount, ctx.remaining_accounts)?;
+
+        ctx.accounts.open_position(current_slot)
+    }
+
+    pub fn withdraw_swapped_token_a<'info>(
+        ctx: Context<'_, '_, '_, 'info, WithdrawSwappedTokensA<'info>>,
+        unwrap: bool,
+    ) -> Result<()> {
+        let current_slot = ctx.accounts.market.current_slot(&Clock::get()?);
+
+        let amount = ctx
+            .accounts
+            .withdraw_swapped_tokens(current_slot, ctx.remaining_accounts)?;
+
+        if unwrap {
+            ctx.accounts.unwrap_native_tokens(&ctx.bumps, amount)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn withdraw_swapped_token_b<'info>(
+        ctx: Context<'_, '_, '_, 'info, WithdrawSwappedTokensB<'info>>,
+        unwrap: bool,
+    ) -> Result<()> {
+        let current_slot = ctx.accounts.market.current_slot(&Clock::get()?);
+
+        let amount = ctx
+            .accounts
+            .withdraw_swapped_tokens(current_slot, ctx.remaining_accounts)?;
 
-        ctx.accounts.update_exits(current_slot)?;
+        if unwrap {
+            ctx.accounts.unwrap_native_tokens(&ctx.bumps, amount)?;
+        }
 
-        ctx.accounts.update_market(current_slot)
+        Ok(())
     }
 
-    pub fn withdraw_swapped_token_a(ctx: Context<WithdrawSwappedTokensA>) -> Result<()> {
-        let current_slot = Clock::get().unwrap().slot;
+    pub fn close_position_a<'info>(
+        ctx: Context<'_, '_, '_, 'info, ClosePositionA<'info>>,
+        unwrap: bool,
+    ) -> Result<()> {
+        let current_slot = ctx.accounts.market.current_slot(&Clock::get()?);
+
+        let (amount_a, amount_b) = ctx
+            .accounts
+            .close_position(current_slot, ctx.remaining_accounts)?;
 
-        ctx.accounts.update_exits(current_slot)?;
+        if unwrap {
+            ctx.accounts
+                .unwrap_native_tokens(&ctx.bumps, amount_a, amount_b)?;
+        }
 
-        ctx.accounts.withdraw_swapped_tokens(current_slot)
+        Ok(())
     }
 
-    pub fn withdraw_swapped_token_b(ctx: Context<WithdrawSwappedTokensB>) -> Result<()> {
-        let current_slot = Clock::get().unwrap().slot;
+    pub fn close_position_b<'info>(
+        ctx: Context<'_, '_, '_, 'info, ClosePositionB<'info>>,
+        unwrap: bool,
+    ) -> Result<()> {
+        let current_slot = ctx.accounts.market.current_slot(&Clock::get()?);
 
-        ctx.accounts.update_exits(current_slot)?;
+        let (amount_a, amount_b) = ctx
+            .accounts
+            .close_position(current_slot, ctx.remaining_accounts)?;
 
-        ctx.accounts.withdraw_swapped_tokens(current_slot)
+        if unwrap {
+            ctx.accounts
+                .unwrap_native_tokens(&ctx.bumps, amount_a, amount_b)?;
+        }
+
+        Ok(())
     }
 
-    pub fn close_position_a(ctx: Context<ClosePositionA>) -> Result<()> {
-        let current_slot = Clock::get().unwrap().slot;
+    pub fn reduce_position_a<'info>(
+        ctx: Context<'_, '_, '_, 'info, ReducePositionA<'info>>,
+        amount: u64,
+        unwrap: bool,
+    ) -> Result<()> {
+        let current_slot = ctx.accounts.market.current_slot(&Clock::get()?);
 
-        ctx.accounts.update_exits(current_slot)?;
+        let (amount_a, amount_b) =
+            ctx.accounts
+                .reduce_position(amount, current_slot, ctx.remaining_accounts)?;
 
-        ctx.accounts.withdraw_tokens(current_slot)?;
+        if unwrap {
+            ctx.accounts
+                .unwrap_native_tokens(&ctx.bumps, amount_a, amount_b)?;
+        }
 
-        ctx.accounts.update_market(current_slot)
+        Ok(())
     }
 
-    pub fn close_position_b(ctx: Context<ClosePositionB>) -> Result<()> {
-        let current_slot = Clock::get().unwrap().slot;
+    pub fn reduce_position_b<'info>(
+        ctx: Context<'_, '_, '_, 'info, ReducePositionB<'info>>,
+        amount: u64,
+        unwrap: bool,
+    ) -> Result<()> {
+        let current_slot = ctx.accounts.market.current_slot(&Clock::get()?);
 
-        ctx.accounts.update_exits(current_slot)?;
+        let (amount_a, amount_b) =
+            ctx.accounts
+                .reduce_position(amount, current_slot, ctx.remaining_accounts)?;
 
-        ctx.accounts.withdraw_tokens(current_slot)?;
+        if unwrap {
+            ctx.accounts
+                .unwrap_native_tokens(&ctx.bumps, amount_a, amount_b)?;
+        }
 
-        ctx.accounts.update_market(current_slot)
+        Ok(())
     }
 
     pub fn update_bookkeeping(ctx: Context<UpdateBookkeeping>) -> Result<()> {
         ctx.accounts.update_exits()
     }
+
+    pub fn max_end_slot(ctx: Context<MaxEndSlot>) -> Result<u64> {
+        ctx.accounts.max_end_slot()
+    }
+
+    pub fn archive_prices(ctx: Context<ArchivePrices>, epoch: u64, page: u64) -> Result<()> {
+        ctx.accounts.archive_prices(epoch, page)
+    }
+
+    pub fn initialize_pool(ctx: Context<InitializePool>) -> Result<()> {
+        ctx.accounts.initialize_pool()
+    }
+
+    pub fn add_liquidity<'info>(
+        ctx: Context<'_, '_, '_, 'info, AddLiquidity<'info>>,
+        max_amount_a: u64,
+        max_amount_b: u64,
+        min_shares: u64,
+    ) -> Result<()> {
+        let current_slot = ctx.accounts.market.current_slot(&Clock::get()?);
+
+        ctx.accounts.update_bookkeeping(current_slot)?;
+
+        ctx.accounts.add_liquidity(
+            max_amount_a,
+            max_amount_b,
+            min_shares,
+            ctx.remaining_accounts,
+        )
+    }
+
+    pub fn remove_liquidity<'info>(
+        ctx: Context<'_, '_, '_, 'info, RemoveLiquidity<'info>>,
+        shares: u64,
+        min_amount_a: u64,
+        min_amount_b: u64,
+    ) -> Result<()> {
+        let current_slot = ctx.accounts.market.current_slot(&Clock::get()?);
+
+        ctx.accounts.update_bookkeeping(current_slot)?;
+
+        ctx.accounts
+            .remove_liquidity(shares, min_amount_a, min_amount_b, ctx.remaining_accounts)
+    }
+
+    pub fn swap_exact_in<'info>(
+        ctx: Context<'_, '_, '_, 'info, SwapExactIn<'info>>,
+        amount_in: u64,
+        min_amount_out: u64,
+        a_to_b: bool,
+    ) -> Result<()> {
+        let current_slot = ctx.accounts.market.current_slot(&Clock::get()?);
+
+        ctx.accounts.update_bookkeeping(current_slot)?;
+
+        ctx.accounts
+            .swap_exact_in(amount_in, min_amount_out, a_to_b, ctx.remaining_accounts)
+    }
+
+    pub fn verify_invariants(ctx: Context<VerifyInvariants>) -> Result<InvariantReport> {
+        ctx.accounts.verify_invariants()
+    }
+
+    pub fn close_market(ctx: Context<CloseMarket>) -> Result<()> {
+        ctx.accounts.close_market()
+    }
+
+    pub fn sweep_dust<'info>(ctx: Context<'_, '_, '_, 'info, SweepDust<'info>>) -> Result<()> {
+        ctx.accounts.sweep_dust(ctx.remaining_accounts)
+    }
 }
@@ -0,0 +1,193 @@
+// Fixed point math for executing streams against the pool. Values in WAD carry 18
+// decimals, intermediate products are kept in 256 bits.
+#![allow(clippy::assign_op_pattern, clippy::manual_div_ceil)]
+
+use uint::construct_uint;
+
+construct_uint! {
+    pub struct U256(4);
+}
+
+pub const WAD: u128 = 1_000_000_000_000_000_000;
+const LN_2_WAD: u128 = 693_147_180_559_945_309;
+
+// Past this exponent the exponential term dominates the closed form entirely
+const MAX_EXPONENT_WAD: u128 = 80 * WAD;
+
+// Integer square root by Newton's method, rounded down
+pub fn isqrt(value: u128) -> u128 {
+    if value < 2 {
+        return value;
+    }
+
+    let mut x = value;
+    let mut y = x.div_ceil(2);
+    while y < x {
+        x = y;
+        y = (x + value / x) / 2;
+    }
+
+    x
+}
+
+// e^x for x in WAD, by splitting off powers of two and a Taylor series for the rest
+pub fn exp_wad(x: U256) -> U256 {
+    let wad = U256::from(WAD);
+    let ln_2 = U256::from(LN_2_WAD);
+
+    let n = x / ln_2;
+    let rest = x - n * ln_2;
+
+    let mut term = wad;
+    let mut sum = wad;
+    for i in 1..=32u64 {
+        term = term * rest / (wad * i);
+        if term.is_zero() {
+            break;
+        }
+        sum += term;
+    }
+
+    sum << n.as_usize()
+}
+
+// Reserves after two opposing streams sold in_a and in_b against the x * y = k pool over
+// the same period, following the closed form solution of the TWAMM paper (Paradigm):
+//
+//   c = (sqrt(x * in_b) - sqrt(y * in_a)) / (sqrt(x * in_b) + sqrt(y * in_a))
+//   e = exp(2 * sqrt(in_a * in_b / k))
+//   x_end = sqrt(k * in_a / in_b) * (e + c) / (e - c),  y_end = k / x_end
+//
+// Both end reserves are rounded up, so the streams never receive more than the pool has.
+pub fn twamm_end_reserves(x: u128, y: u128, in_a: u128, in_b: u128) -> (u128, u128) {
+    let k = U256::from(x) * U256::from(y);
+
+    // a single stream is a plain constant product swap
+    if in_a == 0 || in_b == 0 {
+        let (x_end, y_end) = if in_b == 0 {
+            let x_end = U256::from(x) + U256::from(in_a);
+            (x_end, div_ceil(k, x_end))
+        } else {
+            let y_end = U256::from(y) + U256::from(in_b);
+            (div_ceil(k, y_end), y_end)
+        };
+        return (x_end.as_u128(), y_end.as_u128());
+    }
+
+    let wad = U256::from(WAD);
+
+    let a = (U256::from(x) * U256::from(in_b)).integer_sqrt();
+    let b = (U256::from(y) * U256::from(in_a)).integer_sqrt();
+    let c = (if a >= b { a - b } else { b - a }) * wad / (a + b);
+
+    let exponent = U256::from(2) * (U256::from(in_a) * U256::from(in_b)).integer_sqrt() * wad
+        / k.integer_sqrt();
+
+    let ratio = if exponent >= U256::from(MAX_EXPONENT_WAD) {
+        wad
+    } else {
+        let e = exp_wad(exponent);
+        let (numerator, denominator) = if a >= b {
+            (e + c, e - c)
+        } else {
+            (e - c, e + c)
+        };
+        div_ceil(numerator * wad, denominator)
+    };
+
+    let root = (k * U256::from(in_a) / U256::from(in_b)).integer_sqrt();
+    let x_end = div_ceil(root * ratio, wad);
+    let y_end = div_ceil(k, x_end);
+
+    (x_end.as_u128(), y_end.as_u128())
+}
+
+fn div_ceil(numerator: U256, denominator: U256) -> U256 {
+    let (quotient, remainder) = numerator.div_mod(denominator);
+    if remainder.is_zero() {
+        quotient
+    } else {
+        quotient + 1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    // Both streams trading a small step at a time, which the closed form is the limit of
+    fn simulate(x: u128, y: u128, in_a: u128, in_b: u128, steps: u32) -> (f64, f64) {
+        let (mut x, mut y) = (x as f64, y as f64);
+        let k = x * y;
+        let step_a = in_a as f64 / steps as f64;
+        let step_b = in_b as f64 / steps as f64;
+
+        for _ in 0..steps {
+            x += step_a / 2.0;
+            y = k / x;
+            y += step_b;
+            x = k / y;
+            x += step_a / 2.0;
+            y = k / x;
+        }
+
+        (x, y)
+    }
+
+    // Compares what a stream receives, with some slack for the discrete simulation
+    fn assert_close(value: f64, expected: f64) -> std::result::Result<(), TestCaseError> {
+        prop_assert!(
+            (value - expected).abs() <= expected * 1e-4 + 1.0,
+            "{value} != {expected}"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn exp_matches_float() {
+        for x in [0.0, 0.5, 1.0, 2.5, 10.0, 42.0] {
+            let value = exp_wad(U256::from((x * WAD as f64) as u128)).as_u128() as f64;
+            let expected = f64::exp(x) * WAD as f64;
+            assert!((value - expected).abs() / expected < 1e-12, "exp({x})");
+        }
+    }
+
+    #[test]
+    fn single_stream_is_a_constant_product_swap() {
+        let (x_end, y_end) = twamm_end_reserves(1_000_000, 2_000_000, 500_000, 0);
+        assert_eq!(x_end, 1_500_000);
+        assert_eq!(y_end, 1_333_334);
+    }
+
+    proptest! {
+        #[test]
+        fn closed_form_matches_simulation(
+            x in 1_000_000_000_000u128..1_000_000_000_000_000_000,
+            y in 1_000_000_000_000u128..1_000_000_000_000_000_000,
+            in_a in 1_000u128..1_000_000_000_000_000,
+            in_b in 1_000u128..1_000_000_000_000_000,
+        ) {
+            let (x_end, y_end) = twamm_end_reserves(x, y, in_a, in_b);
+            let (sim_x, sim_y) = simulate(x, y, in_a, in_b, 2_000);
+
+            let total_a = (x + in_a) as f64;
+            let total_b = (y + in_b) as f64;
+            assert_close(total_a - x_end as f64, total_a - sim_x)?;
+            assert_close(total_b - y_end as f64, total_b - sim_y)?;
+        }
+
+        #[test]
+        fn constant_product_never_shrinks(
+            x in 1_000u128..1_000_000_000_000_000_000_000,
+            y in 1_000u128..1_000_000_000_000_000_000_000,
+            in_a in 0u128..1_000_000_000_000_000_000_000,
+            in_b in 0u128..1_000_000_000_000_000_000_000,
+        ) {
+            let (x_end, y_end) = twamm_end_reserves(x, y, in_a, in_b);
+
+            // rounding is in favour of the pool, so streams never drain it
+            prop_assert!(U256::from(x_end) * U256::from(y_end) >= U256::from(x) * U256::from(y));
+        }
+    }
+}
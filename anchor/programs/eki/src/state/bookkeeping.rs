@@ -1,15 +1,28 @@
 use anchor_lang::prelude::*;
 
-use crate::BOOKKEEPING_PRECISION_FACTOR;
+use crate::math::U256;
+use crate::{BOOKKEEPING_PRECISION_FACTOR, DUST_PRECISION, FILL_PRECISION};
+
+use super::Pool;
 
 #[account]
 #[derive(InitSpace)]
 pub struct Bookkeeping {
-    pub a_per_b: u64,
-    pub b_per_a: u64,
-    pub no_trade_slots: u64,
+    // Tokens of the other side received per volume, summed over slots in
+    // BOOKKEEPING_PRECISION_FACTOR. Positions only take differences of them, so they may
+    // wrap around.
+    pub a_per_b: u128,
+    pub b_per_a: u128,
+    // Unfilled share of each stream summed over slots, in FILL_PRECISION per slot
+    pub unfilled_a: u64,
+    pub unfilled_b: u64,
     pub last_slot: u64,
+    pub pool: Pool,
     pub bump: u8,
+    // Rounding dust of each token in DUST_PRECISION. Nobody can claim it, so the whole
+    // tokens of it can be swept once the market is closed.
+    pub dust_a: u128,
+    pub dust_b: u128,
 }
 
 impl Bookkeeping {
@@ -19,9 +32,13 @@ impl Bookkeeping {
         Self {
             a_per_b: 0,
             b_per_a: 0,
-            no_trade_slots: 0,
+            unfilled_a: 0,
+            unfilled_b: 0,
             last_slot,
+            pool: Pool::default(),
             bump,
+            dust_a: 0,
+            dust_b: 0,
         }
     }
 
@@ -29,13 +46,97 @@ impl Bookkeeping {
         let slot_diff = current_slot - self.last_slot;
         self.last_slot = current_slot;
 
+        let (fill_a, fill_b) = self.fill_ratios(volume_a, volume_b);
+        self.unfilled_a += (FILL_PRECISION - fill_a) * slot_diff;
+        self.unfilled_b += (FILL_PRECISION - fill_b) * slot_diff;
+
+        if self.pool.has_liquidity() && (volume_a > 0 || volume_b > 0) {
+            let (to_a, to_b, rest_a, rest_b) = self.pool.execute(
+                volume_a as u128 * slot_diff as u128,
+                volume_b as u128 * slot_diff as u128,
+            );
+
+            // the parts of a token left when the reserves were rounded down
+            self.dust_a += rest_a * BOOKKEEPING_PRECISION_FACTOR;
+            self.dust_b += rest_b * BOOKKEEPING_PRECISION_FACTOR;
+
+            if volume_a > 0 {
+                let value = U256::from(BOOKKEEPING_PRECISION_FACTOR) * U256::from(to_a);
+                self.b_per_a = self
+                    .b_per_a
+                    .wrapping_add((value / U256::from(volume_a)).low_u128());
+                self.dust_b += (value % U256::from(volume_a)).as_u128();
+            }
+            if volume_b > 0 {
+                let value = U256::from(BOOKKEEPING_PRECISION_FACTOR) * U256::from(to_b);
+                self.a_per_b = self
+                    .a_per_b
+                    .wrapping_add((value / U256::from(volume_b)).low_u128());
+                self.dust_a += (value % U256::from(volume_b)).as_u128();
+            }
+            return;
+        }
+
         if volume_a == 0 || volume_b == 0 {
-            self.no_trade_slots += slot_diff;
             return;
         }
 
-        // TODO: handle overflow
-        self.a_per_b += BOOKKEEPING_PRECISION_FACTOR * volume_a / volume_b * slot_diff;
-        self.b_per_a += BOOKKEEPING_PRECISION_FACTOR * volume_b / volume_a * slot_diff;
+        // multiply out the whole update before dividing, so each price truncates once per
+        // update rather than once per slot
+        let value_a =
+            U256::from(BOOKKEEPING_PRECISION_FACTOR) * U256::from(volume_a) * U256::from(slot_diff);
+        let value_b =
+            U256::from(BOOKKEEPING_PRECISION_FACTOR) * U256::from(volume_b) * U256::from(slot_diff);
+
+        self.a_per_b = self
+            .a_per_b
+            .wrapping_add((value_a / U256::from(volume_b)).low_u128());
+        self.b_per_a = self
+            .b_per_a
+            .wrapping_add((value_b / U256::from(volume_a)).low_u128());
+
+        // truncating the prices withholds what the remainders are worth to the whole
+        // opposite volume
+        self.dust_a += (value_a % U256::from(volume_b)).as_u128();
+        self.dust_b += (value_b % U256::from(volume_a)).as_u128();
+    }
+
+    // Whole tokens of a payout given in DUST_PRECISION. Payouts round down in favor of
+    // the market, and the rest is kept as dust.
+    //
+    // A price truncation costs a position less than volume / DUST_PRECISION of a token,
+    // which is below 2e-5 even for u64::MAX volumes. So a position paid once falls short
+    // of its exact amount by less than one base unit plus 2e-5 for every bookkeeping
+    // update it traded through.
+    pub fn payout_a(&mut self, value: U256) -> u64 {
+        self.dust_a += (value % U256::from(DUST_PRECISION)).as_u128();
+        (value / U256::from(DUST_PRECISION)).low_u64()
+    }
+
+    pub fn payout_b(&mut self, value: U256) -> u64 {
+        self.dust_b += (value % U256::from(DUST_PRECISION)).as_u128();
+        (value / U256::from(DUST_PRECISION)).low_u64()
+    }
+
+    // Takes the whole tokens of the dust, leaving the parts of a token
+    pub fn sweep_dust(&mut self) -> (u64, u64) {
+        let amount_a = self.dust_a / DUST_PRECISION;
+        let amount_b = self.dust_b / DUST_PRECISION;
+
+        self.dust_a -= amount_a * DUST_PRECISION;
+        self.dust_b -= amount_b * DUST_PRECISION;
+
+        (amount_a as u64, amount_b as u64)
+    }
+
+    // Share of each stream that finds a counterparty, in FILL_PRECISION. Without limit
+    // prices or caps both streams swap fully against each other or the pool, or nothing
+    // trades.
+    pub fn fill_ratios(&self, volume_a: u64, volume_b: u64) -> (u64, u64) {
+        if !self.pool.has_liquidity() && (volume_a == 0 || volume_b == 0) {
+            (0, 0)
+        } else {
+            (FILL_PRECISION, FILL_PRECISION)
+        }
     }
 }
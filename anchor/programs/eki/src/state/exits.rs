@@ -1,23 +1,278 @@
+use std::ops::{Deref, DerefMut};
+
 use anchor_lang::prelude::*;
 
-use crate::EXITS_LENGTH;
+use crate::error::CustomErrorCode;
+use crate::ANCHOR_DISCRIMINATOR;
 
+// Header of the exits account. It is followed by the ring buffers of exiting volume for
+// both tokens and a sparse index over them, all sized by length at market creation.
 #[account(zero_copy)]
 #[derive(InitSpace)]
 pub struct Exits {
-    pub token_a: [u64; EXITS_LENGTH],
-    pub token_b: [u64; EXITS_LENGTH],
+    // Number of the last processed bucket
     pub pointer: u64,
     pub start_slot: u64,
+    pub length: u64,
 }
 
 impl Exits {
     pub const SEED_PREFIX: &'static str = "exits";
 
-    pub fn new(&mut self, start_slot: u64) {
-        self.token_a = [0; EXITS_LENGTH];
-        self.token_b = [0; EXITS_LENGTH];
-        self.pointer = 0;
-        self.start_slot = start_slot;
+    // Account size needed for a ring buffer of length buckets
+    pub fn space(length: usize) -> usize {
+        let (index_length, index_summary_length) = Self::index_lengths(length);
+
+        ANCHOR_DISCRIMINATOR
+            + Self::INIT_SPACE
+            + 8 * (2 * length + index_length + index_summary_length)
+    }
+
+    // One bit per bucket, and one summary bit per index word, so the crank can skip over
+    // empty buckets
+    fn index_lengths(length: usize) -> (usize, usize) {
+        let index_length = length.div_ceil(64);
+        (index_length, index_length.div_ceil(64))
+    }
+
+    // Number of the bucket holding exits at slot, counted from the exits start slot
+    pub fn bucket_id(&self, slot: u64, end_slot_interval: u64) -> u64 {
+        (slot - self.start_slot) / end_slot_interval
+    }
+
+    // Ring buffer index of the bucket holding exits at slot
+    pub fn bucket(&self, slot: u64, end_slot_interval: u64) -> usize {
+        (self.bucket_id(slot, end_slot_interval) % self.length) as usize
+    }
+}
+
+// Zero-copy view over the whole exits account data
+pub struct ExitsBuffer<'a> {
+    header: &'a mut Exits,
+    pub token_a: &'a mut [u64],
+    pub token_b: &'a mut [u64],
+    index: &'a mut [u64],
+    index_summary: &'a mut [u64],
+}
+
+impl<'a> ExitsBuffer<'a> {
+    pub fn load(data: &'a mut [u8]) -> Result<Self> {
+        let (header, buffers) = data[ANCHOR_DISCRIMINATOR..].split_at_mut(Exits::INIT_SPACE);
+        let header: &mut Exits = bytemuck::from_bytes_mut(header);
+        let length = header.length as usize;
+
+        let size = Exits::space(length) - ANCHOR_DISCRIMINATOR - Exits::INIT_SPACE;
+        if buffers.len() < size {
+            return Err(CustomErrorCode::AccountTooSmall.into());
+        }
+
+        let (index_length, _) = Exits::index_lengths(length);
+        let buffers: &mut [u64] = bytemuck::cast_slice_mut(&mut buffers[..size]);
+        let (token_a, buffers) = buffers.split_at_mut(length);
+        let (token_b, buffers) = buffers.split_at_mut(length);
+        let (index, index_summary) = buffers.split_at_mut(index_length);
+
+        Ok(Self {
+            header,
+            token_a,
+            token_b,
+            index,
+            index_summary,
+        })
+    }
+
+    pub fn init(data: &'a mut [u8], length: usize, start_slot: u64) -> Result<Self> {
+        let header: &mut Exits = bytemuck::from_bytes_mut(
+            &mut data[ANCHOR_DISCRIMINATOR..ANCHOR_DISCRIMINATOR + Exits::INIT_SPACE],
+        );
+        header.pointer = 0;
+        header.start_slot = start_slot;
+        header.length = length as u64;
+
+        let exits = Self::load(data)?;
+        exits.token_a.fill(0);
+        exits.token_b.fill(0);
+        exits.index.fill(0);
+        exits.index_summary.fill(0);
+
+        Ok(exits)
+    }
+
+    pub fn mark(&mut self, bucket: usize) {
+        let word = bucket / 64;
+        self.index[word] |= 1 << (bucket % 64);
+        self.index_summary[word / 64] |= 1 << (word % 64);
+    }
+
+    pub fn unmark_if_empty(&mut self, bucket: usize) {
+        if self.token_a[bucket] != 0 || self.token_b[bucket] != 0 {
+            return;
+        }
+
+        let word = bucket / 64;
+        self.index[word] &= !(1 << (bucket % 64));
+        if self.index[word] == 0 {
+            self.index_summary[word / 64] &= !(1 << (word % 64));
+        }
+    }
+
+    // First bucket in from..=to holding exits, both being ring buffer indices with from <= to
+    pub fn next_non_empty(&self, from: usize, to: usize) -> Option<usize> {
+        let mut word = from / 64;
+        let mut bits = self.index[word] & (u64::MAX << (from % 64));
+
+        loop {
+            if bits != 0 {
+                let bucket = word * 64 + bits.trailing_zeros() as usize;
+                return (bucket <= to).then_some(bucket);
+            }
+
+            word = self.next_non_empty_word(word + 1, to / 64)?;
+            bits = self.index[word];
+        }
+    }
+
+    fn next_non_empty_word(&self, from: usize, to: usize) -> Option<usize> {
+        if from > to {
+            return None;
+        }
+
+        let mut group = from / 64;
+        let mut bits = self.index_summary[group] & (u64::MAX << (from % 64));
+
+        loop {
+            if bits != 0 {
+                let word = group * 64 + bits.trailing_zeros() as usize;
+                return (word <= to).then_some(word);
+            }
+
+            group += 1;
+            if group * 64 > to {
+                return None;
+            }
+            bits = self.index_summary[group];
+        }
+    }
+}
+
+impl Deref for ExitsBuffer<'_> {
+    type Target = Exits;
+
+    fn deref(&self) -> &Exits {
+        self.header
+    }
+}
+
+impl DerefMut for ExitsBuffer<'_> {
+    fn deref_mut(&mut self) -> &mut Exits {
+        self.header
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Runs test against an empty exits buffer of length buckets
+    fn with_exits(length: usize, test: impl FnOnce(&mut ExitsBuffer)) {
+        let mut data = vec![0u64; Exits::space(length).div_ceil(8)];
+        let mut exits = ExitsBuffer::init(bytemuck::cast_slice_mut(&mut data), length, 0).unwrap();
+        test(&mut exits);
+    }
+
+    // Adds exiting volume to bucket the way deposits do
+    fn add(exits: &mut ExitsBuffer, bucket: usize, amount: u64) {
+        exits.token_a[bucket] += amount;
+        exits.mark(bucket);
+    }
+
+    fn remove(exits: &mut ExitsBuffer, bucket: usize, amount: u64) {
+        exits.token_a[bucket] -= amount;
+        exits.unmark_if_empty(bucket);
+    }
+
+    #[test]
+    fn finds_buckets_across_word_boundaries() {
+        with_exits(200, |exits| {
+            for bucket in [63, 64, 128, 199] {
+                add(exits, bucket, 1);
+            }
+
+            assert_eq!(exits.next_non_empty(0, 199), Some(63));
+            assert_eq!(exits.next_non_empty(64, 199), Some(64));
+            assert_eq!(exits.next_non_empty(65, 199), Some(128));
+            assert_eq!(exits.next_non_empty(129, 199), Some(199));
+            assert_eq!(exits.next_non_empty(199, 199), Some(199));
+
+            // buckets past to are not reported, even in the same word
+            assert_eq!(exits.next_non_empty(0, 62), None);
+            assert_eq!(exits.next_non_empty(65, 127), None);
+            assert_eq!(exits.next_non_empty(129, 198), None);
+        });
+    }
+
+    #[test]
+    fn skips_groups_of_empty_words() {
+        // more than 64 index words, so whole summary words are empty in between
+        let length = 64 * 64 * 2 + 10;
+        with_exits(length, |exits| {
+            add(exits, 3, 1);
+            add(exits, 64 * 64 * 2 + 5, 1);
+
+            assert_eq!(exits.next_non_empty(4, length - 1), Some(64 * 64 * 2 + 5));
+            assert_eq!(exits.next_non_empty(4, 64 * 64 * 2 + 4), None);
+            assert_eq!(
+                exits.next_non_empty(64 * 64, length - 1),
+                Some(64 * 64 * 2 + 5)
+            );
+        });
+    }
+
+    #[test]
+    fn scans_around_the_ring() {
+        // the crank scans up to the end of the ring buffer, then on from its start
+        with_exits(100, |exits| {
+            add(exits, 97, 1);
+            add(exits, 2, 1);
+
+            assert_eq!(exits.next_non_empty(95, 99), Some(97));
+            remove(exits, 97, 1);
+            assert_eq!(exits.next_non_empty(95, 99), None);
+            assert_eq!(exits.next_non_empty(0, 10), Some(2));
+
+            // a bucket emptied by the crank is found again once it's reused a round later
+            add(exits, 97, 1);
+            remove(exits, 2, 1);
+            assert_eq!(exits.next_non_empty(3, 99), Some(97));
+            assert_eq!(exits.next_non_empty(0, 96), None);
+        });
+    }
+
+    #[test]
+    fn unmarks_only_empty_buckets() {
+        with_exits(128, |exits| {
+            add(exits, 10, 1);
+            add(exits, 20, 2);
+            add(exits, 100, 1);
+
+            // other buckets in the same word stay marked
+            remove(exits, 10, 1);
+            assert_eq!(exits.next_non_empty(0, 127), Some(20));
+            assert_eq!(exits.index_summary[0], 0b11);
+
+            // a bucket with volume left of either token stays marked
+            remove(exits, 20, 1);
+            assert_eq!(exits.next_non_empty(0, 127), Some(20));
+            exits.token_b[20] = 1;
+            remove(exits, 20, 1);
+            assert_eq!(exits.next_non_empty(0, 127), Some(20));
+
+            // the word leaves the summary once its last bucket is empty
+            exits.token_b[20] = 0;
+            exits.unmark_if_empty(20);
+            assert_eq!(exits.next_non_empty(0, 127), Some(100));
+            assert_eq!(exits.index, [0, 1 << (100 - 64)]);
+            assert_eq!(exits.index_summary[0], 0b10);
+        });
     }
 }
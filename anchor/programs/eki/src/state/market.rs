@@ -1,14 +1,48 @@
 use anchor_lang::prelude::*;
 
+use crate::error::CustomErrorCode;
+use crate::{MINIMUM_TRADE_DURATION_SECONDS, SLOT_DURATION_MS};
+
+// Clock a market runs on. In timestamp mode every slot value of the market, its
+// positions, bookkeeping and exits holds a unix timestamp instead.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, InitSpace)]
+pub enum TimeMode {
+    Slot,
+    Timestamp,
+}
+
+impl TimeMode {
+    pub fn current_slot(&self, clock: &Clock) -> u64 {
+        match self {
+            TimeMode::Slot => clock.slot,
+            TimeMode::Timestamp => clock.unix_timestamp as u64,
+        }
+    }
+
+    pub fn duration_from_seconds(&self, seconds: u64) -> u64 {
+        match self {
+            TimeMode::Slot => seconds * 1000 / SLOT_DURATION_MS,
+            TimeMode::Timestamp => seconds,
+        }
+    }
+}
+
 #[account]
 #[derive(InitSpace)]
 pub struct Market {
+    pub authority: Pubkey,
+    pub token_mint_a: Pubkey,
+    pub token_mint_b: Pubkey,
     pub treasury_a: Pubkey,
     pub treasury_b: Pubkey,
+    pub token_program_a: Pubkey,
+    pub token_program_b: Pubkey,
     pub token_a_volume: u64,
     pub token_b_volume: u64,
     pub end_slot_interval: u64,
+    pub time_mode: TimeMode,
     pub start_slot: u64,
+    // Set when the authority closes the market, after which no positions can be opened
     pub end_slot: Option<u64>,
     pub bump: u8,
 }
@@ -16,22 +50,85 @@ pub struct Market {
 impl Market {
     pub const SEED_PREFIX: &'static str = "market";
 
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
+        authority: Pubkey,
+        token_mint_a: Pubkey,
+        token_mint_b: Pubkey,
         treasury_a: Pubkey,
         treasury_b: Pubkey,
+        token_program_a: Pubkey,
+        token_program_b: Pubkey,
         start_slot: u64,
         end_slot_interval: u64,
+        time_mode: TimeMode,
         bump: u8,
     ) -> Self {
         Self {
+            authority,
+            token_mint_a,
+            token_mint_b,
             treasury_a,
             treasury_b,
+            token_program_a,
+            token_program_b,
             token_a_volume: 0,
             token_b_volume: 0,
             end_slot_interval,
+            time_mode,
             start_slot,
             end_slot: None,
             bump,
         }
     }
+
+    pub fn current_slot(&self, clock: &Clock) -> u64 {
+        self.time_mode.current_slot(clock)
+    }
+
+    pub fn minimum_trade_duration(&self) -> u64 {
+        self.time_mode
+            .duration_from_seconds(MINIMUM_TRADE_DURATION_SECONDS)
+    }
+
+    // Latest end slot whose exits bucket is still ahead of the current one in the ring
+    // buffer. Anything later would wrap around onto a bucket that is processed sooner.
+    pub fn max_end_slot(&self, current_slot: u64, exits_length: u64) -> u64 {
+        let slot = current_slot.max(self.start_slot);
+        let current_bucket_slot = slot - slot % self.end_slot_interval;
+
+        current_bucket_slot + (exits_length - 1) * self.end_slot_interval
+    }
+
+    // Start and end slot of a new position. The end slot is rounded to the nearest bucket
+    // boundary of the exits ring buffer and has to lie within its horizon.
+    pub fn position_slots(
+        &self,
+        end_slot: u64,
+        current_slot: u64,
+        exits_length: u64,
+    ) -> Result<(u64, u64)> {
+        if self.end_slot.is_some() {
+            return Err(CustomErrorCode::MarketClosed.into());
+        }
+
+        let start_slot = current_slot.max(self.start_slot);
+
+        let end_slot_interval = self.end_slot_interval;
+        let end_slot = (end_slot + end_slot_interval / 2) / end_slot_interval * end_slot_interval;
+
+        if end_slot < start_slot + end_slot_interval {
+            return Err(CustomErrorCode::EndSlotAlreadyPassed.into());
+        }
+
+        if end_slot - start_slot < self.minimum_trade_duration() {
+            return Err(CustomErrorCode::TradeDurationTooShort.into());
+        }
+
+        if end_slot > self.max_end_slot(current_slot, exits_length) {
+            return Err(CustomErrorCode::EndSlotBeyondHorizon.into());
+        }
+
+        Ok((start_slot, end_slot))
+    }
 }
@@ -1,11 +1,15 @@
 pub mod bookkeeping;
 pub mod exits;
 pub mod market;
+pub mod pool;
 pub mod position;
+pub mod price_archive;
 pub mod prices;
 
 pub use bookkeeping::*;
 pub use exits::*;
 pub use market::*;
+pub use pool::*;
 pub use position::*;
+pub use price_archive::*;
 pub use prices::*;
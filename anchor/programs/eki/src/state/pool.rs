@@ -0,0 +1,167 @@
+use anchor_lang::prelude::*;
+
+use crate::error::CustomErrorCode;
+use crate::math::{isqrt, twamm_end_reserves};
+use crate::{MINIMUM_LIQUIDITY, SWAP_FEE_BPS, VOLUME_PRECISION};
+
+// Constant product reserves of a market that both streams trade against as virtual
+// orders, so flow still fills when the opposite stream is thin or empty. Liquidity
+// providers own them through LP mint shares.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default, InitSpace)]
+pub struct Pool {
+    pub reserve_a: u64,
+    pub reserve_b: u64,
+    pub shares: u64,
+}
+
+impl Pool {
+    pub fn has_liquidity(&self) -> bool {
+        self.reserve_a > 0 && self.reserve_b > 0
+    }
+
+    // Adds liquidity in the current reserve ratio, taking at most the given amounts.
+    // Returns the minted shares and the amounts actually taken. The first deposit locks
+    // MINIMUM_LIQUIDITY of its shares in the pool.
+    pub fn deposit(&mut self, max_a: u64, max_b: u64) -> Result<(u64, u64, u64)> {
+        let (shares, amount_a, amount_b) = if self.shares == 0 {
+            let shares = isqrt(max_a as u128 * max_b as u128) as u64;
+            if shares <= MINIMUM_LIQUIDITY {
+                return Err(CustomErrorCode::DepositTooSmall.into());
+            }
+
+            self.shares = MINIMUM_LIQUIDITY;
+            (shares - MINIMUM_LIQUIDITY, max_a, max_b)
+        } else {
+            if !self.has_liquidity() {
+                return Err(CustomErrorCode::InsufficientLiquidity.into());
+            }
+
+            let total = self.shares as u128;
+            let shares = (max_a as u128 * total / self.reserve_a as u128)
+                .min(max_b as u128 * total / self.reserve_b as u128);
+
+            // round up so existing shares never lose value
+            let amount_a = (shares * self.reserve_a as u128).div_ceil(total);
+            let amount_b = (shares * self.reserve_b as u128).div_ceil(total);
+
+            (shares as u64, amount_a as u64, amount_b as u64)
+        };
+
+        if shares == 0 {
+            return Err(CustomErrorCode::DepositTooSmall.into());
+        }
+
+        self.reserve_a += amount_a;
+        self.reserve_b += amount_b;
+        self.shares += shares;
+
+        Ok((shares, amount_a, amount_b))
+    }
+
+    // Removes shares and returns their part of both reserves
+    pub fn withdraw(&mut self, shares: u64) -> Result<(u64, u64)> {
+        if shares == 0 || shares > self.shares {
+            return Err(CustomErrorCode::InsufficientLiquidity.into());
+        }
+
+        let total = self.shares as u128;
+        let amount_a = (shares as u128 * self.reserve_a as u128 / total) as u64;
+        let amount_b = (shares as u128 * self.reserve_b as u128 / total) as u64;
+
+        self.reserve_a -= amount_a;
+        self.reserve_b -= amount_b;
+        self.shares -= shares;
+
+        Ok((amount_a, amount_b))
+    }
+
+    // Instant constant product swap against the reserves, returns the output amount. The
+    // fee is taken from the input and stays in the reserves.
+    pub fn swap(&mut self, amount_in: u64, a_to_b: bool) -> Result<u64> {
+        if !self.has_liquidity() {
+            return Err(CustomErrorCode::InsufficientLiquidity.into());
+        }
+
+        let (reserve_in, reserve_out) = if a_to_b {
+            (&mut self.reserve_a, &mut self.reserve_b)
+        } else {
+            (&mut self.reserve_b, &mut self.reserve_a)
+        };
+
+        let amount_in_after_fee = amount_in as u128 * (10_000 - SWAP_FEE_BPS) as u128 / 10_000;
+        let amount_out = (*reserve_out as u128 * amount_in_after_fee
+            / (*reserve_in as u128 + amount_in_after_fee)) as u64;
+
+        *reserve_in += amount_in;
+        *reserve_out -= amount_out;
+
+        Ok(amount_out)
+    }
+
+    // Executes the flow of both streams over some slots, in VOLUME_PRECISION token
+    // units, against the pool with the closed form TWAMM solution. Returns what the A and
+    // the B stream receive, and the parts of a token that rounding the reserves down left.
+    pub fn execute(&mut self, in_a: u128, in_b: u128) -> (u128, u128, u128, u128) {
+        let reserve_a = self.reserve_a as u128 * VOLUME_PRECISION as u128;
+        let reserve_b = self.reserve_b as u128 * VOLUME_PRECISION as u128;
+
+        let (end_a, end_b) = twamm_end_reserves(reserve_a, reserve_b, in_a, in_b);
+
+        // reserves are kept in whole tokens, rounded down so the treasury always covers them
+        self.reserve_a = (end_a / VOLUME_PRECISION as u128) as u64;
+        self.reserve_b = (end_b / VOLUME_PRECISION as u128) as u64;
+
+        (
+            (reserve_b + in_b).saturating_sub(end_b),
+            (reserve_a + in_a).saturating_sub(end_a),
+            end_a % VOLUME_PRECISION as u128,
+            end_b % VOLUME_PRECISION as u128,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn locks_minimum_liquidity_on_the_first_deposit() {
+        let mut pool = Pool::default();
+
+        let (shares, amount_a, amount_b) = pool.deposit(4_000, 9_000).unwrap();
+        assert_eq!(
+            (shares, amount_a, amount_b),
+            (6_000 - MINIMUM_LIQUIDITY, 4_000, 9_000)
+        );
+        assert_eq!(pool.shares, 6_000);
+
+        // every minted share can leave, the locked ones keep their part of the reserves
+        assert_eq!(pool.withdraw(shares).unwrap(), (3_333, 7_500));
+        assert_eq!(pool.shares, MINIMUM_LIQUIDITY);
+        assert!(pool.has_liquidity());
+    }
+
+    #[test]
+    fn keeps_the_swap_fee_in_the_reserves() {
+        let mut pool = Pool {
+            reserve_a: 1_000_000,
+            reserve_b: 1_000_000,
+            shares: 1_000_000,
+        };
+
+        // 9_970 of the input trades, without the fee it would have bought 9_900
+        assert_eq!(pool.swap(10_000, true).unwrap(), 9_871);
+        assert_eq!((pool.reserve_a, pool.reserve_b), (1_010_000, 990_129));
+    }
+
+    #[test]
+    fn rejects_a_first_deposit_within_the_locked_shares() {
+        let mut pool = Pool::default();
+
+        assert_eq!(
+            pool.deposit(1_000, 1_000),
+            Err(CustomErrorCode::DepositTooSmall.into())
+        );
+        assert_eq!(pool.shares, 0);
+    }
+}
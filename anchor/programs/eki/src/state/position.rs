@@ -1,6 +1,8 @@
 use anchor_lang::prelude::*;
 
-use crate::VOLUME_PRECISION;
+use crate::error::CustomErrorCode;
+use crate::math::U256;
+use crate::{BOOKKEEPING_PRECISION_FACTOR, DUST_PRECISION, FILL_PRECISION, VOLUME_PRECISION};
 
 #[account]
 #[derive(InitSpace)]
@@ -8,9 +10,16 @@ pub struct PositionA {
     pub amount: u64,
     pub start_slot: u64,
     pub end_slot: u64,
-    pub bookkeeping: u64,
-    pub no_trade_slots: u64,
-    pub total_no_trades: u64,
+    pub bookkeeping: u128,
+    // Bookkeeping unfilled share of this side at the last settlement
+    pub unfilled: u64,
+    pub total_unfilled: u64,
+    // Deposit tokens swapped and refunded as unmatched since the position was opened
+    pub filled: u64,
+    pub refunded: u64,
+    // Part of filled and refunded from before the position was last reduced
+    pub reduced_filled: u64,
+    pub reduced_refunded: u64,
     pub bump: u8,
 }
 
@@ -23,15 +32,113 @@ impl PositionA {
             start_slot,
             end_slot,
             bookkeeping: 0,
-            no_trade_slots: 0,
-            total_no_trades: 0,
+            unfilled: 0,
+            total_unfilled: 0,
+            filled: 0,
+            refunded: 0,
+            reduced_filled: 0,
+            reduced_refunded: 0,
             bump,
         }
     }
 
     pub fn get_volume(&self) -> u64 {
-        let volume = VOLUME_PRECISION * self.amount / (self.end_slot - self.start_slot); // no trading at end slot
-        volume
+        self.wide_volume() as u64
+    }
+
+    // Volumes are kept in u64, which a large deposit over few slots can exceed. A small
+    // deposit over many slots can truncate to no volume, and would all end up as dust.
+    pub fn check_volume(&self) -> Result<()> {
+        let volume = self.wide_volume();
+        if volume > u64::MAX as u128 {
+            return Err(CustomErrorCode::DepositTooLarge.into());
+        }
+
+        if volume == 0 {
+            return Err(CustomErrorCode::DepositTooSmall.into());
+        }
+
+        Ok(())
+    }
+
+    // Deposit per slot in VOLUME_PRECISION, truncated by less than a unit. No trading at
+    // end slot.
+    fn wide_volume(&self) -> u128 {
+        VOLUME_PRECISION as u128 * self.amount as u128 / (self.end_slot - self.start_slot) as u128
+    }
+
+    // Tokens of the other side received between the stored bookkeeping and price, in
+    // DUST_PRECISION
+    pub fn swapped_value(&self, price: u128) -> U256 {
+        U256::from(self.get_volume()) * U256::from(price.wrapping_sub(self.bookkeeping))
+    }
+
+    // Brings the unfilled share up to the bookkeeping of this side at slot, and with it
+    // the filled and refunded totals
+    pub fn settle_fill(&mut self, unfilled: u64, slot: u64) {
+        self.total_unfilled += unfilled - self.unfilled;
+        self.unfilled = unfilled;
+
+        let streamed = slot.min(self.end_slot).saturating_sub(self.start_slot) as u128
+            * self.get_volume() as u128
+            / VOLUME_PRECISION as u128;
+
+        let refunded = self.refund_amount(0);
+        self.refunded = self.reduced_refunded + refunded;
+        self.filled = self.reduced_filled + (streamed as u64).saturating_sub(refunded);
+    }
+
+    // Deposit tokens owed back for the unfilled share and the remaining slots, in
+    // DUST_PRECISION. Exact, so a refund only loses its final rounding down.
+    pub fn refund_value(&self, remaining_slots: u64) -> U256 {
+        let unfilled =
+            remaining_slots as u128 * FILL_PRECISION as u128 + self.total_unfilled as u128;
+
+        U256::from(unfilled)
+            * U256::from(self.get_volume())
+            * U256::from(BOOKKEEPING_PRECISION_FACTOR / FILL_PRECISION as u128)
+    }
+
+    pub fn refund_amount(&self, remaining_slots: u64) -> u64 {
+        (self.refund_value(remaining_slots) / U256::from(DUST_PRECISION)).low_u64()
+    }
+
+    // Gives up amount of the deposit not streamed by slot, once everything owed before
+    // it has been paid out. The position restarts at slot with what's left, streaming a
+    // lower volume until its end slot. Returns the dust of the new deposit, in
+    // DUST_PRECISION.
+    pub fn reduce(&mut self, amount: u64, slot: u64) -> Result<u128> {
+        let start_slot = slot.max(self.start_slot);
+        let duration = (self.end_slot - start_slot) as u128;
+        let unstreamed = self.get_volume() as u128 * duration;
+        let withdrawn = amount as u128 * VOLUME_PRECISION as u128;
+
+        // what's left has to keep streaming a volume of at least one
+        let left = unstreamed.saturating_sub(withdrawn);
+        let amount_left = left / VOLUME_PRECISION as u128;
+        if amount == 0
+            || withdrawn > unstreamed
+            || amount_left * (VOLUME_PRECISION as u128) < duration
+        {
+            return Err(CustomErrorCode::InvalidReduceAmount.into());
+        }
+
+        self.amount = amount_left as u64;
+        self.start_slot = start_slot;
+        self.total_unfilled = 0;
+        self.reduced_filled = self.filled;
+        self.reduced_refunded = self.refunded;
+
+        Ok(left % VOLUME_PRECISION as u128 * BOOKKEEPING_PRECISION_FACTOR + self.volume_dust())
+    }
+
+    // Deposit tokens that don't add up to a whole volume per slot, so they are neither
+    // streamed nor refunded, in DUST_PRECISION
+    pub fn volume_dust(&self) -> u128 {
+        let deposited = self.amount as u128 * VOLUME_PRECISION as u128;
+        let streamed = self.get_volume() as u128 * (self.end_slot - self.start_slot) as u128;
+
+        (deposited - streamed) * BOOKKEEPING_PRECISION_FACTOR
     }
 }
 
@@ -41,9 +148,16 @@ pub struct PositionB {
     pub amount: u64,
     pub start_slot: u64,
     pub end_slot: u64,
-    pub bookkeeping: u64,
-    pub no_trade_slots: u64,
-    pub total_no_trades: u64,
+    pub bookkeeping: u128,
+    // Bookkeeping unfilled share of this side at the last settlement
+    pub unfilled: u64,
+    pub total_unfilled: u64,
+    // Deposit tokens swapped and refunded as unmatched since the position was opened
+    pub filled: u64,
+    pub refunded: u64,
+    // Part of filled and refunded from before the position was last reduced
+    pub reduced_filled: u64,
+    pub reduced_refunded: u64,
     pub bump: u8,
 }
 
@@ -56,14 +170,112 @@ impl PositionB {
             start_slot,
             end_slot,
             bookkeeping: 0,
-            no_trade_slots: 0,
-            total_no_trades: 0,
+            unfilled: 0,
+            total_unfilled: 0,
+            filled: 0,
+            refunded: 0,
+            reduced_filled: 0,
+            reduced_refunded: 0,
             bump,
         }
     }
 
     pub fn get_volume(&self) -> u64 {
-        let volume = VOLUME_PRECISION * self.amount / (self.end_slot - self.start_slot); // no trading at end slot
-        volume
+        self.wide_volume() as u64
+    }
+
+    // Volumes are kept in u64, which a large deposit over few slots can exceed. A small
+    // deposit over many slots can truncate to no volume, and would all end up as dust.
+    pub fn check_volume(&self) -> Result<()> {
+        let volume = self.wide_volume();
+        if volume > u64::MAX as u128 {
+            return Err(CustomErrorCode::DepositTooLarge.into());
+        }
+
+        if volume == 0 {
+            return Err(CustomErrorCode::DepositTooSmall.into());
+        }
+
+        Ok(())
+    }
+
+    // Deposit per slot in VOLUME_PRECISION, truncated by less than a unit. No trading at
+    // end slot.
+    fn wide_volume(&self) -> u128 {
+        VOLUME_PRECISION as u128 * self.amount as u128 / (self.end_slot - self.start_slot) as u128
+    }
+
+    // Tokens of the other side received between the stored bookkeeping and price, in
+    // DUST_PRECISION
+    pub fn swapped_value(&self, price: u128) -> U256 {
+        U256::from(self.get_volume()) * U256::from(price.wrapping_sub(self.bookkeeping))
+    }
+
+    // Brings the unfilled share up to the bookkeeping of this side at slot, and with it
+    // the filled and refunded totals
+    pub fn settle_fill(&mut self, unfilled: u64, slot: u64) {
+        self.total_unfilled += unfilled - self.unfilled;
+        self.unfilled = unfilled;
+
+        let streamed = slot.min(self.end_slot).saturating_sub(self.start_slot) as u128
+            * self.get_volume() as u128
+            / VOLUME_PRECISION as u128;
+
+        let refunded = self.refund_amount(0);
+        self.refunded = self.reduced_refunded + refunded;
+        self.filled = self.reduced_filled + (streamed as u64).saturating_sub(refunded);
+    }
+
+    // Deposit tokens owed back for the unfilled share and the remaining slots, in
+    // DUST_PRECISION. Exact, so a refund only loses its final rounding down.
+    pub fn refund_value(&self, remaining_slots: u64) -> U256 {
+        let unfilled =
+            remaining_slots as u128 * FILL_PRECISION as u128 + self.total_unfilled as u128;
+
+        U256::from(unfilled)
+            * U256::from(self.get_volume())
+            * U256::from(BOOKKEEPING_PRECISION_FACTOR / FILL_PRECISION as u128)
+    }
+
+    pub fn refund_amount(&self, remaining_slots: u64) -> u64 {
+        (self.refund_value(remaining_slots) / U256::from(DUST_PRECISION)).low_u64()
+    }
+
+    // Gives up amount of the deposit not streamed by slot, once everything owed before
+    // it has been paid out. The position restarts at slot with what's left, streaming a
+    // lower volume until its end slot. Returns the dust of the new deposit, in
+    // DUST_PRECISION.
+    pub fn reduce(&mut self, amount: u64, slot: u64) -> Result<u128> {
+        let start_slot = slot.max(self.start_slot);
+        let duration = (self.end_slot - start_slot) as u128;
+        let unstreamed = self.get_volume() as u128 * duration;
+        let withdrawn = amount as u128 * VOLUME_PRECISION as u128;
+
+        // what's left has to keep streaming a volume of at least one
+        let left = unstreamed.saturating_sub(withdrawn);
+        let amount_left = left / VOLUME_PRECISION as u128;
+        if amount == 0
+            || withdrawn > unstreamed
+            || amount_left * (VOLUME_PRECISION as u128) < duration
+        {
+            return Err(CustomErrorCode::InvalidReduceAmount.into());
+        }
+
+        self.amount = amount_left as u64;
+        self.start_slot = start_slot;
+        self.total_unfilled = 0;
+        self.reduced_filled = self.filled;
+        self.reduced_refunded = self.refunded;
+
+        Ok(left % VOLUME_PRECISION as u128 * BOOKKEEPING_PRECISION_FACTOR + self.volume_dust())
+    }
+
+    // Deposit tokens that don't add up to a whole volume per slot, so they are neither
+    // streamed nor refunded, in DUST_PRECISION
+    pub fn volume_dust(&self) -> u128 {
+        let deposited = self.amount as u128 * VOLUME_PRECISION as u128;
+        let streamed = self.get_volume() as u128 * (self.end_slot - self.start_slot) as u128;
+
+        (deposited - streamed) * BOOKKEEPING_PRECISION_FACTOR
     }
 }
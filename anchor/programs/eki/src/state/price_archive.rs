@@ -0,0 +1,70 @@
+use std::mem::size_of;
+
+use anchor_lang::prelude::*;
+use anchor_lang::solana_program::entrypoint::MAX_PERMITTED_DATA_INCREASE;
+
+use crate::error::CustomErrorCode;
+use crate::ANCHOR_DISCRIMINATOR;
+
+use super::PriceCheckpoint;
+
+// Checkpoints per archive page, as many as fit into an account created by the program
+// next to the discriminator and the page header
+pub const PRICE_ARCHIVE_LENGTH: usize =
+    (MAX_PERMITTED_DATA_INCREASE - ANCHOR_DISCRIMINATOR - PriceArchive::HEADER_SPACE)
+        / PriceCheckpoint::INIT_SPACE;
+
+// Overflow page for price checkpoints of one round (epoch) of the exits ring buffer.
+// Checkpoints are moved here once their epoch has passed so positions that ended long
+// ago can still be closed while the prices table stays free for new buckets.
+#[account(zero_copy)]
+#[derive(InitSpace)]
+pub struct PriceArchive {
+    pub market: Pubkey,
+    pub epoch: u64,
+    pub page: u64,
+    pub len: u64,
+    pub checkpoints: [PriceCheckpoint; PRICE_ARCHIVE_LENGTH],
+}
+
+// The header has to list every field before the checkpoints
+const _: () = assert!(
+    PriceArchive::HEADER_SPACE + PRICE_ARCHIVE_LENGTH * PriceCheckpoint::INIT_SPACE
+        == PriceArchive::INIT_SPACE
+);
+const _: () =
+    assert!(ANCHOR_DISCRIMINATOR + PriceArchive::INIT_SPACE <= MAX_PERMITTED_DATA_INCREASE);
+
+impl PriceArchive {
+    pub const SEED_PREFIX: &'static str = "price_archive";
+
+    // market, epoch, page and len
+    pub const HEADER_SPACE: usize = size_of::<Pubkey>() + 3 * size_of::<u64>();
+
+    pub fn is_full(&self) -> bool {
+        self.len as usize == PRICE_ARCHIVE_LENGTH
+    }
+
+    pub fn push(&mut self, checkpoint: PriceCheckpoint) {
+        self.checkpoints[self.len as usize] = checkpoint;
+        self.len += 1;
+    }
+
+    // Same as PricesBuffer::settle, entries are swap removed since the page isn't hashed
+    pub fn settle(&mut self, bucket: u64, volume_a: u64, volume_b: u64) -> Result<PriceCheckpoint> {
+        let len = self.len as usize;
+        let index = self.checkpoints[..len]
+            .iter()
+            .position(|checkpoint| checkpoint.bucket == bucket)
+            .ok_or(CustomErrorCode::MissingPriceCheckpoint)?;
+
+        let checkpoint = self.checkpoints[index];
+        if self.checkpoints[index].settle(volume_a, volume_b) {
+            self.checkpoints[index] = self.checkpoints[len - 1];
+            self.checkpoints[len - 1] = PriceCheckpoint::default();
+            self.len -= 1;
+        }
+
+        Ok(checkpoint)
+    }
+}
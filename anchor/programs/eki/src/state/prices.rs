@@ -1,21 +1,378 @@
+use std::ops::{Deref, DerefMut};
+
 use anchor_lang::prelude::*;
 
-use crate::PRICES_LENGTH;
+use crate::error::CustomErrorCode;
+use crate::ANCHOR_DISCRIMINATOR;
+
+// A u128 kept as two words. u128 is 16 byte aligned on the host but only 8 on chain, so
+// zero-copy accounts can't hold it directly without their layouts differing.
+#[zero_copy]
+#[derive(Default, InitSpace)]
+pub struct PodU128 {
+    pub low: u64,
+    pub high: u64,
+}
+
+impl From<u128> for PodU128 {
+    fn from(value: u128) -> Self {
+        Self {
+            low: value as u64,
+            high: (value >> 64) as u64,
+        }
+    }
+}
+
+impl From<PodU128> for u128 {
+    fn from(value: PodU128) -> Self {
+        (value.high as u128) << 64 | value.low as u128
+    }
+}
+
+// Prices at the boundary of an exits bucket, kept until every position ending there
+// has been closed. Bucket 0 lies before the market start and is never checkpointed,
+// so a zero bucket marks a free entry.
+#[zero_copy]
+#[derive(Default, InitSpace)]
+pub struct PriceCheckpoint {
+    pub bucket: u64,
+    pub a_per_b: PodU128,
+    pub b_per_a: PodU128,
+    pub outstanding_a: u64,
+    pub outstanding_b: u64,
+    pub unfilled_a: u64,
+    pub unfilled_b: u64,
+}
+
+impl PriceCheckpoint {
+    // Returns whether every position ending in this bucket has been settled
+    pub fn settle(&mut self, volume_a: u64, volume_b: u64) -> bool {
+        self.outstanding_a -= volume_a;
+        self.outstanding_b -= volume_b;
+
+        self.outstanding_a == 0 && self.outstanding_b == 0
+    }
+}
 
+// Header of the prices account. It is followed by an open addressing table of length
+// checkpoints keyed by absolute exits bucket, so only buckets that have exits take up
+// space.
 #[account(zero_copy)]
 #[derive(InitSpace)]
 pub struct Prices {
-    pub a_per_b: [u64; PRICES_LENGTH],
-    pub b_per_a: [u64; PRICES_LENGTH],
-    pub no_trade_slots: [u64; PRICES_LENGTH],
+    pub length: u64,
 }
 
 impl Prices {
     pub const SEED_PREFIX: &'static str = "prices";
 
-    pub fn new(&mut self) {
-        self.a_per_b = [0; PRICES_LENGTH];
-        self.b_per_a = [0; PRICES_LENGTH];
-        self.no_trade_slots = [0; PRICES_LENGTH];
+    // Account size needed for a table of length checkpoints
+    pub fn space(length: usize) -> usize {
+        ANCHOR_DISCRIMINATOR + Self::INIT_SPACE + length * PriceCheckpoint::INIT_SPACE
+    }
+}
+
+// Zero-copy view over the whole prices account data
+pub struct PricesBuffer<'a> {
+    header: &'a mut Prices,
+    pub checkpoints: &'a mut [PriceCheckpoint],
+}
+
+impl<'a> PricesBuffer<'a> {
+    pub fn load(data: &'a mut [u8]) -> Result<Self> {
+        let (header, checkpoints) = data[ANCHOR_DISCRIMINATOR..].split_at_mut(Prices::INIT_SPACE);
+        let header: &mut Prices = bytemuck::from_bytes_mut(header);
+        let length = header.length as usize;
+
+        let size = length * PriceCheckpoint::INIT_SPACE;
+        if checkpoints.len() < size {
+            return Err(CustomErrorCode::AccountTooSmall.into());
+        }
+
+        Ok(Self {
+            header,
+            checkpoints: bytemuck::cast_slice_mut(&mut checkpoints[..size]),
+        })
+    }
+
+    pub fn init(data: &'a mut [u8], length: usize) -> Result<Self> {
+        let header: &mut Prices = bytemuck::from_bytes_mut(
+            &mut data[ANCHOR_DISCRIMINATOR..ANCHOR_DISCRIMINATOR + Prices::INIT_SPACE],
+        );
+        header.length = length as u64;
+
+        let prices = Self::load(data)?;
+        prices.checkpoints.fill(PriceCheckpoint::default());
+
+        Ok(prices)
+    }
+
+    // Returns false when the table is full
+    pub fn insert(&mut self, checkpoint: PriceCheckpoint) -> bool {
+        let home = self.home(checkpoint.bucket);
+        let length = self.checkpoints.len();
+
+        for i in 0..length {
+            let entry = &mut self.checkpoints[(home + i) % length];
+            if entry.bucket == 0 {
+                *entry = checkpoint;
+                return true;
+            }
+        }
+
+        false
+    }
+
+    // Removes the volume of a closed position from its checkpoint, freeing the entry once
+    // no position ending in that bucket is left. Returns the checkpoint as it was before.
+    pub fn settle(&mut self, bucket: u64, volume_a: u64, volume_b: u64) -> Option<PriceCheckpoint> {
+        let index = self.find(bucket)?;
+
+        let checkpoint = self.checkpoints[index];
+        if self.checkpoints[index].settle(volume_a, volume_b) {
+            self.remove(index);
+        }
+
+        Some(checkpoint)
+    }
+
+    // Takes out every checkpoint of buckets in the given range, for as long as keep accepts
+    pub fn drain(
+        &mut self,
+        buckets: std::ops::Range<u64>,
+        mut keep: impl FnMut(PriceCheckpoint) -> bool,
+    ) {
+        let mut index = 0;
+        while index < self.checkpoints.len() {
+            let checkpoint = self.checkpoints[index];
+            if checkpoint.bucket == 0 || !buckets.contains(&checkpoint.bucket) {
+                index += 1;
+                continue;
+            }
+
+            if !keep(checkpoint) {
+                return;
+            }
+
+            // backward shift may move another entry into index, so look at it again
+            self.remove(index);
+        }
+    }
+
+    fn home(&self, bucket: u64) -> usize {
+        (bucket % self.checkpoints.len() as u64) as usize
+    }
+
+    fn find(&self, bucket: u64) -> Option<usize> {
+        let home = self.home(bucket);
+        let length = self.checkpoints.len();
+
+        for i in 0..length {
+            let index = (home + i) % length;
+            match self.checkpoints[index].bucket {
+                0 => break,
+                b if b == bucket => return Some(index),
+                _ => {}
+            }
+        }
+
+        None
+    }
+
+    // Backward shift deletion, so lookups never need tombstones
+    fn remove(&mut self, index: usize) {
+        let length = self.checkpoints.len();
+        let mut hole = index;
+
+        for i in 1..length {
+            let next = (index + i) % length;
+            let bucket = self.checkpoints[next].bucket;
+            if bucket == 0 {
+                break;
+            }
+
+            // entries whose home lies cyclically in (hole, next] can't move before it
+            let home = self.home(bucket);
+            let stays = if hole <= next {
+                hole < home && home <= next
+            } else {
+                hole < home || home <= next
+            };
+
+            if !stays {
+                self.checkpoints[hole] = self.checkpoints[next];
+                hole = next;
+            }
+        }
+
+        self.checkpoints[hole] = PriceCheckpoint::default();
+    }
+}
+
+impl Deref for PricesBuffer<'_> {
+    type Target = Prices;
+
+    fn deref(&self) -> &Prices {
+        self.header
+    }
+}
+
+impl DerefMut for PricesBuffer<'_> {
+    fn deref_mut(&mut self) -> &mut Prices {
+        self.header
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Runs test against an empty table of length checkpoints
+    fn with_prices(length: usize, test: impl FnOnce(&mut PricesBuffer)) {
+        let mut data = vec![0u64; Prices::space(length).div_ceil(8)];
+        let mut prices = PricesBuffer::init(bytemuck::cast_slice_mut(&mut data), length).unwrap();
+        test(&mut prices);
+    }
+
+    // Checkpoint with one position outstanding, tagged with its bucket
+    fn checkpoint(bucket: u64) -> PriceCheckpoint {
+        PriceCheckpoint {
+            bucket,
+            a_per_b: (bucket as u128).into(),
+            outstanding_a: 1,
+            ..Default::default()
+        }
+    }
+
+    // Looks bucket up and checks it found the checkpoint inserted for it
+    fn contains(prices: &PricesBuffer, bucket: u64) -> bool {
+        let Some(index) = prices.find(bucket) else {
+            return false;
+        };
+
+        assert_eq!(
+            u128::from(prices.checkpoints[index].a_per_b),
+            bucket as u128
+        );
+        true
+    }
+
+    #[test]
+    fn probes_past_the_end_of_the_table() {
+        with_prices(8, |prices| {
+            // 7, 15 and 23 all hash to the last entry, 8 to the first
+            for bucket in [7, 15, 23, 8] {
+                assert!(prices.insert(checkpoint(bucket)));
+            }
+
+            let buckets: Vec<u64> = prices.checkpoints.iter().map(|c| c.bucket).collect();
+            assert_eq!(buckets, [15, 23, 8, 0, 0, 0, 0, 7]);
+            for bucket in [7, 15, 23, 8] {
+                assert!(contains(prices, bucket));
+            }
+            assert!(!contains(prices, 31));
+        });
+    }
+
+    #[test]
+    fn refuses_inserts_into_a_full_table() {
+        with_prices(2, |prices| {
+            assert!(prices.insert(checkpoint(1)));
+            assert!(prices.insert(checkpoint(3)));
+            assert!(!prices.insert(checkpoint(5)));
+
+            assert!(!contains(prices, 5));
+            assert!(contains(prices, 1) && contains(prices, 3));
+        });
+    }
+
+    #[test]
+    fn removes_from_the_middle_of_a_probe_chain() {
+        with_prices(8, |prices| {
+            for bucket in [7, 15, 23, 8] {
+                assert!(prices.insert(checkpoint(bucket)));
+            }
+
+            // the last position of 15 closes, entries behind it shift back across the wrap
+            assert_eq!(prices.settle(15, 1, 0).unwrap().bucket, 15);
+
+            let buckets: Vec<u64> = prices.checkpoints.iter().map(|c| c.bucket).collect();
+            assert_eq!(buckets, [23, 8, 0, 0, 0, 0, 0, 7]);
+            assert!(!contains(prices, 15));
+            for bucket in [7, 23, 8] {
+                assert!(contains(prices, bucket));
+            }
+
+            // an entry sitting at its home doesn't move before it
+            prices.settle(8, 1, 0).unwrap();
+            assert!(prices.insert(checkpoint(1)));
+            prices.settle(23, 1, 0).unwrap();
+            let buckets: Vec<u64> = prices.checkpoints.iter().map(|c| c.bucket).collect();
+            assert_eq!(buckets, [0, 1, 0, 0, 0, 0, 0, 7]);
+            for bucket in [7, 1] {
+                assert!(contains(prices, bucket));
+            }
+        });
+    }
+
+    #[test]
+    fn keeps_checkpoints_with_positions_outstanding() {
+        with_prices(4, |prices| {
+            let mut two_positions = checkpoint(5);
+            two_positions.outstanding_b = 1;
+            assert!(prices.insert(two_positions));
+
+            prices.settle(5, 1, 0).unwrap();
+            assert!(contains(prices, 5));
+            prices.settle(5, 0, 1).unwrap();
+            assert!(!contains(prices, 5));
+            assert!(prices.settle(5, 0, 0).is_none());
+        });
+    }
+
+    #[test]
+    fn finds_what_is_left_after_draining() {
+        with_prices(8, |prices| {
+            for bucket in [7, 15, 23, 8, 9, 3] {
+                assert!(prices.insert(checkpoint(bucket)));
+            }
+
+            let mut drained = vec![];
+            prices.drain(8..16, |checkpoint| {
+                drained.push(checkpoint.bucket);
+                true
+            });
+            drained.sort();
+
+            assert_eq!(drained, [8, 9, 15]);
+            for bucket in [8, 9, 15] {
+                assert!(!contains(prices, bucket));
+            }
+            for bucket in [7, 23, 3] {
+                assert!(contains(prices, bucket));
+            }
+        });
+    }
+
+    #[test]
+    fn stops_draining_when_refused() {
+        with_prices(8, |prices| {
+            for bucket in [7, 15, 23, 8] {
+                assert!(prices.insert(checkpoint(bucket)));
+            }
+
+            // room for a single checkpoint, the rest stays in the table
+            let mut drained = vec![];
+            prices.drain(0..32, |checkpoint| {
+                drained.push(checkpoint.bucket);
+                drained.len() < 2
+            });
+
+            assert_eq!(drained.len(), 2);
+            assert!(!contains(prices, drained[0]));
+            for bucket in [7, 15, 23, 8] {
+                assert_eq!(contains(prices, bucket), bucket != drained[0]);
+            }
+        });
     }
 }
@@ -18,8 +18,14 @@ import { idWallet, loadKeypairFromFile } from "./helpers";
 process.env.ANCHOR_PROVIDER_URL = "http://127.0.0.1:8899";
 process.env.ANCHOR_WALLET = idWallet;
 
-const EXITS_ACCOUNT_SIZE = 6720024; // check account size in program
-const PRICES_ACCOUNT_SIZE = 10080008; // check account size in program
+const EXITS_LENGTH = 420000;
+const PRICES_LENGTH = 16384;
+// must match Exits::space and Prices::space in the program
+const EXITS_INDEX_LENGTH = Math.ceil(EXITS_LENGTH / 64);
+const EXITS_BUFFER_WORDS =
+  2 * EXITS_LENGTH + EXITS_INDEX_LENGTH + Math.ceil(EXITS_INDEX_LENGTH / 64);
+const EXITS_ACCOUNT_SIZE = 8 + 24 + EXITS_BUFFER_WORDS * 8;
+const PRICES_ACCOUNT_SIZE = 8 + 8 + PRICES_LENGTH * 72;
 
 // Original USCD mint address
 // const USDC_MINT = new PublicKey("EPjFWdd5AufqSSqeM2qN1xzybapC8G4wEGGkZwyTDt1v");
@@ -100,7 +106,8 @@ const USDC_MINT = new PublicKey("95eBaAbEAZvxngRgNL4qmikTcYhzHNBwxKTuT2p7SGU");
   });
 
   const accounts: Record<string, PublicKey> = {
-    tokenProgram: TOKEN_PROGRAM_ID,
+    tokenProgramA: TOKEN_PROGRAM_ID,
+    tokenProgramB: TOKEN_PROGRAM_ID,
     tokenMintA: SOL_MINT,
     tokenMintB: USDC_MINT,
     market: market,
@@ -122,7 +129,13 @@ const USDC_MINT = new PublicKey("95eBaAbEAZvxngRgNL4qmikTcYhzHNBwxKTuT2p7SGU");
       .accounts({ ...accounts })
       .instruction(),
     await program.methods
-      .initializeMarket(new BN(startSlot), new BN(100))
+      .initializeMarket(
+        new BN(startSlot),
+        new BN(100),
+        { slot: {} },
+        new BN(EXITS_LENGTH),
+        new BN(PRICES_LENGTH)
+      )
       .accounts({ ...accounts })
       .instruction(),
   ];
@@ -21,18 +21,23 @@ import { BankrunProvider, startAnchor } from "anchor-bankrun";
 import IDL from "../target/idl/eki.json";
 import { BanksClient, ProgramTestContext } from "solana-bankrun";
 import { SYSTEM_PROGRAM_ID } from "@coral-xyz/anchor/dist/cjs/native/system";
-import { createTransferWrapSolTx } from "./utils";
+import {
+  createTransferWrapSolTx,
+  exitsAccountSize,
+  fetchExitsBucket,
+  pricesAccountSize,
+} from "./utils";
 
 const TOKEN_PROGRAM: typeof TOKEN_2022_PROGRAM_ID | typeof TOKEN_PROGRAM_ID =
   TOKEN_2022_PROGRAM_ID;
 
 const NUM_USERS = 10;
 
-const EXITS_LENGTH = 640000; // must be the same as in the program
-const EXITS_ACCOUNT_SIZE = 10240024; // check account size in program (+Discriminator size)
-const PRICES_ACCOUNT_SIZE = 10080008; // check account size in program (+Discriminator size)
-const BOOKKEEPING_PRECISION = 1_000_000; // must be the same as BOOKKEEPING_PRECISION in the program
+const EXITS_LENGTH = 640000;
+const PRICES_LENGTH = 16384;
+const BOOKKEEPING_PRECISION = new BN("1000000000000000000"); // must be the same as BOOKKEEPING_PRECISION in the program
 const VOLUME_PRECISION = 1_000_000; // must be the same as VOLUME_PRECISION in the program
+const FILL_PRECISION = 1_000_000; // must be the same as FILL_PRECISION in the program
 
 describe("eki", () => {
   let program = anchor.workspace.Eki as Program<Eki>;
@@ -80,7 +85,8 @@ describe("eki", () => {
   const allPositionsB = Array(NUM_USERS);
 
   const accounts: Record<string, PublicKey> = {
-    tokenProgram: TOKEN_PROGRAM_ID,
+    tokenProgramA: TOKEN_PROGRAM_ID,
+    tokenProgramB: TOKEN_PROGRAM_ID,
     tokenMintA: tokenAMint,
     tokenMintB: usdcMint,
   };
@@ -240,7 +246,7 @@ describe("eki", () => {
     const createExitsAccountIx = SystemProgram.createAccount({
       fromPubkey: userKeypairs[0].publicKey,
       newAccountPubkey: exits.publicKey,
-      space: EXITS_ACCOUNT_SIZE,
+      space: exitsAccountSize(EXITS_LENGTH),
       // lamports: await getMinimumBalanceForRentExemptAccount(
       //   provider.connection
       // ),
@@ -251,7 +257,7 @@ describe("eki", () => {
     const createPricesAccountIx = SystemProgram.createAccount({
       fromPubkey: userKeypairs[0].publicKey,
       newAccountPubkey: prices.publicKey,
-      space: PRICES_ACCOUNT_SIZE,
+      space: pricesAccountSize(PRICES_LENGTH),
       // lamports: await getMinimumBalanceForRentExemptAccount(
       //   provider.connection
       // ),
@@ -280,7 +286,13 @@ describe("eki", () => {
         .accounts({ ...accounts })
         .instruction(),
       await program.methods
-        .initializeMarket(new BN(startSlot), new BN(endSlotInterval))
+        .initializeMarket(
+          new BN(startSlot),
+          new BN(endSlotInterval),
+          { slot: {} },
+          new BN(EXITS_LENGTH),
+          new BN(PRICES_LENGTH)
+        )
         .accounts({ ...accounts })
         .instruction(),
     ];
@@ -300,6 +312,18 @@ describe("eki", () => {
     expect(marketAccount.treasuryB.toString()).toStrictEqual(
       accounts.treasuryB.toString()
     );
+    expect(marketAccount.tokenMintA.toString()).toStrictEqual(
+      accounts.tokenMintA.toString()
+    );
+    expect(marketAccount.tokenMintB.toString()).toStrictEqual(
+      accounts.tokenMintB.toString()
+    );
+    expect(marketAccount.tokenProgramA.toString()).toStrictEqual(
+      accounts.tokenProgramA.toString()
+    );
+    expect(marketAccount.tokenProgramB.toString()).toStrictEqual(
+      accounts.tokenProgramB.toString()
+    );
     expect(marketAccount.tokenAVolume.toString()).toStrictEqual("0");
     expect(marketAccount.tokenBVolume.toString()).toStrictEqual("0");
     expect(marketAccount.startSlot.toNumber()).toStrictEqual(startSlot);
@@ -312,7 +336,6 @@ describe("eki", () => {
     const bookkeepingAccount =
       await program.account.bookkeeping.fetch(bookkeeping);
     expect(bookkeepingAccount.aPerB.toNumber()).toStrictEqual(0);
-    expect(bookkeepingAccount.noTradeSlots.toNumber()).toStrictEqual(0);
     expect(bookkeepingAccount.bPerA.toNumber()).toStrictEqual(0);
     expect(bookkeepingAccount.lastSlot.toNumber()).toStrictEqual(
       marketAccount.startSlot.toNumber()
@@ -406,10 +429,8 @@ describe("eki", () => {
       endSlotInterval
     );
     expect(positionAccount.bookkeeping.toNumber()).toStrictEqual(0);
-    expect(positionAccount.noTradeSlots.toNumber()).toStrictEqual(0);
     expect(startPositionSlot).toStrictEqual(marketAccount.startSlot.toNumber()); // start slot for position was before market start slot
     expect(endPositionSlot % endSlotInterval).toStrictEqual(0);
-    expect(positionAccount.totalNoTrades.toNumber()).toStrictEqual(0);
     expect(positionAccount.bump).toStrictEqual(positionBump);
 
     // Bookkeeping Account
@@ -418,14 +439,18 @@ describe("eki", () => {
     );
     expect(bookkeepingAccount.aPerB.toNumber()).toStrictEqual(0);
     expect(bookkeepingAccount.bPerA.toNumber()).toStrictEqual(0);
-    expect(bookkeepingAccount.noTradeSlots.toNumber()).toStrictEqual(0);
 
     // Exits
     const exitsAccount = await program.account.exits.fetch(accounts.exits);
     const pointer = (endPositionSlot - startSlot) / endSlotInterval;
+    const exitsBucket = await fetchExitsBucket(
+      context,
+      accounts.exits,
+      pointer
+    );
     expect(exitsAccount.pointer.toNumber()).toStrictEqual(0);
     expect(
-      Math.floor(exitsAccount.tokenA[pointer].toNumber() / VOLUME_PRECISION)
+      Math.floor(exitsBucket.tokenA.toNumber() / VOLUME_PRECISION)
     ).toStrictEqual(volume);
 
     // Treasury Account
@@ -487,10 +512,14 @@ describe("eki", () => {
       endSlotInterval
     );
     expect(positionAccount.bookkeeping.toNumber()).toStrictEqual(0); // still no trade since both deposits are on token A
-    expect(positionAccount.noTradeSlots.toNumber()).toStrictEqual(jumpSlots);
+    // nothing of either stream was filled while token B had no volume
+    expect(positionAccount.unfilled.toNumber()).toStrictEqual(
+      jumpSlots * FILL_PRECISION
+    );
+    expect(positionAccount.filled.toNumber()).toStrictEqual(0);
+    expect(positionAccount.refunded.toNumber()).toStrictEqual(0);
     expect(startPositionSlot).toStrictEqual(startSlot + jumpSlots);
     expect(endPositionSlot % endSlotInterval).toStrictEqual(0);
-    expect(positionAccount.totalNoTrades.toNumber()).toStrictEqual(0);
     expect(positionAccount.bump).toStrictEqual(positionBump);
 
     // Market Account
@@ -514,16 +543,26 @@ describe("eki", () => {
     );
     expect(bookkeepingAccount.aPerB.toNumber()).toStrictEqual(0);
     expect(bookkeepingAccount.bPerA.toNumber()).toStrictEqual(0);
-    expect(bookkeepingAccount.noTradeSlots.toNumber()).toStrictEqual(jumpSlots);
+    expect(bookkeepingAccount.unfilledA.toNumber()).toStrictEqual(
+      jumpSlots * FILL_PRECISION
+    );
+    expect(bookkeepingAccount.unfilledB.toNumber()).toStrictEqual(
+      jumpSlots * FILL_PRECISION
+    );
 
     // Exits
     const exitsAccount = await program.account.exits.fetch(accounts.exits);
     const pointer = (endPositionSlot - startSlot) / endSlotInterval;
+    const exitsBucket = await fetchExitsBucket(
+      context,
+      accounts.exits,
+      pointer
+    );
     expect(exitsAccount.pointer.toNumber()).toStrictEqual(
       (startPositionSlot - exitsAccount.startSlot.toNumber()) / endSlotInterval
     );
     expect(
-      Math.floor(exitsAccount.tokenA[pointer].toNumber() / VOLUME_PRECISION)
+      Math.floor(exitsBucket.tokenA.toNumber() / VOLUME_PRECISION)
     ).toStrictEqual(volume); // because this and the previous position end at same time
 
     // Treasury Account
@@ -576,12 +615,8 @@ describe("eki", () => {
       endSlotInterval
     );
     expect(positionAccount.bookkeeping.toNumber()).toStrictEqual(0); // still no trade since this is first deposit on token B
-    expect(positionAccount.noTradeSlots.toNumber()).toStrictEqual(
-      Number(current_slot) - startSlot
-    );
     expect(startPositionSlot).toStrictEqual(Number(current_slot));
     expect(endPositionSlot % endSlotInterval).toStrictEqual(0);
-    expect(positionAccount.totalNoTrades.toNumber()).toStrictEqual(0);
     expect(positionAccount.bump).toStrictEqual(positionBump);
 
     // Market Account
@@ -597,9 +632,6 @@ describe("eki", () => {
     );
     expect(bookkeepingAccount.aPerB.toNumber()).toStrictEqual(0);
     expect(bookkeepingAccount.bPerA.toNumber()).toStrictEqual(0);
-    expect(bookkeepingAccount.noTradeSlots.toNumber()).toStrictEqual(
-      Number(current_slot) - startSlot
-    );
 
     // // Exits
     // If uncommented the following test case fail due to not found blockhash
@@ -669,12 +701,8 @@ describe("eki", () => {
       endSlotInterval
     );
     expect(positionAccount.bookkeeping.toNumber()).toStrictEqual(0); // still no trade since deposits happened on same slot
-    expect(positionAccount.noTradeSlots.toNumber()).toStrictEqual(
-      Number(currentSlot) - startSlot
-    );
     expect(startPositionSlot).toStrictEqual(Number(currentSlot));
     expect(endPositionSlot % endSlotInterval).toStrictEqual(0);
-    expect(positionAccount.totalNoTrades.toNumber()).toStrictEqual(0);
     expect(positionAccount.bump).toStrictEqual(positionBump);
 
     // Market Account
@@ -704,18 +732,20 @@ describe("eki", () => {
     );
     expect(bookkeepingAccount.aPerB.toNumber()).toStrictEqual(0);
     expect(bookkeepingAccount.bPerA.toNumber()).toStrictEqual(0);
-    expect(bookkeepingAccount.noTradeSlots.toNumber()).toStrictEqual(
-      Number(currentSlot) - startSlot
-    );
 
     // Exits Account
     const exitsAccount = await program.account.exits.fetch(accounts.exits);
     const pointer = (endPositionSlot - startSlot) / endSlotInterval;
+    const exitsBucket = await fetchExitsBucket(
+      context,
+      accounts.exits,
+      pointer
+    );
     expect(exitsAccount.pointer.toNumber()).toStrictEqual(
       (startPositionSlot - exitsAccount.startSlot.toNumber()) / endSlotInterval
     );
     expect(
-      Math.floor(exitsAccount.tokenA[pointer].toNumber() / VOLUME_PRECISION)
+      Math.floor(exitsBucket.tokenA.toNumber() / VOLUME_PRECISION)
     ).toStrictEqual(
       Math.floor(
         depositAmount / (endSlot - positionAccount.startSlot.toNumber())
@@ -817,27 +847,21 @@ describe("eki", () => {
       endSlotInterval
     );
 
-    expect(
-      Math.floor(positionAccount.bookkeeping.toNumber() / BOOKKEEPING_PRECISION)
-    ).toStrictEqual(
-      Math.floor(
-        ((currentSlot - lastSlot) *
-          Math.floor(
-            (marketAccount.tokenBVolume.toNumber() * BOOKKEEPING_PRECISION) /
-              (marketAccount.tokenAVolume.toNumber() -
-                (depositAmount * VOLUME_PRECISION) / (endSlot - currentSlot))
-          )) /
-          BOOKKEEPING_PRECISION
-      )
+    // prices advance by the exact volume ratio over all slots since the last update
+    const slots = new BN(currentSlot - lastSlot);
+    const previousVolumeA = marketAccount.tokenAVolume.sub(
+      new BN(depositAmount)
+        .muln(VOLUME_PRECISION)
+        .div(new BN(endSlot - currentSlot))
     );
+    const volumeB = marketAccount.tokenBVolume;
+    expect(
+      positionAccount.bookkeeping.div(BOOKKEEPING_PRECISION).toNumber()
+    ).toStrictEqual(slots.mul(volumeB).div(previousVolumeA).toNumber());
 
-    expect(positionAccount.noTradeSlots.toNumber()).toStrictEqual(
-      lastSlot - startSlot
-    );
     expect(startPositionSlot).toStrictEqual(currentSlot);
     expect(endPositionSlot).toStrictEqual(endSlot);
     expect(endPositionSlot % endSlotInterval).toStrictEqual(0);
-    expect(positionAccount.totalNoTrades.toNumber()).toStrictEqual(0);
     expect(positionAccount.bump).toStrictEqual(positionBump);
 
     // Bookkeeping Account
@@ -845,44 +869,25 @@ describe("eki", () => {
       accounts.bookkeeping
     );
     expect(
-      Math.floor(bookkeepingAccount.aPerB.toNumber() / BOOKKEEPING_PRECISION)
-    ).toStrictEqual(
-      Math.floor(
-        ((currentSlot - lastSlot) *
-          Math.floor(
-            ((marketAccount.tokenAVolume.toNumber() -
-              (depositAmount * VOLUME_PRECISION) / (endSlot - currentSlot)) *
-              BOOKKEEPING_PRECISION) /
-              marketAccount.tokenBVolume.toNumber()
-          )) /
-          BOOKKEEPING_PRECISION
-      )
-    );
+      bookkeepingAccount.aPerB.div(BOOKKEEPING_PRECISION).toNumber()
+    ).toStrictEqual(slots.mul(previousVolumeA).div(volumeB).toNumber());
     expect(
-      Math.floor(bookkeepingAccount.bPerA.toNumber() / BOOKKEEPING_PRECISION)
-    ).toStrictEqual(
-      Math.floor(
-        ((currentSlot - lastSlot) *
-          Math.floor(
-            (marketAccount.tokenBVolume.toNumber() * BOOKKEEPING_PRECISION) /
-              (marketAccount.tokenAVolume.toNumber() -
-                (depositAmount * VOLUME_PRECISION) / (endSlot - currentSlot))
-          )) /
-          BOOKKEEPING_PRECISION
-      )
-    );
-    expect(bookkeepingAccount.noTradeSlots.toNumber()).toStrictEqual(
-      lastSlot - startSlot
-    );
+      bookkeepingAccount.bPerA.div(BOOKKEEPING_PRECISION).toNumber()
+    ).toStrictEqual(slots.mul(volumeB).div(previousVolumeA).toNumber());
 
     // Exits Account
     const exitsAccount = await program.account.exits.fetch(accounts.exits);
     const pointer = (endPositionSlot - startSlot) / endSlotInterval;
+    const exitsBucket = await fetchExitsBucket(
+      context,
+      accounts.exits,
+      pointer
+    );
     expect(exitsAccount.pointer.toNumber()).toStrictEqual(
       (startPositionSlot - exitsAccount.startSlot.toNumber()) / endSlotInterval
     );
     expect(
-      Math.floor(exitsAccount.tokenA[pointer].toNumber() / VOLUME_PRECISION)
+      Math.floor(exitsBucket.tokenA.toNumber() / VOLUME_PRECISION)
     ).toStrictEqual(
       Math.floor(
         depositAmount / (endSlot - positionAccount.startSlot.toNumber())
@@ -917,9 +922,10 @@ describe("eki", () => {
     accounts.withdrawerTokenAccount = tokenAtas[userId];
 
     await program.methods
-      .withdrawSwappedTokenA()
+      .withdrawSwappedTokenA(false)
       .accounts({
         ...accounts,
+        priceArchive: null,
         withdrawer: userKeypairs[userId].publicKey,
         // depositorTokenAccount: atas[0],
         // positionA: position,
@@ -988,16 +994,26 @@ describe("eki", () => {
     const startPositionSlot = positionAccount.startSlot.toNumber();
     const endPositionSlot = positionAccount.endSlot.toNumber();
 
-    // need to be calculated with variables
-    expect(positionAccount.noTradeSlots.toNumber()).toStrictEqual(5000);
-    expect(positionAccount.totalNoTrades.toNumber()).toStrictEqual(0);
+    // everything the position streamed so far was either filled or refunded
+    const volume = positionAccount.amount
+      .muln(VOLUME_PRECISION)
+      .divn(endPositionSlot - startPositionSlot);
+    const streamed = volume
+      .muln(Math.min(currentSlot, endPositionSlot) - startPositionSlot)
+      .divn(VOLUME_PRECISION);
+    expect(
+      positionAccount.filled.add(positionAccount.refunded).toString()
+    ).toStrictEqual(streamed.toString());
 
     // Bookkeeping Account
     const bookkeepingAccount = await program.account.bookkeeping.fetch(
       accounts.bookkeeping
     );
-    expect(Math.floor(bookkeepingAccount.aPerB.toNumber())).toStrictEqual(
-      positionAccount.bookkeeping.toNumber()
+    expect(bookkeepingAccount.aPerB.toString()).toStrictEqual(
+      positionAccount.bookkeeping.toString()
+    );
+    expect(positionAccount.unfilled.toNumber()).toStrictEqual(
+      bookkeepingAccount.unfilledB.toNumber()
     );
 
     // Exits Account
@@ -1029,9 +1045,10 @@ describe("eki", () => {
     accounts.withdrawerTokenAccount = usdcAtas[userId];
 
     await program.methods
-      .withdrawSwappedTokenB()
+      .withdrawSwappedTokenB(false)
       .accounts({
         ...accounts,
+        priceArchive: null,
         withdrawer: userKeypairs[userId].publicKey,
         // depositorTokenAccount: atas[0],
         // positionA: position,
@@ -1065,10 +1082,11 @@ describe("eki", () => {
     accounts.positionA = positionA;
 
     await program.methods
-      .closePositionA()
+      .closePositionA(false)
       .accounts({
         ...accounts,
         signer: userKeypairs[userId].publicKey,
+        priceArchive: null,
         // depositorTokenAccount: atas[0],
         // positionA: position,
       })
@@ -1171,10 +1189,11 @@ describe("eki", () => {
       accounts.positionA = positionA;
 
       await program.methods
-        .closePositionA()
+        .closePositionA(false)
         .accounts({
           ...accounts,
           signer: userKeypairs[userAIds[i]].publicKey,
+          priceArchive: null,
         })
         .signers([userKeypairs[userAIds[i]]])
         .rpc({ skipPreflight: true });
@@ -1194,10 +1213,11 @@ describe("eki", () => {
     accounts.positionB = positionB;
 
     await program.methods
-      .closePositionB()
+      .closePositionB(false)
       .accounts({
         ...accounts,
         signer: userKeypairs[userId].publicKey,
+        priceArchive: null,
       })
       .signers([userKeypairs[userId]])
       .rpc({ skipPreflight: true });
@@ -0,0 +1,243 @@
+import { BN, Program } from "@coral-xyz/anchor";
+import type { Eki } from "../target/types/eki";
+import {
+  getAssociatedTokenAddressSync,
+  NATIVE_MINT,
+  TOKEN_PROGRAM_ID,
+} from "@solana/spl-token";
+import { Keypair, LAMPORTS_PER_SOL, PublicKey } from "@solana/web3.js";
+import { makeKeypairs } from "@solana-developers/helpers";
+import { BanksClient, ProgramTestContext } from "solana-bankrun";
+import { createFundedMint, setupMarket, tokenBalance } from "./utils";
+
+const EXITS_LENGTH = 1024;
+const PRICES_LENGTH = 64;
+const DECIMALS = 6;
+
+describe("eki with native SOL", () => {
+  let program: Program<Eki>;
+  let context: ProgramTestContext;
+  let banksClient: BanksClient;
+  let accounts: Record<string, PublicKey>;
+
+  const [depositorA, depositorB] = makeKeypairs(2);
+  const mintB = Keypair.generate();
+
+  const depositAmountA = 10 * LAMPORTS_PER_SOL;
+  const depositAmountB = 1_000 * 10 ** DECIMALS;
+  const startSlot = 100;
+  const endSlotInterval = 100;
+
+  const ata = (mint: PublicKey, owner: PublicKey) =>
+    getAssociatedTokenAddressSync(mint, owner, false, TOKEN_PROGRAM_ID);
+
+  beforeAll(async () => {
+    ({ context, program, accounts } = await setupMarket({
+      interval: endSlotInterval,
+      startSlot,
+      exitsLength: EXITS_LENGTH,
+      pricesLength: PRICES_LENGTH,
+      users: [depositorA, depositorB],
+      // token A is native SOL, which the depositors hold as lamports
+      mints: async (context, provider) => {
+        await createFundedMint(
+          context,
+          provider.wallet.payer,
+          mintB,
+          DECIMALS,
+          [depositorB.publicKey],
+          depositAmountB
+        );
+
+        return {
+          tokenMintA: NATIVE_MINT,
+          tokenMintB: mintB.publicKey,
+          tokenProgramA: TOKEN_PROGRAM_ID,
+          tokenProgramB: TOKEN_PROGRAM_ID,
+        };
+      },
+    }));
+    banksClient = context.banksClient;
+
+    accounts.treasuryA = PublicKey.findProgramAddressSync(
+      [Buffer.from("treasury_a"), accounts.market.toBuffer()],
+      program.programId
+    )[0];
+    accounts.treasuryB = PublicKey.findProgramAddressSync(
+      [Buffer.from("treasury_b"), accounts.market.toBuffer()],
+      program.programId
+    )[0];
+  });
+
+  it("deposits native SOL without a wrapped SOL account", async () => {
+    const endSlot = startSlot + endSlotInterval * 10;
+    const lamportsBefore = (await banksClient.getAccount(depositorA.publicKey))!
+      .lamports;
+
+    await program.methods
+      .depositTokenA(new BN(depositAmountA), new BN(endSlot))
+      .accounts({
+        ...accounts,
+        depositor: depositorA.publicKey,
+        depositorTokenAccount: null,
+      })
+      .signers([depositorA])
+      .rpc();
+
+    const lamportsAfter = (await banksClient.getAccount(depositorA.publicKey))!
+      .lamports;
+    expect(lamportsBefore - lamportsAfter).toBeGreaterThanOrEqual(
+      depositAmountA
+    );
+    expect(await tokenBalance(context, accounts.treasuryA)).toBe(
+      depositAmountA
+    );
+    expect(
+      await banksClient.getAccount(ata(NATIVE_MINT, depositorA.publicKey))
+    ).toBeNull();
+  });
+
+  it("requires a token account for non-native deposits", async () => {
+    await expect(
+      program.methods
+        .depositTokenB(new BN(depositAmountB), new BN(startSlot * 10))
+        .accounts({
+          ...accounts,
+          depositor: depositorB.publicKey,
+          depositorTokenAccount: null,
+        })
+        .signers([depositorB])
+        .rpc()
+    ).rejects.toThrow();
+
+    await program.methods
+      .depositTokenB(new BN(depositAmountB), new BN(startSlot * 10))
+      .accounts({
+        ...accounts,
+        depositor: depositorB.publicKey,
+        depositorTokenAccount: ata(mintB.publicKey, depositorB.publicKey),
+      })
+      .signers([depositorB])
+      .rpc();
+
+    expect(await tokenBalance(context, accounts.treasuryB)).toBe(
+      depositAmountB
+    );
+  });
+
+  it("withdraws swapped SOL as lamports", async () => {
+    context.warpToSlot(BigInt(startSlot + endSlotInterval * 5));
+
+    const [positionB] = PublicKey.findProgramAddressSync(
+      [
+        Buffer.from("position_b"),
+        accounts.market.toBuffer(),
+        depositorB.publicKey.toBuffer(),
+      ],
+      program.programId
+    );
+    const lamportsBefore = (await banksClient.getAccount(depositorB.publicKey))!
+      .lamports;
+
+    await program.methods
+      .withdrawSwappedTokenA(true)
+      .accounts({
+        ...accounts,
+        priceArchive: null,
+        withdrawer: depositorB.publicKey,
+        positionB,
+      })
+      .signers([depositorB])
+      .rpc();
+
+    const lamportsAfter = (await banksClient.getAccount(depositorB.publicKey))!
+      .lamports;
+    expect(lamportsAfter).toBeGreaterThan(lamportsBefore);
+    // only the payout is unwrapped, the wrapped SOL account stays open
+    expect(
+      await tokenBalance(context, ata(NATIVE_MINT, depositorB.publicKey))
+    ).toBe(0);
+  });
+
+  it("rejects unwrapping a non-native side", async () => {
+    const [positionA] = PublicKey.findProgramAddressSync(
+      [
+        Buffer.from("position_a"),
+        accounts.market.toBuffer(),
+        depositorA.publicKey.toBuffer(),
+      ],
+      program.programId
+    );
+
+    await expect(
+      program.methods
+        .withdrawSwappedTokenB(true)
+        .accounts({
+          ...accounts,
+          priceArchive: null,
+          withdrawer: depositorA.publicKey,
+          positionA,
+        })
+        .signers([depositorA])
+        .rpc()
+    ).rejects.toThrow();
+  });
+
+  it("rejects a mint that does not belong to the market", async () => {
+    const [positionB] = PublicKey.findProgramAddressSync(
+      [
+        Buffer.from("position_b"),
+        accounts.market.toBuffer(),
+        depositorB.publicKey.toBuffer(),
+      ],
+      program.programId
+    );
+
+    await expect(
+      program.methods
+        .withdrawSwappedTokenA(false)
+        .accounts({
+          ...accounts,
+          priceArchive: null,
+          tokenMintA: mintB.publicKey,
+          withdrawer: depositorB.publicKey,
+          withdrawerTokenAccount: ata(mintB.publicKey, depositorB.publicKey),
+          positionB,
+        })
+        .signers([depositorB])
+        .rpc()
+    ).rejects.toThrow();
+  });
+
+  it("closes a native position and unwraps the refund", async () => {
+    context.warpToSlot(BigInt(startSlot + endSlotInterval * 20));
+
+    const [positionA] = PublicKey.findProgramAddressSync(
+      [
+        Buffer.from("position_a"),
+        accounts.market.toBuffer(),
+        depositorA.publicKey.toBuffer(),
+      ],
+      program.programId
+    );
+
+    await program.methods
+      .closePositionA(true)
+      .accounts({
+        ...accounts,
+        signer: depositorA.publicKey,
+        priceArchive: null,
+        positionA,
+      })
+      .signers([depositorA])
+      .rpc();
+
+    expect(await banksClient.getAccount(positionA)).toBeNull();
+    expect(
+      await tokenBalance(context, ata(NATIVE_MINT, depositorA.publicKey))
+    ).toBe(0);
+    expect(
+      await tokenBalance(context, ata(mintB.publicKey, depositorA.publicKey))
+    ).toBeGreaterThan(0);
+  });
+});
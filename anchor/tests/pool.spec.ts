@@ -0,0 +1,203 @@
+import { BN, Program } from "@coral-xyz/anchor";
+import type { Eki } from "../target/types/eki";
+import { getAssociatedTokenAddressSync } from "@solana/spl-token";
+import { Keypair, PublicKey } from "@solana/web3.js";
+import { makeKeypairs } from "@solana-developers/helpers";
+import { ProgramTestContext } from "solana-bankrun";
+import { fundedMints, setupMarket, tokenBalance } from "./utils";
+
+const EXITS_LENGTH = 1000;
+const PRICES_LENGTH = 16;
+const DECIMALS = 6;
+const MINIMUM_LIQUIDITY = 1_000;
+
+describe("eki with a liquidity pool", () => {
+  let program: Program<Eki>;
+  let context: ProgramTestContext;
+  let accounts: Record<string, PublicKey>;
+  let lpMint: PublicKey;
+
+  const [depositorA, lpProvider] = makeKeypairs(2);
+  const mintA = Keypair.generate();
+  const mintB = Keypair.generate();
+
+  const depositAmount = 1_000 * 10 ** DECIMALS;
+  const liquidity = 10_000 * 10 ** DECIMALS;
+  const startSlot = 100;
+  const endSlotInterval = 10;
+  const endSlot = startSlot + endSlotInterval * 100;
+
+  const ata = (mint: PublicKey, owner: PublicKey) =>
+    getAssociatedTokenAddressSync(mint, owner);
+
+  const fetchBookkeeping = () =>
+    program.account.bookkeeping.fetch(
+      PublicKey.findProgramAddressSync(
+        [Buffer.from("bookkeeping"), accounts.market.toBuffer()],
+        program.programId
+      )[0]
+    );
+
+  beforeAll(async () => {
+    ({ context, program, accounts } = await setupMarket({
+      interval: endSlotInterval,
+      startSlot,
+      exitsLength: EXITS_LENGTH,
+      pricesLength: PRICES_LENGTH,
+      users: [depositorA, lpProvider],
+      mints: fundedMints(
+        mintA,
+        mintB,
+        DECIMALS,
+        [depositorA.publicKey, lpProvider.publicKey],
+        liquidity
+      ),
+    }));
+
+    lpMint = PublicKey.findProgramAddressSync(
+      [Buffer.from("lp_mint"), accounts.market.toBuffer()],
+      program.programId
+    )[0];
+  });
+
+  it("creates an empty pool with its LP mint", async () => {
+    await program.methods.initializePool().accounts({ ...accounts }).rpc();
+
+    const bookkeepingAccount = await fetchBookkeeping();
+    expect(bookkeepingAccount.pool.reserveA.toNumber()).toStrictEqual(0);
+    expect(bookkeepingAccount.pool.reserveB.toNumber()).toStrictEqual(0);
+    expect(bookkeepingAccount.pool.shares.toNumber()).toStrictEqual(0);
+  });
+
+  it("mints shares for added liquidity", async () => {
+    await program.methods
+      .addLiquidity(
+        new BN(liquidity),
+        new BN(liquidity),
+        new BN(liquidity - MINIMUM_LIQUIDITY)
+      )
+      .accounts({ ...accounts, provider: lpProvider.publicKey })
+      .signers([lpProvider])
+      .rpc();
+
+    const bookkeepingAccount = await fetchBookkeeping();
+    expect(bookkeepingAccount.pool.reserveA.toNumber()).toStrictEqual(
+      liquidity
+    );
+    expect(bookkeepingAccount.pool.reserveB.toNumber()).toStrictEqual(
+      liquidity
+    );
+    expect(bookkeepingAccount.pool.shares.toNumber()).toStrictEqual(liquidity);
+    // the first deposit locks the minimum liquidity in the pool
+    expect(
+      await tokenBalance(context, ata(lpMint, lpProvider.publicKey))
+    ).toStrictEqual(liquidity - MINIMUM_LIQUIDITY);
+  });
+
+  it("fills a one-sided stream against the pool", async () => {
+    await program.methods
+      .depositTokenA(new BN(depositAmount), new BN(endSlot))
+      .accounts({
+        ...accounts,
+        depositor: depositorA.publicKey,
+        depositorTokenAccount: ata(mintA.publicKey, depositorA.publicKey),
+      })
+      .signers([depositorA])
+      .rpc();
+
+    context.warpToSlot(BigInt(startSlot + (endSlot - startSlot) / 2));
+    await program.methods.updateBookkeeping().accounts({ ...accounts }).rpc();
+
+    const bookkeepingAccount = await fetchBookkeeping();
+    expect(bookkeepingAccount.bPerA.isZero()).toBe(false);
+    expect(bookkeepingAccount.pool.reserveA.toNumber()).toBeGreaterThan(
+      liquidity
+    );
+    expect(bookkeepingAccount.pool.reserveB.toNumber()).toBeLessThan(
+      liquidity
+    );
+
+    await program.methods
+      .withdrawSwappedTokenB(false)
+      .accounts({
+        ...accounts,
+        withdrawer: depositorA.publicKey,
+        priceArchive: null,
+      })
+      .signers([depositorA])
+      .rpc();
+
+    expect(
+      await tokenBalance(context, ata(mintB.publicKey, depositorA.publicKey))
+    ).toBeGreaterThan(0);
+  });
+
+  it("swaps instantly against the pool within the slippage limit", async () => {
+    const swapAmount = 100 * 10 ** DECIMALS;
+    const swap = (minAmountOut: number) =>
+      program.methods
+        .swapExactIn(new BN(swapAmount), new BN(minAmountOut), true)
+        .accounts({ ...accounts, trader: depositorA.publicKey })
+        .signers([depositorA])
+        .rpc();
+
+    await expect(swap(swapAmount * 2)).rejects.toThrow();
+
+    const before = await fetchBookkeeping();
+    const balanceB = await tokenBalance(
+      context,
+      ata(mintB.publicKey, depositorA.publicKey)
+    );
+
+    await swap(1);
+
+    const after = await fetchBookkeeping();
+    expect(
+      await tokenBalance(context, ata(mintB.publicKey, depositorA.publicKey))
+    ).toBeGreaterThan(balanceB);
+    expect(after.pool.reserveA.toNumber()).toBeGreaterThan(
+      before.pool.reserveA.toNumber()
+    );
+    expect(after.pool.reserveB.toNumber()).toBeLessThan(
+      before.pool.reserveB.toNumber()
+    );
+  });
+
+  it("rejects removals below the minimum amounts", async () => {
+    await expect(
+      program.methods
+        .removeLiquidity(
+          new BN(liquidity - MINIMUM_LIQUIDITY),
+          new BN(liquidity * 2),
+          new BN(0)
+        )
+        .accounts({ ...accounts, provider: lpProvider.publicKey })
+        .signers([lpProvider])
+        .rpc()
+    ).rejects.toThrow();
+  });
+
+  it("burns shares for their part of the reserves", async () => {
+    await program.methods
+      .removeLiquidity(
+        new BN(liquidity - MINIMUM_LIQUIDITY),
+        new BN(0),
+        new BN(0)
+      )
+      .accounts({ ...accounts, provider: lpProvider.publicKey })
+      .signers([lpProvider])
+      .rpc();
+
+    const bookkeepingAccount = await fetchBookkeeping();
+    expect(bookkeepingAccount.pool.shares.toNumber()).toStrictEqual(
+      MINIMUM_LIQUIDITY
+    );
+    expect(
+      await tokenBalance(context, ata(lpMint, lpProvider.publicKey))
+    ).toStrictEqual(0);
+    // the provider took the streamed token A the pool absorbed
+    expect(
+      await tokenBalance(context, ata(mintA.publicKey, lpProvider.publicKey))
+    ).toBeGreaterThan(liquidity - MINIMUM_LIQUIDITY);
+  });
+});
@@ -0,0 +1,174 @@
+import { BN, Program } from "@coral-xyz/anchor";
+import type { Eki } from "../target/types/eki";
+import { getAssociatedTokenAddressSync } from "@solana/spl-token";
+import { Keypair, PublicKey } from "@solana/web3.js";
+import { makeKeypairs } from "@solana-developers/helpers";
+import { BankrunProvider } from "anchor-bankrun";
+import { ProgramTestContext } from "solana-bankrun";
+import { fetchPriceCheckpoint, fundedMints, setupMarket } from "./utils";
+
+const EXITS_LENGTH = 1000;
+const PRICES_LENGTH = 4;
+const DECIMALS = 6;
+
+describe("eki price archive", () => {
+  let program: Program<Eki>;
+  let context: ProgramTestContext;
+  let provider: BankrunProvider;
+  let accounts: Record<string, PublicKey>;
+
+  const [depositorA, depositorB] = makeKeypairs(2);
+  const mintA = Keypair.generate();
+  const mintB = Keypair.generate();
+
+  const depositAmount = 1_000 * 10 ** DECIMALS;
+  const startSlot = 100;
+  const endSlotInterval = 10;
+  const endSlot = startSlot + endSlotInterval * 10;
+  const endBucket = (endSlot - startSlot) / endSlotInterval;
+
+  const position = (seed: string, depositor: Keypair) =>
+    PublicKey.findProgramAddressSync(
+      [
+        Buffer.from(seed),
+        accounts.market.toBuffer(),
+        depositor.publicKey.toBuffer(),
+      ],
+      program.programId
+    )[0];
+
+  const priceArchive = (epoch: number, page: number) =>
+    PublicKey.findProgramAddressSync(
+      [
+        Buffer.from("price_archive"),
+        accounts.market.toBuffer(),
+        new BN(epoch).toArrayLike(Buffer, "le", 8),
+        new BN(page).toArrayLike(Buffer, "le", 8),
+      ],
+      program.programId
+    )[0];
+
+  const archivePrices = (epoch: number, page: number) =>
+    program.methods
+      .archivePrices(new BN(epoch), new BN(page))
+      .accounts({
+        ...accounts,
+        payer: provider.wallet.publicKey,
+        priceArchive: priceArchive(epoch, page),
+      })
+      .rpc();
+
+  beforeAll(async () => {
+    ({ context, provider, program, accounts } = await setupMarket({
+      interval: endSlotInterval,
+      startSlot,
+      exitsLength: EXITS_LENGTH,
+      pricesLength: PRICES_LENGTH,
+      users: [depositorA, depositorB],
+      mints: fundedMints(
+        mintA,
+        mintB,
+        DECIMALS,
+        [depositorA.publicKey, depositorB.publicKey],
+        depositAmount
+      ),
+    }));
+  });
+
+  it("keeps checkpoints of positions ending a full round ago", async () => {
+    for (const [depositor, mint, method] of [
+      [depositorA, mintA, "depositTokenA"],
+      [depositorB, mintB, "depositTokenB"],
+    ] as const) {
+      await program.methods[method](new BN(depositAmount), new BN(endSlot))
+        .accounts({
+          ...accounts,
+          depositor: depositor.publicKey,
+          depositorTokenAccount: getAssociatedTokenAddressSync(
+            mint.publicKey,
+            depositor.publicKey
+          ),
+        })
+        .signers([depositor])
+        .rpc();
+    }
+
+    // the crank moves past the end bucket, but no bucket of the next round yet
+    context.warpToSlot(BigInt(endSlot + endSlotInterval));
+    await program.methods.updateBookkeeping().accounts({ ...accounts }).rpc();
+    await expect(archivePrices(1, 0)).rejects.toThrow();
+
+    // more than one round of the ring buffer later
+    context.warpToSlot(
+      BigInt(startSlot + (EXITS_LENGTH + 1) * endSlotInterval)
+    );
+    await program.methods.updateBookkeeping().accounts({ ...accounts }).rpc();
+
+    const checkpoint = await fetchPriceCheckpoint(
+      context,
+      accounts.prices,
+      endBucket % PRICES_LENGTH
+    );
+    expect(checkpoint.bucket.toNumber()).toStrictEqual(endBucket);
+  });
+
+  it("moves checkpoints of a passed epoch into an archive page", async () => {
+    await expect(archivePrices(2, 0)).rejects.toThrow();
+
+    await archivePrices(0, 0);
+
+    const archive = await program.account.priceArchive.fetch(
+      priceArchive(0, 0)
+    );
+    expect(archive.market).toStrictEqual(accounts.market);
+    expect(archive.epoch.toNumber()).toStrictEqual(0);
+    expect(archive.len.toNumber()).toStrictEqual(1);
+    expect(archive.checkpoints[0].bucket.toNumber()).toStrictEqual(endBucket);
+
+    const checkpoint = await fetchPriceCheckpoint(
+      context,
+      accounts.prices,
+      endBucket % PRICES_LENGTH
+    );
+    expect(checkpoint.bucket.toNumber()).toStrictEqual(0);
+  });
+
+  it("closes positions against the archived checkpoint", async () => {
+    const closeAccounts = {
+      ...accounts,
+      signer: depositorA.publicKey,
+      positionA: position("position_a", depositorA),
+    };
+
+    // without the archive page the checkpoint can't be found
+    await expect(
+      program.methods
+        .closePositionA(false)
+        .accounts({ ...closeAccounts, priceArchive: null })
+        .signers([depositorA])
+        .rpc()
+    ).rejects.toThrow();
+
+    await program.methods
+      .closePositionA(false)
+      .accounts({ ...closeAccounts, priceArchive: priceArchive(0, 0) })
+      .signers([depositorA])
+      .rpc();
+
+    await program.methods
+      .closePositionB(false)
+      .accounts({
+        ...accounts,
+        signer: depositorB.publicKey,
+        positionB: position("position_b", depositorB),
+        priceArchive: priceArchive(0, 0),
+      })
+      .signers([depositorB])
+      .rpc();
+
+    const archive = await program.account.priceArchive.fetch(
+      priceArchive(0, 0)
+    );
+    expect(archive.len.toNumber()).toStrictEqual(0);
+  });
+});
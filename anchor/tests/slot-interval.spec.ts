@@ -0,0 +1,231 @@
+import { BN, Program } from "@coral-xyz/anchor";
+import type { Eki } from "../target/types/eki";
+import { getAssociatedTokenAddressSync } from "@solana/spl-token";
+import { Keypair, PublicKey, Transaction } from "@solana/web3.js";
+import { makeKeypairs } from "@solana-developers/helpers";
+import { BankrunProvider } from "anchor-bankrun";
+import { ProgramTestContext } from "solana-bankrun";
+import {
+  fetchExitsBucket,
+  fetchPriceCheckpoint,
+  fundedMints,
+  processTransaction,
+  setupMarket,
+} from "./utils";
+
+const EXITS_LENGTH = 2048;
+const PRICES_LENGTH = 64;
+const DECIMALS = 6;
+
+describe("eki with an arbitrary end slot interval", () => {
+  let program: Program<Eki>;
+  let context: ProgramTestContext;
+  let provider: BankrunProvider;
+  let accounts: Record<string, PublicKey>;
+
+  const [depositorA, depositorB] = makeKeypairs(2);
+  const mintA = Keypair.generate();
+  const mintB = Keypair.generate();
+
+  const depositAmount = 1_000 * 10 ** DECIMALS;
+  const startSlot = 100;
+  const endSlotInterval = 7;
+  const exitsStartSlot =
+    Math.floor(startSlot / endSlotInterval) * endSlotInterval;
+  const requestedEndSlot = startSlot + endSlotInterval * 10 + 3;
+  const roundedEndSlot =
+    Math.round(requestedEndSlot / endSlotInterval) * endSlotInterval;
+  const endBucket = (roundedEndSlot - exitsStartSlot) / endSlotInterval;
+
+  const initializeMarket = async (interval: number) =>
+    processTransaction(
+      context,
+      new Transaction().add(
+        await program.methods
+          .initializeMarket(
+            new BN(startSlot),
+            new BN(interval),
+            { slot: {} },
+            new BN(EXITS_LENGTH),
+            new BN(PRICES_LENGTH)
+          )
+          .accounts({ ...accounts })
+          .instruction()
+      ),
+      [provider.wallet.payer]
+    );
+
+  // the market is initialized by the tests, which try a zero interval first
+  beforeAll(async () => {
+    ({ context, provider, program, accounts } = await setupMarket({
+      exitsLength: EXITS_LENGTH,
+      pricesLength: PRICES_LENGTH,
+      users: [depositorA, depositorB],
+      mints: fundedMints(
+        mintA,
+        mintB,
+        DECIMALS,
+        [depositorA.publicKey, depositorB.publicKey],
+        depositAmount
+      ),
+    }));
+  });
+
+  it("rejects a zero end slot interval", async () => {
+    await expect(initializeMarket(0)).rejects.toThrow();
+  });
+
+  it("accepts an interval that is not a power of ten", async () => {
+    await initializeMarket(endSlotInterval);
+
+    const marketAccount = await program.account.market.fetch(accounts.market);
+    expect(marketAccount.endSlotInterval.toNumber()).toStrictEqual(
+      endSlotInterval
+    );
+
+    const exitsAccount = await program.account.exits.fetch(accounts.exits);
+    expect(exitsAccount.startSlot.toNumber()).toStrictEqual(exitsStartSlot);
+    expect(exitsAccount.length.toNumber()).toStrictEqual(EXITS_LENGTH);
+
+    const pricesAccount = await program.account.prices.fetch(accounts.prices);
+    expect(pricesAccount.length.toNumber()).toStrictEqual(PRICES_LENGTH);
+  });
+
+  it("reports the latest end slot covered by the exits ring buffer", async () => {
+    const maxEndSlot = await program.methods
+      .maxEndSlot()
+      .accounts({ market: accounts.market, exits: accounts.exits })
+      .view();
+
+    expect(maxEndSlot.toNumber()).toStrictEqual(
+      exitsStartSlot + (EXITS_LENGTH - 1) * endSlotInterval
+    );
+  });
+
+  it("rejects end slots beyond the exits ring buffer horizon", async () => {
+    const beyondHorizon = exitsStartSlot + EXITS_LENGTH * endSlotInterval;
+
+    await expect(
+      program.methods
+        .depositTokenA(new BN(depositAmount), new BN(beyondHorizon))
+        .accounts({
+          ...accounts,
+          depositor: depositorA.publicKey,
+          depositorTokenAccount: getAssociatedTokenAddressSync(
+            mintA.publicKey,
+            depositorA.publicKey
+          ),
+        })
+        .signers([depositorA])
+        .rpc()
+    ).rejects.toThrow();
+  });
+
+  it("rounds end slots to the nearest interval boundary", async () => {
+    for (const [depositor, mint, method] of [
+      [depositorA, mintA, "depositTokenA"],
+      [depositorB, mintB, "depositTokenB"],
+    ] as const) {
+      await program.methods[method](
+        new BN(depositAmount),
+        new BN(requestedEndSlot)
+      )
+        .accounts({
+          ...accounts,
+          depositor: depositor.publicKey,
+          depositorTokenAccount: getAssociatedTokenAddressSync(
+            mint.publicKey,
+            depositor.publicKey
+          ),
+        })
+        .signers([depositor])
+        .rpc();
+    }
+
+    const [positionA] = PublicKey.findProgramAddressSync(
+      [
+        Buffer.from("position_a"),
+        accounts.market.toBuffer(),
+        depositorA.publicKey.toBuffer(),
+      ],
+      program.programId
+    );
+    const positionAccount = await program.account.positionA.fetch(positionA);
+    expect(positionAccount.endSlot.toNumber()).toStrictEqual(roundedEndSlot);
+    expect(roundedEndSlot % endSlotInterval).toStrictEqual(0);
+
+    // the bucket is marked in the sparse exits index
+    const exitsBucket = await fetchExitsBucket(
+      context,
+      accounts.exits,
+      endBucket
+    );
+    expect(exitsBucket.marked).toBe(true);
+  });
+
+  it("removes exiting volume once its bucket has passed", async () => {
+    const currentSlot = startSlot + endSlotInterval * 12;
+    context.warpToSlot(BigInt(currentSlot));
+
+    await program.methods.updateBookkeeping().accounts({ ...accounts }).rpc();
+
+    const exitsAccount = await program.account.exits.fetch(accounts.exits);
+    expect(exitsAccount.pointer.toNumber()).toStrictEqual(
+      Math.floor((currentSlot - exitsStartSlot) / endSlotInterval)
+    );
+
+    const marketAccount = await program.account.market.fetch(accounts.market);
+    expect(marketAccount.tokenAVolume.toNumber()).toStrictEqual(0);
+    expect(marketAccount.tokenBVolume.toNumber()).toStrictEqual(0);
+
+    // the passed bucket is cleared and its exits move into a price checkpoint
+    const exitsBucket = await fetchExitsBucket(
+      context,
+      accounts.exits,
+      endBucket
+    );
+    expect(exitsBucket.tokenA.toNumber()).toStrictEqual(0);
+    expect(exitsBucket.tokenB.toNumber()).toStrictEqual(0);
+    expect(exitsBucket.marked).toBe(false);
+
+    const checkpoint = await fetchPriceCheckpoint(
+      context,
+      accounts.prices,
+      endBucket % PRICES_LENGTH
+    );
+    expect(checkpoint.bucket.toNumber()).toStrictEqual(endBucket);
+    expect(checkpoint.outstandingA.toNumber()).toBeGreaterThan(0);
+    expect(checkpoint.outstandingB.toNumber()).toBeGreaterThan(0);
+  });
+
+  it("frees the price checkpoint once every position has closed", async () => {
+    for (const [depositor, method, position, seed] of [
+      [depositorA, "closePositionA", "positionA", "position_a"],
+      [depositorB, "closePositionB", "positionB", "position_b"],
+    ] as const) {
+      await program.methods[method](false)
+        .accounts({
+          ...accounts,
+          signer: depositor.publicKey,
+          priceArchive: null,
+          [position]: PublicKey.findProgramAddressSync(
+            [
+              Buffer.from(seed),
+              accounts.market.toBuffer(),
+              depositor.publicKey.toBuffer(),
+            ],
+            program.programId
+          )[0],
+        })
+        .signers([depositor])
+        .rpc();
+    }
+
+    const checkpoint = await fetchPriceCheckpoint(
+      context,
+      accounts.prices,
+      endBucket % PRICES_LENGTH
+    );
+    expect(checkpoint.bucket.toNumber()).toStrictEqual(0);
+  });
+});
@@ -0,0 +1,131 @@
+import { BN, Program } from "@coral-xyz/anchor";
+import type { Eki } from "../target/types/eki";
+import { getAssociatedTokenAddressSync } from "@solana/spl-token";
+import { Keypair, PublicKey } from "@solana/web3.js";
+import { makeKeypairs } from "@solana-developers/helpers";
+import { ProgramTestContext } from "solana-bankrun";
+import { fundedMints, setUnixTimestamp, setupMarket } from "./utils";
+
+const EXITS_LENGTH = 4096;
+const PRICES_LENGTH = 64;
+const MINIMUM_TRADE_DURATION_SECONDS = 10; // must be the same as in the program
+const DECIMALS = 6;
+
+describe("eki in timestamp mode", () => {
+  let program: Program<Eki>;
+  let context: ProgramTestContext;
+  let accounts: Record<string, PublicKey>;
+
+  const [depositorA, depositorB] = makeKeypairs(2);
+  const mintA = Keypair.generate();
+  const mintB = Keypair.generate();
+
+  const depositAmount = 1_000 * 10 ** DECIMALS;
+  const startTimestamp = 1_700_000_000;
+  const endTimestampInterval = 1;
+
+  beforeAll(async () => {
+    ({ context, program, accounts } = await setupMarket({
+      interval: endTimestampInterval,
+      timeMode: { timestamp: {} },
+      startSlot: 0,
+      unixTimestamp: startTimestamp,
+      exitsLength: EXITS_LENGTH,
+      pricesLength: PRICES_LENGTH,
+      users: [depositorA, depositorB],
+      mints: fundedMints(
+        mintA,
+        mintB,
+        DECIMALS,
+        [depositorA.publicKey, depositorB.publicKey],
+        depositAmount
+      ),
+    }));
+  });
+
+  it("starts the market at the current unix timestamp", async () => {
+    const marketAccount = await program.account.market.fetch(accounts.market);
+    expect(marketAccount.timeMode).toStrictEqual({ timestamp: {} });
+    expect(marketAccount.startSlot.toNumber()).toStrictEqual(startTimestamp);
+  });
+
+  it("rejects positions shorter than the minimum trade duration", async () => {
+    await expect(
+      program.methods
+        .depositTokenA(
+          new BN(depositAmount),
+          new BN(startTimestamp + MINIMUM_TRADE_DURATION_SECONDS - 1)
+        )
+        .accounts({
+          ...accounts,
+          depositor: depositorA.publicKey,
+          depositorTokenAccount: getAssociatedTokenAddressSync(
+            mintA.publicKey,
+            depositorA.publicKey
+          ),
+        })
+        .signers([depositorA])
+        .rpc()
+    ).rejects.toThrow();
+  });
+
+  it("opens positions ending at a unix timestamp", async () => {
+    const endTimestamp = startTimestamp + 100;
+
+    await program.methods
+      .depositTokenA(new BN(depositAmount), new BN(endTimestamp))
+      .accounts({
+        ...accounts,
+        depositor: depositorA.publicKey,
+        depositorTokenAccount: getAssociatedTokenAddressSync(
+          mintA.publicKey,
+          depositorA.publicKey
+        ),
+      })
+      .signers([depositorA])
+      .rpc();
+
+    await program.methods
+      .depositTokenB(new BN(depositAmount), new BN(endTimestamp))
+      .accounts({
+        ...accounts,
+        depositor: depositorB.publicKey,
+        depositorTokenAccount: getAssociatedTokenAddressSync(
+          mintB.publicKey,
+          depositorB.publicKey
+        ),
+      })
+      .signers([depositorB])
+      .rpc();
+
+    const [positionA] = PublicKey.findProgramAddressSync(
+      [
+        Buffer.from("position_a"),
+        accounts.market.toBuffer(),
+        depositorA.publicKey.toBuffer(),
+      ],
+      program.programId
+    );
+    const positionAccount = await program.account.positionA.fetch(positionA);
+    expect(positionAccount.startSlot.toNumber()).toStrictEqual(startTimestamp);
+    expect(positionAccount.endSlot.toNumber()).toStrictEqual(endTimestamp);
+  });
+
+  it("trades according to elapsed seconds", async () => {
+    await setUnixTimestamp(context, startTimestamp + 50);
+
+    await program.methods.updateBookkeeping().accounts({ ...accounts }).rpc();
+
+    const [bookkeeping] = PublicKey.findProgramAddressSync(
+      [Buffer.from("bookkeeping"), accounts.market.toBuffer()],
+      program.programId
+    );
+    const bookkeepingAccount =
+      await program.account.bookkeeping.fetch(bookkeeping);
+    expect(bookkeepingAccount.lastSlot.toNumber()).toStrictEqual(
+      startTimestamp + 50
+    );
+    expect(bookkeepingAccount.aPerB.isZero()).toBe(false);
+    expect(bookkeepingAccount.bPerA.isZero()).toBe(false);
+  });
+});
@@ -0,0 +1,275 @@
+import { BN, Program } from "@coral-xyz/anchor";
+import type { Eki } from "../target/types/eki";
+import type { NoopTransferHook } from "../target/types/noop_transfer_hook";
+import {
+  createAssociatedTokenAccountInstruction,
+  createInitializeMintInstruction,
+  createInitializeTransferHookInstruction,
+  createMintToInstruction,
+  ExtensionType,
+  getAssociatedTokenAddressSync,
+  getExtraAccountMetaAddress,
+  getMintLen,
+  TOKEN_2022_PROGRAM_ID,
+} from "@solana/spl-token";
+import {
+  AccountMeta,
+  Keypair,
+  PublicKey,
+  SystemProgram,
+  Transaction,
+} from "@solana/web3.js";
+import { makeKeypairs } from "@solana-developers/helpers";
+import { BankrunProvider } from "anchor-bankrun";
+import HOOK_IDL from "../target/idl/noop_transfer_hook.json";
+import { BanksClient, ProgramTestContext } from "solana-bankrun";
+import { processTransaction, setupMarket, tokenBalance } from "./utils";
+
+const EXITS_LENGTH = 1024;
+const PRICES_LENGTH = 64;
+const DECIMALS = 6;
+
+describe("eki with transfer hook mints", () => {
+  let program: Program<Eki>;
+  let context: ProgramTestContext;
+  let banksClient: BanksClient;
+  let accounts: Record<string, PublicKey>;
+
+  const [depositorA, depositorB] = makeKeypairs(2);
+  const mintA = Keypair.generate();
+  const mintB = Keypair.generate();
+
+  const hookProgramId = new PublicKey(HOOK_IDL.address);
+  const hookAccounts = (mints: PublicKey[]): AccountMeta[] => [
+    ...mints.map((mint) => ({
+      pubkey: getExtraAccountMetaAddress(mint, hookProgramId),
+      isSigner: false,
+      isWritable: false,
+    })),
+    { pubkey: hookProgramId, isSigner: false, isWritable: false },
+  ];
+
+  const depositAmount = 1_000 * 10 ** DECIMALS;
+  const startSlot = 100;
+  const endSlotInterval = 100;
+
+  const ata = (mint: PublicKey, owner: PublicKey) =>
+    getAssociatedTokenAddressSync(mint, owner, false, TOKEN_2022_PROGRAM_ID);
+
+  // Both mints call the no-op hook program on every transfer, and both
+  // depositors hold both of them
+  const createHookedMints = async (
+    context: ProgramTestContext,
+    provider: BankrunProvider
+  ) => {
+    const hookProgram = new Program<NoopTransferHook>(
+      HOOK_IDL as NoopTransferHook,
+      provider
+    );
+    const payer = provider.wallet.payer;
+    const mintLen = getMintLen([ExtensionType.TransferHook]);
+    const mintLamports = (await context.banksClient.getRent()).minimumBalance(
+      BigInt(mintLen)
+    );
+
+    for (const mint of [mintA, mintB]) {
+      const tx = new Transaction().add(
+        SystemProgram.createAccount({
+          fromPubkey: payer.publicKey,
+          newAccountPubkey: mint.publicKey,
+          space: mintLen,
+          lamports: Number(mintLamports),
+          programId: TOKEN_2022_PROGRAM_ID,
+        }),
+        createInitializeTransferHookInstruction(
+          mint.publicKey,
+          payer.publicKey,
+          hookProgramId,
+          TOKEN_2022_PROGRAM_ID
+        ),
+        createInitializeMintInstruction(
+          mint.publicKey,
+          DECIMALS,
+          payer.publicKey,
+          null,
+          TOKEN_2022_PROGRAM_ID
+        ),
+        await hookProgram.methods
+          .initializeExtraAccountMetaList()
+          .accounts({ payer: payer.publicKey, mint: mint.publicKey })
+          .instruction()
+      );
+      await processTransaction(context, tx, [payer, mint]);
+    }
+
+    const fundTx = new Transaction();
+    for (const user of [depositorA, depositorB]) {
+      for (const mint of [mintA, mintB]) {
+        fundTx.add(
+          createAssociatedTokenAccountInstruction(
+            payer.publicKey,
+            ata(mint.publicKey, user.publicKey),
+            user.publicKey,
+            mint.publicKey,
+            TOKEN_2022_PROGRAM_ID
+          )
+        );
+        fundTx.add(
+          createMintToInstruction(
+            mint.publicKey,
+            ata(mint.publicKey, user.publicKey),
+            payer.publicKey,
+            depositAmount,
+            [],
+            TOKEN_2022_PROGRAM_ID
+          )
+        );
+      }
+    }
+    await processTransaction(context, fundTx, [payer]);
+
+    return {
+      tokenMintA: mintA.publicKey,
+      tokenMintB: mintB.publicKey,
+      tokenProgramA: TOKEN_2022_PROGRAM_ID,
+      tokenProgramB: TOKEN_2022_PROGRAM_ID,
+    };
+  };
+
+  beforeAll(async () => {
+    ({ context, program, accounts } = await setupMarket({
+      interval: endSlotInterval,
+      startSlot,
+      exitsLength: EXITS_LENGTH,
+      pricesLength: PRICES_LENGTH,
+      users: [depositorA, depositorB],
+      mints: createHookedMints,
+    }));
+    banksClient = context.banksClient;
+
+    for (const [name, seed] of [
+      ["treasuryA", "treasury_a"],
+      ["treasuryB", "treasury_b"],
+      ["bookkeeping", "bookkeeping"],
+    ]) {
+      accounts[name] = PublicKey.findProgramAddressSync(
+        [Buffer.from(seed), accounts.market.toBuffer()],
+        program.programId
+      )[0];
+    }
+  });
+
+  it("initializes a market for two hooked mints", async () => {
+    const marketAccount = await program.account.market.fetch(accounts.market);
+    expect(marketAccount.treasuryA.toBase58()).toBe(
+      accounts.treasuryA.toBase58()
+    );
+    expect(marketAccount.treasuryB.toBase58()).toBe(
+      accounts.treasuryB.toBase58()
+    );
+  });
+
+  it("deposits hooked tokens on both sides", async () => {
+    const endSlot = startSlot + endSlotInterval * 10;
+
+    await program.methods
+      .depositTokenA(new BN(depositAmount), new BN(endSlot))
+      .accounts({
+        ...accounts,
+        depositor: depositorA.publicKey,
+        depositorTokenAccount: ata(mintA.publicKey, depositorA.publicKey),
+      })
+      .remainingAccounts(hookAccounts([mintA.publicKey]))
+      .signers([depositorA])
+      .rpc();
+
+    await program.methods
+      .depositTokenB(new BN(depositAmount), new BN(endSlot))
+      .accounts({
+        ...accounts,
+        depositor: depositorB.publicKey,
+        depositorTokenAccount: ata(mintB.publicKey, depositorB.publicKey),
+      })
+      .remainingAccounts(hookAccounts([mintB.publicKey]))
+      .signers([depositorB])
+      .rpc();
+
+    expect(await tokenBalance(context, accounts.treasuryA)).toBe(depositAmount);
+    expect(await tokenBalance(context, accounts.treasuryB)).toBe(depositAmount);
+  });
+
+  it("fails to deposit without the hook accounts", async () => {
+    await expect(
+      program.methods
+        .depositTokenA(new BN(depositAmount), new BN(startSlot * 20))
+        .accounts({
+          ...accounts,
+          depositor: depositorB.publicKey,
+          depositorTokenAccount: ata(mintA.publicKey, depositorB.publicKey),
+        })
+        .signers([depositorB])
+        .rpc()
+    ).rejects.toThrow();
+
+    expect(await tokenBalance(context, accounts.treasuryA)).toBe(depositAmount);
+  });
+
+  it("withdraws swapped hooked tokens", async () => {
+    context.warpToSlot(BigInt(startSlot + endSlotInterval * 5));
+
+    const [positionB] = PublicKey.findProgramAddressSync(
+      [
+        Buffer.from("position_b"),
+        accounts.market.toBuffer(),
+        depositorB.publicKey.toBuffer(),
+      ],
+      program.programId
+    );
+
+    await program.methods
+      .withdrawSwappedTokenA(false)
+      .accounts({
+        ...accounts,
+        priceArchive: null,
+        withdrawer: depositorB.publicKey,
+        positionB,
+      })
+      .remainingAccounts(hookAccounts([mintA.publicKey]))
+      .signers([depositorB])
+      .rpc();
+
+    expect(
+      await tokenBalance(context, ata(mintA.publicKey, depositorB.publicKey))
+    ).toBeGreaterThan(depositAmount);
+  });
+
+  it("closes a position paying out both hooked mints", async () => {
+    context.warpToSlot(BigInt(startSlot + endSlotInterval * 20));
+
+    const [positionA] = PublicKey.findProgramAddressSync(
+      [
+        Buffer.from("position_a"),
+        accounts.market.toBuffer(),
+        depositorA.publicKey.toBuffer(),
+      ],
+      program.programId
+    );
+
+    await program.methods
+      .closePositionA(false)
+      .accounts({
+        ...accounts,
+        signer: depositorA.publicKey,
+        priceArchive: null,
+        positionA,
+      })
+      .remainingAccounts(hookAccounts([mintA.publicKey, mintB.publicKey]))
+      .signers([depositorA])
+      .rpc();
+
+    expect(
+      await tokenBalance(context, ata(mintB.publicKey, depositorA.publicKey))
+    ).toBeGreaterThan(depositAmount);
+    expect(await banksClient.getAccount(positionA)).toBeNull();
+  });
+});
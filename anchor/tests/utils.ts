@@ -1,8 +1,17 @@
+import * as anchor from "@coral-xyz/anchor";
+import { BN, Program } from "@coral-xyz/anchor";
+import type { Eki } from "../target/types/eki";
 import {
   ACCOUNT_SIZE,
+  AccountLayout,
+  createAssociatedTokenAccountInstruction,
   createCloseAccountInstruction,
   createInitializeAccount3Instruction,
+  createInitializeMintInstruction,
+  createMintToInstruction,
   createTransferInstruction,
+  getAssociatedTokenAddressSync,
+  MINT_SIZE,
   NATIVE_MINT,
   TOKEN_PROGRAM_ID,
 } from "@solana/spl-token";
@@ -17,7 +26,9 @@ import {
   VersionedTransaction,
 } from "@solana/web3.js";
 import { makeKeypairs } from "@solana-developers/helpers";
-import { ProgramTestContext } from "solana-bankrun";
+import { BankrunProvider, startAnchor } from "anchor-bankrun";
+import IDL from "../target/idl/eki.json";
+import { Clock, ProgramTestContext } from "solana-bankrun";
 
 export const createTransferWrapSolTx = (
   context: ProgramTestContext,
@@ -91,3 +102,276 @@ export function buildTransaction({
 
   return tx;
 }
+
+export const processTransaction = async (
+  context: ProgramTestContext,
+  tx: Transaction,
+  signers: Keypair[]
+) => {
+  tx.recentBlockhash = context.lastBlockhash;
+  tx.feePayer = signers[0].publicKey;
+  tx.sign(...signers);
+  await context.banksClient.processTransaction(tx);
+};
+
+// Creates a classic SPL mint and funds an associated token account for every owner
+export const createFundedMint = async (
+  context: ProgramTestContext,
+  payer: Keypair,
+  mint: Keypair,
+  decimals: number,
+  owners: PublicKey[],
+  amount: number
+) => {
+  const rent = await context.banksClient.getRent();
+  const tx = new Transaction().add(
+    SystemProgram.createAccount({
+      fromPubkey: payer.publicKey,
+      newAccountPubkey: mint.publicKey,
+      space: MINT_SIZE,
+      lamports: Number(rent.minimumBalance(BigInt(MINT_SIZE))),
+      programId: TOKEN_PROGRAM_ID,
+    }),
+    createInitializeMintInstruction(
+      mint.publicKey,
+      decimals,
+      payer.publicKey,
+      null,
+      TOKEN_PROGRAM_ID
+    )
+  );
+
+  owners.forEach((owner) => {
+    const ata = getAssociatedTokenAddressSync(mint.publicKey, owner);
+    tx.add(
+      createAssociatedTokenAccountInstruction(
+        payer.publicKey,
+        ata,
+        owner,
+        mint.publicKey
+      ),
+      createMintToInstruction(mint.publicKey, ata, payer.publicKey, amount)
+    );
+  });
+
+  await processTransaction(context, tx, [payer, mint]);
+};
+
+export const tokenBalance = async (
+  context: ProgramTestContext,
+  address: PublicKey
+) => {
+  const account = await context.banksClient.getAccount(address);
+  return Number(AccountLayout.decode(account!.data).amount);
+};
+
+// Moves the clock to the next slot at the given unix timestamp
+export const setUnixTimestamp = async (
+  context: ProgramTestContext,
+  unixTimestamp: number
+) => {
+  const clock = await context.banksClient.getClock();
+  context.setClock(
+    new Clock(
+      clock.slot + 1n,
+      clock.epochStartTimestamp,
+      clock.epoch,
+      clock.leaderScheduleEpoch,
+      BigInt(unixTimestamp)
+    )
+  );
+};
+
+export type MarketMints = {
+  tokenMintA: PublicKey;
+  tokenMintB: PublicKey;
+  tokenProgramA: PublicKey;
+  tokenProgramB: PublicKey;
+};
+
+// Two classic SPL mints, funding every owner with amount of each
+export const fundedMints =
+  (
+    mintA: Keypair,
+    mintB: Keypair,
+    decimals: number,
+    owners: PublicKey[],
+    amount: number
+  ) =>
+  async (
+    context: ProgramTestContext,
+    provider: BankrunProvider
+  ): Promise<MarketMints> => {
+    for (const mint of [mintA, mintB]) {
+      await createFundedMint(
+        context,
+        provider.wallet.payer,
+        mint,
+        decimals,
+        owners,
+        amount
+      );
+    }
+
+    return {
+      tokenMintA: mintA.publicKey,
+      tokenMintB: mintB.publicKey,
+      tokenProgramA: TOKEN_PROGRAM_ID,
+      tokenProgramB: TOKEN_PROGRAM_ID,
+    };
+  };
+
+// Starts bankrun with SOL for every user, creates the mints and the exits and prices
+// accounts, and initializes the market. Without an interval the market is left for the
+// test to initialize. The returned accounts hold the mints, token programs, market,
+// exits and prices.
+export const setupMarket = async ({
+  interval,
+  timeMode = { slot: {} },
+  startSlot = 100,
+  unixTimestamp,
+  exitsLength,
+  pricesLength,
+  users,
+  mints,
+}: {
+  interval?: number;
+  timeMode?: { slot: {} } | { timestamp: {} };
+  startSlot?: number;
+  // clock at which the market is initialized, for markets in timestamp mode
+  unixTimestamp?: number;
+  exitsLength: number;
+  pricesLength: number;
+  users: Keypair[];
+  mints: (
+    context: ProgramTestContext,
+    provider: BankrunProvider
+  ) => Promise<MarketMints>;
+}) => {
+  const context = await startAnchor(
+    "",
+    [],
+    users.map((user) => ({
+      address: user.publicKey,
+      info: {
+        lamports: 1000 * LAMPORTS_PER_SOL,
+        data: Buffer.alloc(0),
+        owner: SystemProgram.programId,
+        executable: false,
+      },
+    }))
+  );
+
+  const provider = new BankrunProvider(context);
+  anchor.setProvider(provider);
+  const program = new Program<Eki>(IDL as Eki, provider);
+  const payer = provider.wallet.payer;
+
+  const accounts: Record<string, PublicKey> = {
+    ...(await mints(context, provider)),
+  };
+
+  if (unixTimestamp !== undefined) {
+    await setUnixTimestamp(context, unixTimestamp);
+  }
+
+  const [exits, prices] = makeKeypairs(2);
+  accounts.market = PublicKey.findProgramAddressSync(
+    [Buffer.from("market")],
+    program.programId
+  )[0];
+  accounts.exits = exits.publicKey;
+  accounts.prices = prices.publicKey;
+
+  const tx = new Transaction().add(
+    SystemProgram.createAccount({
+      fromPubkey: payer.publicKey,
+      newAccountPubkey: exits.publicKey,
+      space: exitsAccountSize(exitsLength),
+      lamports: 100 * LAMPORTS_PER_SOL,
+      programId: program.programId,
+    }),
+    SystemProgram.createAccount({
+      fromPubkey: payer.publicKey,
+      newAccountPubkey: prices.publicKey,
+      space: pricesAccountSize(pricesLength),
+      lamports: 100 * LAMPORTS_PER_SOL,
+      programId: program.programId,
+    }),
+    await program.methods
+      .initializeExits()
+      .accounts({ exits: exits.publicKey, prices: prices.publicKey })
+      .instruction()
+  );
+
+  if (interval !== undefined) {
+    tx.add(
+      await program.methods
+        .initializeMarket(
+          new BN(startSlot),
+          new BN(interval),
+          timeMode,
+          new BN(exitsLength),
+          new BN(pricesLength)
+        )
+        .accounts({ ...accounts })
+        .instruction()
+    );
+  }
+
+  await processTransaction(context, tx, [payer, exits, prices]);
+
+  return { context, provider, program, accounts };
+};
+
+// Sizes of exits and prices accounts for the buffer lengths chosen at market creation,
+// must match Exits::space and Prices::space in the program
+export const exitsAccountSize = (length: number) => {
+  const indexLength = Math.ceil(length / 64);
+  return 8 + 24 + (2 * length + indexLength + Math.ceil(indexLength / 64)) * 8;
+};
+
+export const pricesAccountSize = (length: number) => 8 + 8 + length * 72;
+
+const readU64 = (data: Uint8Array, offset: number) =>
+  new BN(data.subarray(offset, offset + 8), "le");
+
+// u128 fields of zero-copy accounts are stored as two little endian words
+const readU128 = (data: Uint8Array, offset: number) =>
+  new BN(data.subarray(offset, offset + 16), "le");
+
+// Exits buffers are sized at runtime and not part of the IDL, so they are decoded by hand
+export const fetchExitsBucket = async (
+  context: ProgramTestContext,
+  exits: PublicKey,
+  bucket: number
+) => {
+  const { data } = (await context.banksClient.getAccount(exits))!;
+  const length = readU64(data, 24).toNumber();
+  const indexOffset = 32 + (2 * length + Math.floor(bucket / 64)) * 8;
+
+  return {
+    tokenA: readU64(data, 32 + bucket * 8),
+    tokenB: readU64(data, 32 + (length + bucket) * 8),
+    marked: readU64(data, indexOffset).testn(bucket % 64),
+  };
+};
+
+export const fetchPriceCheckpoint = async (
+  context: ProgramTestContext,
+  prices: PublicKey,
+  index: number
+) => {
+  const { data } = (await context.banksClient.getAccount(prices))!;
+  const offset = 16 + index * 72;
+
+  return {
+    bucket: readU64(data, offset),
+    aPerB: readU128(data, offset + 8),
+    bPerA: readU128(data, offset + 24),
+    outstandingA: readU64(data, offset + 40),
+    outstandingB: readU64(data, offset + 48),
+    unfilledA: readU64(data, offset + 56),
+    unfilledB: readU64(data, offset + 64),
+  };
+};
@@ -107,7 +107,7 @@ export function useEkiProgram() {
           // bookkeeping: bookkeeping,
           exits: exitsAddress,
           prices: pricesAddress,
-          tokenProgram: TOKEN_PROGRAM_ID,
+          tokenProgramA: TOKEN_PROGRAM_ID,
         })
         .rpc(),
     onSuccess: (signature) => {
@@ -131,7 +131,7 @@ export function useEkiProgram() {
           // bookkeeping: bookkeeping,
           exits: exitsAddress,
           prices: pricesAddress,
-          tokenProgram: TOKEN_PROGRAM_ID,
+          tokenProgramB: TOKEN_PROGRAM_ID,
         })
         .rpc(),
     onSuccess: (signature) => {